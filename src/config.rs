@@ -0,0 +1,831 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::modifiers::Modifiers;
+use crate::pieces;
+
+// Everything the player can do with the keyboard during the game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    SoftDrop,
+    HardDrop,
+    RotateCw,
+    RotateCcw,
+    Rotate180,
+    Hold,
+    Pause,
+    Quit,
+    Restart,
+    KeyBindings,
+    Hint,
+    Undo,
+    Redo,
+}
+
+impl Action {
+    // The order in which the actions are listed in the config file and on the rebind screen
+    pub const ALL: [Action; 15] = [
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::SoftDrop,
+        Action::HardDrop,
+        Action::RotateCw,
+        Action::RotateCcw,
+        Action::Rotate180,
+        Action::Hold,
+        Action::Pause,
+        Action::Quit,
+        Action::Restart,
+        Action::KeyBindings,
+        Action::Hint,
+        Action::Undo,
+        Action::Redo,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::MoveLeft => "move_left",
+            Action::MoveRight => "move_right",
+            Action::SoftDrop => "soft_drop",
+            Action::HardDrop => "hard_drop",
+            Action::RotateCw => "rotate_cw",
+            Action::RotateCcw => "rotate_ccw",
+            Action::Rotate180 => "rotate_180",
+            Action::Hold => "hold",
+            Action::Pause => "pause",
+            Action::Quit => "quit",
+            Action::Restart => "restart",
+            Action::KeyBindings => "key_bindings",
+            Action::Hint => "hint",
+            Action::Undo => "undo",
+            Action::Redo => "redo",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Action> {
+        Action::ALL.into_iter().find(|action| action.name() == name)
+    }
+}
+
+// A single key together with the modifiers that must be held with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyBinding {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+impl KeyBinding {
+    pub fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        // Terminals report Shift+a either as 'A' or as 'A' with SHIFT,
+        // so the letters are always stored in lowercase with an explicit SHIFT.
+        let mut modifiers =
+            modifiers & (KeyModifiers::SHIFT | KeyModifiers::CONTROL | KeyModifiers::ALT);
+        let code = match code {
+            KeyCode::Char(c) if c.is_uppercase() => {
+                modifiers |= KeyModifiers::SHIFT;
+                KeyCode::Char(c.to_lowercase().next().unwrap_or(c))
+            }
+            KeyCode::BackTab => {
+                modifiers |= KeyModifiers::SHIFT;
+                KeyCode::Tab
+            }
+            code => code,
+        };
+        Self { code, modifiers }
+    }
+
+    pub fn from_event(event: &KeyEvent) -> Self {
+        Self::new(event.code, event.modifiers)
+    }
+
+    // Parses the notation used in the config file, e.g. `Left`, `Space`, `x` or `Ctrl+Shift+z`
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut rest = text;
+        while let Some((modifier, tail)) = rest.split_once('+') {
+            if modifier.is_empty() {
+                // `+` itself is the key, e.g. `Shift++`
+                break;
+            }
+            modifiers |= match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "alt" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => return None,
+            };
+            rest = tail;
+        }
+
+        let code = match rest.to_ascii_lowercase().as_str() {
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "space" => KeyCode::Char(' '),
+            "enter" => KeyCode::Enter,
+            "esc" => KeyCode::Esc,
+            "tab" => KeyCode::Tab,
+            "backspace" => KeyCode::Backspace,
+            "delete" => KeyCode::Delete,
+            "insert" => KeyCode::Insert,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            name if name.len() > 1 && name.starts_with('f') => KeyCode::F(name[1..].parse().ok()?),
+            _ => {
+                let mut chars = rest.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => KeyCode::Char(c),
+                    _ => return None,
+                }
+            }
+        };
+        Some(Self::new(code, modifiers))
+    }
+
+    // The inverse of `parse`
+    pub fn format(&self) -> String {
+        let mut result = String::new();
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            result.push_str("Ctrl+");
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            result.push_str("Alt+");
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            result.push_str("Shift+");
+        }
+        match self.code {
+            KeyCode::Left => result.push_str("Left"),
+            KeyCode::Right => result.push_str("Right"),
+            KeyCode::Up => result.push_str("Up"),
+            KeyCode::Down => result.push_str("Down"),
+            KeyCode::Char(' ') => result.push_str("Space"),
+            KeyCode::Enter => result.push_str("Enter"),
+            KeyCode::Esc => result.push_str("Esc"),
+            KeyCode::Tab => result.push_str("Tab"),
+            KeyCode::Backspace => result.push_str("Backspace"),
+            KeyCode::Delete => result.push_str("Delete"),
+            KeyCode::Insert => result.push_str("Insert"),
+            KeyCode::Home => result.push_str("Home"),
+            KeyCode::End => result.push_str("End"),
+            KeyCode::PageUp => result.push_str("PageUp"),
+            KeyCode::PageDown => result.push_str("PageDown"),
+            KeyCode::F(n) => result.push_str(&format!("F{}", n)),
+            KeyCode::Char(c) => result.push(c),
+            _ => result.push('?'),
+        }
+        result
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    Arrows,
+    Wasd,
+    Vim,
+    // The two halves of the keyboard for the players of the versus mode
+    VersusLeft,
+    VersusRight,
+}
+
+impl Preset {
+    pub const ALL: [Preset; 5] = [
+        Preset::Arrows,
+        Preset::Wasd,
+        Preset::Vim,
+        Preset::VersusLeft,
+        Preset::VersusRight,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Preset::Arrows => "arrows",
+            Preset::Wasd => "wasd",
+            Preset::Vim => "vim",
+            Preset::VersusLeft => "versus_left",
+            Preset::VersusRight => "versus_right",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Preset> {
+        Preset::ALL.into_iter().find(|preset| preset.name() == name)
+    }
+
+    fn keys(&self, action: &Action) -> &'static [&'static str] {
+        // The keys which are shared by all presets
+        match action {
+            Action::Pause => return &["Esc", "p"],
+            Action::Quit => return &["q"],
+            Action::Restart => return &["r"],
+            Action::KeyBindings => return &["F2"],
+            Action::Hint => return &["F3"],
+            Action::Undo => return &["Ctrl+z"],
+            Action::Redo => return &["Ctrl+y"],
+            _ => {}
+        }
+        match (self, action) {
+            (Preset::Arrows, Action::MoveLeft) => &["Left"],
+            (Preset::Arrows, Action::MoveRight) => &["Right"],
+            (Preset::Arrows, Action::SoftDrop) => &["Down"],
+            (Preset::Arrows, Action::HardDrop) => &["Space"],
+            (Preset::Arrows, Action::RotateCw) => &["Up", "x"],
+            (Preset::Arrows, Action::RotateCcw) => &["z"],
+            (Preset::Arrows, Action::Rotate180) => &["a"],
+            (Preset::Arrows, Action::Hold) => &["c"],
+
+            (Preset::Wasd, Action::MoveLeft) => &["a"],
+            (Preset::Wasd, Action::MoveRight) => &["d"],
+            (Preset::Wasd, Action::SoftDrop) => &["s"],
+            (Preset::Wasd, Action::HardDrop) => &["w"],
+            (Preset::Wasd, Action::RotateCw) => &["Right", "k"],
+            (Preset::Wasd, Action::RotateCcw) => &["Left", "j"],
+            (Preset::Wasd, Action::Rotate180) => &["Up", "l"],
+            (Preset::Wasd, Action::Hold) => &["Space"],
+
+            (Preset::Vim, Action::MoveLeft) => &["h"],
+            (Preset::Vim, Action::MoveRight) => &["l"],
+            (Preset::Vim, Action::SoftDrop) => &["j"],
+            (Preset::Vim, Action::HardDrop) => &["k"],
+            (Preset::Vim, Action::RotateCw) => &["f"],
+            (Preset::Vim, Action::RotateCcw) => &["d"],
+            (Preset::Vim, Action::Rotate180) => &["s"],
+            (Preset::Vim, Action::Hold) => &["Space"],
+
+            (Preset::VersusLeft, Action::MoveLeft) => &["a"],
+            (Preset::VersusLeft, Action::MoveRight) => &["d"],
+            (Preset::VersusLeft, Action::SoftDrop) => &["s"],
+            (Preset::VersusLeft, Action::HardDrop) => &["w"],
+            (Preset::VersusLeft, Action::RotateCw) => &["g"],
+            (Preset::VersusLeft, Action::RotateCcw) => &["f"],
+            (Preset::VersusLeft, Action::Rotate180) => &["t"],
+            (Preset::VersusLeft, Action::Hold) => &["c"],
+
+            (Preset::VersusRight, Action::MoveLeft) => &["Left"],
+            (Preset::VersusRight, Action::MoveRight) => &["Right"],
+            (Preset::VersusRight, Action::SoftDrop) => &["Down"],
+            (Preset::VersusRight, Action::HardDrop) => &["Up"],
+            (Preset::VersusRight, Action::RotateCw) => &["."],
+            (Preset::VersusRight, Action::RotateCcw) => &[","],
+            (Preset::VersusRight, Action::Rotate180) => &["/"],
+            (Preset::VersusRight, Action::Hold) => &["m"],
+
+            _ => &[],
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct KeyBindings {
+    pub preset: Preset,
+    bindings: HashMap<Action, Vec<KeyBinding>>,
+}
+
+impl KeyBindings {
+    pub fn from_preset(preset: Preset) -> Self {
+        let mut bindings = HashMap::new();
+        for action in Action::ALL {
+            let keys = preset
+                .keys(&action)
+                .iter()
+                .filter_map(|key| KeyBinding::parse(key))
+                .collect();
+            bindings.insert(action, keys);
+        }
+        Self { preset, bindings }
+    }
+
+    pub fn keys(&self, action: &Action) -> &[KeyBinding] {
+        self.bindings
+            .get(action)
+            .map_or(&[], |keys| keys.as_slice())
+    }
+
+    pub fn action_for(&self, event: &KeyEvent) -> Option<Action> {
+        let pressed = KeyBinding::from_event(event);
+        Action::ALL
+            .into_iter()
+            .find(|action| self.keys(action).contains(&pressed))
+    }
+
+    pub fn set(&mut self, action: Action, keys: Vec<KeyBinding>) {
+        self.bindings.insert(action, keys);
+    }
+
+    // Binds one more key to the action. The key is taken away from any other action,
+    // otherwise the same key press would trigger two actions at once.
+    pub fn add(&mut self, action: Action, key: KeyBinding) {
+        for keys in self.bindings.values_mut() {
+            keys.retain(|bound| *bound != key);
+        }
+        self.bindings.entry(action).or_default().push(key);
+    }
+}
+
+// How the held keys are repeated. The durations are stored in the config file in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Handling {
+    // Delayed auto shift: how long a key is held before the piece starts moving by itself
+    pub das: Duration,
+    // Auto repeat rate: the delay between the automatic moves, zero moves the piece to the wall at once
+    pub arr: Duration,
+    // Soft drop factor: how many times the soft drop is faster than gravity
+    pub sdf: u32,
+    // DAS cut delay: the automatic moves are postponed after a rotation, hold or hard drop
+    pub dcd: Duration,
+}
+
+impl Default for Handling {
+    fn default() -> Self {
+        Self {
+            das: Duration::from_millis(167),
+            arr: Duration::from_millis(33),
+            sdf: 20,
+            dcd: Duration::from_millis(17),
+        }
+    }
+}
+
+// The config sections with the key bindings and their default presets
+const KEY_SECTIONS: [(&str, Preset); 3] = [
+    ("keys", Preset::Arrows),
+    ("versus.player1", Preset::VersusLeft),
+    ("versus.player2", Preset::VersusRight),
+];
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub keys: KeyBindings,
+    // The keys of the two players sharing the keyboard in the versus mode
+    pub versus_keys: [KeyBindings; 2],
+    pub handling: Handling,
+    // The id of the piece set of the games which use one
+    pub pieces: String,
+    // The cascade gravity in the same games
+    pub cascade: bool,
+    pub modifiers: Modifiers,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            keys: KeyBindings::from_preset(KEY_SECTIONS[0].1),
+            versus_keys: [
+                KeyBindings::from_preset(KEY_SECTIONS[1].1),
+                KeyBindings::from_preset(KEY_SECTIONS[2].1),
+            ],
+            handling: Handling::default(),
+            pieces: pieces::STANDARD_SET.to_string(),
+            cascade: false,
+            modifiers: Modifiers::default(),
+        }
+    }
+}
+
+impl Config {
+    // `$XDG_CONFIG_HOME/ratrix/config.toml`, falling back to `~/.config/ratrix/config.toml`
+    pub fn path() -> Option<PathBuf> {
+        let base = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(base.join("ratrix").join("config.toml"))
+    }
+
+    // Loads the config file. A missing file means that the defaults should be used.
+    pub fn load() -> io::Result<Self> {
+        let Some(path) = Self::path() else {
+            return Ok(Self::default());
+        };
+        match fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e))),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = Self::path() else {
+            return Err(io::Error::new(ErrorKind::NotFound, "no config directory"));
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_toml())
+    }
+
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let entries = parse_toml(text)?;
+
+        // The presets are applied first, so the rest of the keys can override them
+        let mut config = Self::default();
+        for entry in entries.iter().filter(|entry| entry.key == "preset") {
+            if let Some(keys) = config.section_keys(&entry.section) {
+                let preset = entry
+                    .values
+                    .first()
+                    .and_then(|name| Preset::from_name(name))
+                    .ok_or_else(|| invalid_data(entry.line, "unknown preset"))?;
+                *keys = KeyBindings::from_preset(preset);
+            }
+        }
+
+        for entry in entries.iter() {
+            if entry.section == "handling" {
+                config.parse_handling_entry(entry)?;
+            } else if entry.section == "game" {
+                config.parse_game_entry(entry)?;
+            } else if entry.section == "modifiers" {
+                config.parse_modifier_entry(entry)?;
+            } else if entry.key != "preset"
+                && let Some(keys) = config.section_keys(&entry.section)
+            {
+                Self::parse_key_entry(keys, entry)?;
+            }
+        }
+        Ok(config)
+    }
+
+    fn section_keys(&mut self, section: &str) -> Option<&mut KeyBindings> {
+        let [player1, player2] = &mut self.versus_keys;
+        match KEY_SECTIONS.iter().position(|(name, _)| *name == section)? {
+            0 => Some(&mut self.keys),
+            1 => Some(player1),
+            _ => Some(player2),
+        }
+    }
+
+    fn parse_key_entry(bindings: &mut KeyBindings, entry: &Entry) -> io::Result<()> {
+        let action = Action::from_name(&entry.key)
+            .ok_or_else(|| invalid_data(entry.line, "unknown action"))?;
+        let mut keys = Vec::new();
+        for value in entry.values.iter() {
+            let key = KeyBinding::parse(value)
+                .ok_or_else(|| invalid_data(entry.line, &format!("unknown key `{}`", value)))?;
+            keys.push(key);
+        }
+        bindings.set(action, keys);
+        Ok(())
+    }
+
+    fn parse_handling_entry(&mut self, entry: &Entry) -> io::Result<()> {
+        let value: u64 = match entry.values.as_slice() {
+            [value] => value
+                .parse()
+                .map_err(|_| invalid_data(entry.line, "expected a number"))?,
+            _ => return Err(invalid_data(entry.line, "expected a number")),
+        };
+        match entry.key.as_str() {
+            "das" => self.handling.das = Duration::from_millis(value),
+            "arr" => self.handling.arr = Duration::from_millis(value),
+            "dcd" => self.handling.dcd = Duration::from_millis(value),
+            "sdf" if value > 0 => self.handling.sdf = value.min(u32::MAX as u64) as u32,
+            "sdf" => return Err(invalid_data(entry.line, "sdf must be positive")),
+            _ => return Err(invalid_data(entry.line, "unknown handling setting")),
+        }
+        Ok(())
+    }
+
+    // The set isn't checked here, a missing one falls back to the standard pieces
+    fn parse_game_entry(&mut self, entry: &Entry) -> io::Result<()> {
+        match (entry.key.as_str(), entry.values.as_slice()) {
+            ("pieces", [id]) => self.pieces = id.clone(),
+            ("pieces", _) => return Err(invalid_data(entry.line, "expected a piece set")),
+            ("cascade", [value]) if value == "true" || value == "false" => {
+                self.cascade = value == "true";
+            }
+            ("cascade", _) => return Err(invalid_data(entry.line, "expected true or false")),
+            _ => return Err(invalid_data(entry.line, "unknown game setting")),
+        }
+        Ok(())
+    }
+
+    // The fading is given in seconds, zero turns it off
+    fn parse_modifier_entry(&mut self, entry: &Entry) -> io::Result<()> {
+        let modifiers = &mut self.modifiers;
+        let flag = || match entry.values.as_slice() {
+            [value] if value == "true" || value == "false" => Ok(value == "true"),
+            _ => Err(invalid_data(entry.line, "expected true or false")),
+        };
+        match entry.key.as_str() {
+            "invisible" => modifiers.invisible = flag()?,
+            "monochrome" => modifiers.monochrome = flag()?,
+            "big" => modifiers.big = flag()?,
+            "fade" => {
+                let seconds: u64 = match entry.values.as_slice() {
+                    [value] => value
+                        .parse()
+                        .map_err(|_| invalid_data(entry.line, "expected a number"))?,
+                    _ => return Err(invalid_data(entry.line, "expected a number")),
+                };
+                modifiers.fade = (seconds > 0).then_some(seconds);
+            }
+            _ => return Err(invalid_data(entry.line, "unknown modifier")),
+        }
+        Ok(())
+    }
+
+    pub fn to_toml(&self) -> String {
+        let mut result = String::from("# Ratrix configuration\n");
+        let sections = [&self.keys, &self.versus_keys[0], &self.versus_keys[1]];
+        for ((section, _), bindings) in KEY_SECTIONS.iter().zip(sections) {
+            result.push_str(&format!("\n[{}]\n", section));
+            result.push_str(&format!("preset = \"{}\"\n", bindings.preset.name()));
+            for action in Action::ALL {
+                let keys: Vec<String> = bindings
+                    .keys(&action)
+                    .iter()
+                    .map(|key| quote(&key.format()))
+                    .collect();
+                result.push_str(&format!("{} = [{}]\n", action.name(), keys.join(", ")));
+            }
+        }
+
+        let handling = &self.handling;
+        result.push_str("\n# Delays in milliseconds, sdf is a multiplier of gravity\n[handling]\n");
+        result.push_str(&format!("das = {}\n", handling.das.as_millis()));
+        result.push_str(&format!("arr = {}\n", handling.arr.as_millis()));
+        result.push_str(&format!("sdf = {}\n", handling.sdf));
+        result.push_str(&format!("dcd = {}\n", handling.dcd.as_millis()));
+
+        result.push_str("\n[game]\n");
+        result.push_str(&format!("pieces = {}\n", quote(&self.pieces)));
+        result.push_str(&format!("cascade = {}\n", self.cascade));
+
+        let modifiers = &self.modifiers;
+        result.push_str("\n# The fading is in seconds, 0 turns it off\n[modifiers]\n");
+        result.push_str(&format!("invisible = {}\n", modifiers.invisible));
+        result.push_str(&format!("fade = {}\n", modifiers.fade.unwrap_or(0)));
+        result.push_str(&format!("monochrome = {}\n", modifiers.monochrome));
+        result.push_str(&format!("big = {}\n", modifiers.big));
+        result
+    }
+}
+
+// The directory for the records of the games: `$XDG_DATA_HOME/ratrix`,
+// falling back to `~/.local/share/ratrix`
+pub fn data_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?)
+            .join(".local")
+            .join("share"),
+    };
+    Some(base.join("ratrix"))
+}
+
+// The `.toml` files of the directory in the order of their names,
+// each with its name without the extension
+pub fn toml_files(dir: &Path) -> io::Result<Vec<(String, PathBuf)>> {
+    let mut files: Vec<(String, PathBuf)> = fs::read_dir(dir)?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .map(|path| {
+            let stem = path.file_stem().unwrap_or_default();
+            (stem.to_string_lossy().into_owned(), path)
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+// A single `key = value` line of the config file.
+// Scalar values are stored as a one-element list, so `hold = "c"` and `hold = ["c"]` are the same.
+#[derive(Debug)]
+pub struct Entry {
+    pub line: usize,
+    pub section: String,
+    pub key: String,
+    pub values: Vec<String>,
+}
+
+pub fn invalid_data(line: usize, message: &str) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("line {}: {}", line, message),
+    )
+}
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// Parses the small subset of TOML used by the config and the puzzle files:
+// `[section]` headers, `key = value` pairs, strings, bare words (numbers, booleans),
+// arrays of those and `#` comments.
+pub fn parse_toml(text: &str) -> io::Result<Vec<Entry>> {
+    let mut result = Vec::new();
+    let mut section = String::new();
+
+    for (i, raw_line) in text.lines().enumerate() {
+        let line_no = i + 1;
+        let mut chars = raw_line.trim().chars().peekable();
+
+        match chars.peek() {
+            None | Some('#') => continue,
+            Some('[') => {
+                let line = raw_line.trim();
+                if !line.ends_with(']') {
+                    return Err(invalid_data(line_no, "unterminated section header"));
+                }
+                section = line[1..line.len() - 1].trim().to_string();
+                continue;
+            }
+            _ => {}
+        }
+
+        let key: String = chars.by_ref().take_while(|c| *c != '=').collect();
+        let key = key.trim().to_string();
+        if key.is_empty() {
+            return Err(invalid_data(line_no, "expected `key = value`"));
+        }
+
+        let mut values = Vec::new();
+        let mut in_array = false;
+        loop {
+            match chars.next() {
+                None if in_array => return Err(invalid_data(line_no, "unterminated array")),
+                None => break,
+                Some(c) if c.is_whitespace() || c == ',' => {}
+                Some('#') if !in_array => break,
+                Some('[') if !in_array && values.is_empty() => in_array = true,
+                Some(']') if in_array => in_array = false,
+                Some('"') => {
+                    let mut value = String::new();
+                    loop {
+                        match chars.next() {
+                            None => return Err(invalid_data(line_no, "unterminated string")),
+                            Some('"') => break,
+                            Some('\\') => match chars.next() {
+                                Some(escaped) => value.push(escaped),
+                                None => return Err(invalid_data(line_no, "unterminated string")),
+                            },
+                            Some(c) => value.push(c),
+                        }
+                    }
+                    values.push(value);
+                }
+                Some(c) => {
+                    // A bare word, e.g. a number
+                    let mut value = String::from(c);
+                    while let Some(next) = chars.peek() {
+                        if next.is_whitespace() || *next == ',' || *next == ']' || *next == '#' {
+                            break;
+                        }
+                        value.push(*next);
+                        chars.next();
+                    }
+                    values.push(value);
+                }
+            }
+        }
+        if values.is_empty() && !raw_line.contains('[') {
+            return Err(invalid_data(line_no, "missing value"));
+        }
+
+        result.push(Entry {
+            line: line_no,
+            section: section.clone(),
+            key,
+            values,
+        });
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_notation_is_parsed() {
+        let key = KeyBinding::parse("Ctrl+Shift+z").unwrap();
+        assert_eq!(key.code, KeyCode::Char('z'));
+        assert_eq!(key.modifiers, KeyModifiers::CONTROL | KeyModifiers::SHIFT);
+        assert_eq!(KeyBinding::parse("Space").unwrap().code, KeyCode::Char(' '));
+        assert_eq!(KeyBinding::parse("F12").unwrap().code, KeyCode::F(12));
+        assert!(KeyBinding::parse("Hyper+x").is_none());
+        assert!(KeyBinding::parse("xyz").is_none());
+    }
+
+    #[test]
+    fn uppercase_letter_is_the_same_as_shift() {
+        assert_eq!(KeyBinding::parse("A"), KeyBinding::parse("Shift+a"));
+        let event = KeyEvent::new(KeyCode::Char('A'), KeyModifiers::SHIFT);
+        assert_eq!(
+            KeyBinding::from_event(&event),
+            KeyBinding::parse("Shift+a").unwrap()
+        );
+    }
+
+    #[test]
+    fn config_overrides_the_preset() {
+        let text = r#"
+            # Vim keys, but hard drop on the space bar
+            [keys]
+            preset = "vim"
+            hard_drop = ["Space", "Enter"] # two keys
+            hold = "c"
+        "#;
+        let config = Config::parse(text).unwrap();
+        let keys = &config.keys;
+        assert_eq!(
+            keys.keys(&Action::MoveLeft),
+            &[KeyBinding::parse("h").unwrap()]
+        );
+        assert_eq!(keys.keys(&Action::HardDrop).len(), 2);
+        let event = KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE);
+        assert_eq!(keys.action_for(&event), Some(Action::HardDrop));
+        let event = KeyEvent::new(KeyCode::Char('c'), KeyModifiers::NONE);
+        assert_eq!(keys.action_for(&event), Some(Action::Hold));
+    }
+
+    #[test]
+    fn handling_is_read_in_milliseconds() {
+        let text = "[handling]\ndas = 100\narr = 0\n";
+        let handling = Config::parse(text).unwrap().handling;
+        assert_eq!(handling.das, Duration::from_millis(100));
+        assert_eq!(handling.arr, Duration::ZERO);
+        assert_eq!(handling.sdf, Handling::default().sdf);
+        assert!(Config::parse("[handling]\nsdf = 0\n").is_err());
+        assert!(Config::parse("[handling]\ndas = \"fast\"\n").is_err());
+    }
+
+    #[test]
+    fn config_errors_report_the_line() {
+        let error = Config::parse("[keys]\nfly = \"f\"\n").unwrap_err();
+        assert!(error.to_string().contains("line 2"));
+        let error = Config::parse("[keys]\nhold = \"c\n").unwrap_err();
+        assert!(error.to_string().contains("unterminated string"));
+    }
+
+    #[test]
+    fn config_survives_the_round_trip() {
+        let mut config = Config::default();
+        config
+            .keys
+            .add(Action::Hold, KeyBinding::parse("\"").unwrap());
+        config
+            .keys
+            .add(Action::Hold, KeyBinding::parse("Alt+Left").unwrap());
+        config.handling.arr = Duration::ZERO;
+        config.pieces = String::from("pentominoes");
+        config.cascade = true;
+        config.modifiers.fade = Some(5);
+        config.modifiers.big = true;
+        let restored = Config::parse(&config.to_toml()).unwrap();
+        assert_eq!(config.handling, restored.handling);
+        assert_eq!(config.pieces, restored.pieces);
+        assert!(restored.cascade);
+        assert_eq!(config.modifiers, restored.modifiers);
+        for action in Action::ALL {
+            assert_eq!(config.keys.keys(&action), restored.keys.keys(&action));
+        }
+    }
+
+    #[test]
+    fn added_key_is_removed_from_other_actions() {
+        let mut keys = KeyBindings::from_preset(Preset::Arrows);
+        keys.add(Action::Hold, KeyBinding::parse("Space").unwrap());
+        let event = KeyEvent::new(KeyCode::Char(' '), KeyModifiers::NONE);
+        assert_eq!(keys.action_for(&event), Some(Action::Hold));
+        assert!(keys.keys(&Action::HardDrop).is_empty());
+    }
+
+    #[test]
+    fn versus_players_have_separate_keys() {
+        let text = r#"
+            [versus.player2]
+            preset = "vim"
+            hold = "Enter"
+        "#;
+        let config = Config::parse(text).unwrap();
+        let [player1, player2] = &config.versus_keys;
+        assert_eq!(player1.preset, Preset::VersusLeft);
+        assert_eq!(player2.preset, Preset::Vim);
+        let event = KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE);
+        assert_eq!(player2.action_for(&event), Some(Action::Hold));
+        // The keys of the player 1 and the single player game are untouched
+        assert_eq!(config.keys.action_for(&event), None);
+
+        // The default halves of the keyboard don't share the gameplay keys
+        let defaults = Config::default();
+        let [left, right] = &defaults.versus_keys;
+        for action in Action::ALL.iter().filter(|a| left.keys(a) != right.keys(a)) {
+            for key in left.keys(action) {
+                assert!(Action::ALL.iter().all(|a| !right.keys(a).contains(key)));
+            }
+        }
+    }
+}
@@ -0,0 +1,332 @@
+use crossterm::event::{KeyCode, KeyEvent};
+
+use crate::pieces::Color;
+use crate::puzzles::{self, Goal};
+use crate::tetris_engine::{
+    DEFAULT_HEIGHT, DEFAULT_WIDTH, GameState, Setup, piece_by_name, piece_color, piece_name,
+};
+
+// The standard pieces in the order they are cycled through
+const PIECES: [char; 7] = ['I', 'J', 'L', 'O', 'S', 'T', 'Z'];
+// The goals the puzzle can be saved with
+const GOALS: [Goal; 8] = [
+    Goal::Lines(1),
+    Goal::Lines(2),
+    Goal::Lines(3),
+    Goal::Lines(4),
+    Goal::TSpin(1),
+    Goal::TSpin(2),
+    Goal::TSpin(3),
+    Goal::PerfectClear,
+];
+// The queue still fits the side panel
+const MAX_QUEUE: usize = 14;
+
+const HELP: [&str; 7] = [
+    "Arrows move  Space cell",
+    "c color  g garbage  Del clear",
+    "a active  h hold",
+    "IJLOSTZ queue  Bksp remove",
+    "n goal  p save puzzle",
+    "w save position  PgUp/PgDn load",
+    "Enter play  Esc back",
+];
+
+// What the editor asks the app to do after a key press
+#[derive(Debug, PartialEq)]
+pub enum EditorRequest {
+    // Starts the practice game from the edited position
+    Play(Setup),
+    Back,
+}
+
+// The piece after the given one, no piece comes after the last one
+fn cycled(piece: Option<char>) -> Option<char> {
+    match piece {
+        None => Some(PIECES[0]),
+        Some(piece) => {
+            let index = PIECES.iter().position(|&p| p == piece)?;
+            PIECES.get(index + 1).copied()
+        }
+    }
+}
+
+// The board editor: the cells of the standard playfield with their colors and the pieces
+// of the position, which is played from here or saved as a puzzle or a starting position.
+// The keys of this screen are fixed, as on the key bindings screen.
+#[derive(Debug)]
+pub struct EditorScreen {
+    // The rows from the top, the colors of the cells from the left
+    playfield: Vec<u16>,
+    colors: Vec<Vec<Color>>,
+    // The column and the row of the cell under the cursor
+    cursor: [usize; 2],
+    // The cells are filled in the color of this piece, or as the garbage
+    brush: Option<char>,
+    active: Option<char>,
+    hold: Option<char>,
+    queue: Vec<char>,
+    // The goal of the puzzle when it's saved as one
+    goal: Goal,
+    // The saved position loaded last, the saved ones are browsed from it
+    position: Option<usize>,
+    status: String,
+}
+
+impl EditorScreen {
+    pub fn new() -> Self {
+        Self {
+            playfield: vec![0; DEFAULT_HEIGHT],
+            colors: vec![vec![Color::default(); DEFAULT_WIDTH]; DEFAULT_HEIGHT],
+            cursor: [0, DEFAULT_HEIGHT - 1],
+            brush: None,
+            active: None,
+            hold: None,
+            queue: Vec::new(),
+            goal: Goal::PerfectClear,
+            position: None,
+            status: String::new(),
+        }
+    }
+
+    pub fn handle_key(&mut self, key: &KeyEvent) -> Option<EditorRequest> {
+        self.status.clear();
+        let [x, y] = &mut self.cursor;
+        match key.code {
+            KeyCode::Esc => return Some(EditorRequest::Back),
+            KeyCode::Enter => return Some(EditorRequest::Play(self.setup())),
+            KeyCode::Left => *x = x.saturating_sub(1),
+            KeyCode::Right => *x = (*x + 1).min(DEFAULT_WIDTH - 1),
+            KeyCode::Up => *y = y.saturating_sub(1),
+            KeyCode::Down => *y = (*y + 1).min(DEFAULT_HEIGHT - 1),
+            KeyCode::Char(' ') => self.toggle_cell(),
+            KeyCode::Char('c') => self.brush = cycled(self.brush),
+            KeyCode::Char('g') => self.garbage_row(),
+            KeyCode::Delete => self.clear(),
+            KeyCode::Char('a') => self.active = cycled(self.active),
+            KeyCode::Char('h') => self.hold = cycled(self.hold),
+            KeyCode::Char(c)
+                if PIECES.contains(&c.to_ascii_uppercase()) && self.queue.len() < MAX_QUEUE =>
+            {
+                self.queue.push(c.to_ascii_uppercase())
+            }
+            KeyCode::Backspace => {
+                self.queue.pop();
+            }
+            KeyCode::Char('n') => {
+                let index = GOALS.iter().position(|&goal| goal == self.goal);
+                self.goal = GOALS[index.map_or(0, |index| (index + 1) % GOALS.len())];
+            }
+            KeyCode::Char('p') => self.save(Some(self.goal)),
+            KeyCode::Char('w') => self.save(None),
+            KeyCode::PageUp => self.browse(false),
+            KeyCode::PageDown => self.browse(true),
+            _ => {}
+        }
+        None
+    }
+
+    fn bit(&self) -> u16 {
+        1 << (DEFAULT_WIDTH - 1 - self.cursor[0])
+    }
+
+    // Fills the empty cell with the brush, or empties the filled one
+    fn toggle_cell(&mut self) {
+        let bit = self.bit();
+        let [x, y] = self.cursor;
+        self.playfield[y] ^= bit;
+        self.colors[y][x] = match self.playfield[y] & bit {
+            0 => Color::default(),
+            _ => self.brush.and_then(piece_color).unwrap_or_default(),
+        };
+    }
+
+    // The row of the cursor becomes a garbage row with the hole under the cursor
+    fn garbage_row(&mut self) {
+        let full_row = (1 << DEFAULT_WIDTH) - 1;
+        let y = self.cursor[1];
+        self.playfield[y] = full_row & !self.bit();
+        self.colors[y].fill(Color::default());
+    }
+
+    fn clear(&mut self) {
+        self.playfield.fill(0);
+        for row in &mut self.colors {
+            row.fill(Color::default());
+        }
+    }
+
+    // The position as the game starts from it, the active piece is the first one to come
+    pub fn setup(&self) -> Setup {
+        Setup {
+            playfield: self.playfield.clone(),
+            colors: self.colors.clone(),
+            queue: self
+                .active
+                .iter()
+                .chain(&self.queue)
+                .filter_map(|&name| piece_by_name(name))
+                .collect(),
+            hold: self.hold.and_then(piece_by_name),
+        }
+    }
+
+    // Edits the position, the rows which don't fit are cut off at the top
+    fn load(&mut self, setup: &Setup) {
+        self.clear();
+        let rows = setup.playfield.len().min(DEFAULT_HEIGHT);
+        let skipped = setup.playfield.len() - rows;
+        let top = DEFAULT_HEIGHT - rows;
+        for (i, row) in setup.playfield.iter().skip(skipped).enumerate() {
+            self.playfield[top + i] = row & ((1 << DEFAULT_WIDTH) - 1);
+            if let Some(colors) = setup.colors.get(skipped + i) {
+                for (cell, color) in self.colors[top + i].iter_mut().zip(colors) {
+                    *cell = *color;
+                }
+            }
+        }
+        let mut pieces = setup.queue.iter().filter_map(|&piece| piece_name(piece));
+        self.active = pieces.next();
+        self.queue = pieces.take(MAX_QUEUE).collect();
+        self.hold = setup.hold.and_then(piece_name);
+    }
+
+    // Saves the position as a puzzle with the goal, or as a starting position without it
+    fn save(&mut self, goal: Option<Goal>) {
+        let setup = self.setup();
+        if goal.is_some() && setup.queue.is_empty() {
+            self.status = String::from("The puzzle needs pieces");
+            return;
+        }
+        self.status = match puzzles::save(goal, &setup) {
+            Ok(path) => format!("Saved to {}", path.display()),
+            Err(e) => format!("Couldn't save: {}", e),
+        };
+    }
+
+    // Loads the next saved position, or the previous one
+    fn browse(&mut self, forward: bool) {
+        let positions = match puzzles::positions() {
+            Ok(positions) => positions,
+            Err(e) => {
+                self.status = format!("Couldn't read the positions: {}", e);
+                return;
+            }
+        };
+        let len = positions.len();
+        if len == 0 {
+            self.status = String::from("No saved positions yet");
+            return;
+        }
+        let index = match (self.position, forward) {
+            (None, true) => 0,
+            (None, false) => len - 1,
+            (Some(index), true) => (index + 1) % len,
+            (Some(index), false) => (index + len - 1) % len,
+        };
+        self.load(&positions[index]);
+        self.position = Some(index);
+        self.status = format!("Position {}/{}", index + 1, len);
+    }
+
+    // The board as it's drawn, the active piece isn't on the board yet
+    pub fn state(&self) -> GameState {
+        GameState {
+            playfield: self.playfield.clone(),
+            colors: self.colors.clone(),
+            hold_piece: self.hold.and_then(piece_by_name),
+            hold_color: self.hold.and_then(piece_color).unwrap_or_default(),
+            ..GameState::default()
+        }
+    }
+
+    pub fn cursor(&self) -> [usize; 2] {
+        self.cursor
+    }
+
+    pub fn status(&self) -> &str {
+        &self.status
+    }
+
+    // The pieces, the brush and the goal, with the keys under them
+    pub fn hud(&self) -> Vec<String> {
+        let name = |piece: Option<char>| piece.map_or(String::from("-"), String::from);
+        let queue: String = self.queue.iter().collect();
+        let mut hud = vec![
+            format!("Active  {}", name(self.active)),
+            format!("Queue   {}", if queue.is_empty() { "-" } else { &queue }),
+            format!(
+                "Color   {}",
+                self.brush.map_or(String::from("garbage"), String::from)
+            ),
+            format!("Goal    {}", self.goal.describe()),
+            String::new(),
+        ];
+        hud.extend(HELP.iter().map(|line| line.to_string()));
+        hud
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::KeyModifiers;
+
+    fn press(editor: &mut EditorScreen, keys: &[KeyCode]) {
+        for &code in keys {
+            editor.handle_key(&KeyEvent::new(code, KeyModifiers::NONE));
+        }
+    }
+
+    #[test]
+    fn edited_cells_and_pieces_make_the_setup() {
+        let mut editor = EditorScreen::new();
+        // The T colored cell in the bottom left corner, the garbage row above it
+        // with the hole in the second column
+        press(&mut editor, &[KeyCode::Char('c'); 6]);
+        press(
+            &mut editor,
+            &[KeyCode::Char(' '), KeyCode::Up, KeyCode::Right],
+        );
+        press(&mut editor, &[KeyCode::Char('g')]);
+        press(&mut editor, &[KeyCode::Char('a'), KeyCode::Char('h')]);
+        press(&mut editor, &[KeyCode::Char('h'), KeyCode::Char('t')]);
+        press(&mut editor, &[KeyCode::Char('O'), KeyCode::Char('z')]);
+        press(&mut editor, &[KeyCode::Backspace]);
+
+        let setup = editor.setup();
+        assert_eq!(setup.playfield[18..], [0b1011111111, 0b1000000000]);
+        assert_eq!(setup.colors[19][0], piece_color('T').unwrap());
+        assert_eq!(setup.colors[18][0], Color::default());
+        let pieces: Vec<_> = "ITO".chars().filter_map(piece_by_name).collect();
+        assert_eq!(setup.queue, pieces);
+        assert_eq!(setup.hold, piece_by_name('J'));
+
+        // The setup is edited again as it was
+        let mut loaded = EditorScreen::new();
+        loaded.load(&setup);
+        assert_eq!(loaded.setup(), setup);
+
+        // The cell is emptied by the second toggle
+        press(
+            &mut editor,
+            &[KeyCode::Down, KeyCode::Left, KeyCode::Char(' ')],
+        );
+        assert_eq!(editor.setup().playfield[19], 0);
+        press(&mut editor, &[KeyCode::Delete]);
+        assert!(editor.setup().playfield.iter().all(|&row| row == 0));
+    }
+
+    #[test]
+    fn play_starts_from_the_edited_position() {
+        let mut editor = EditorScreen::new();
+        press(&mut editor, &[KeyCode::Char('t')]);
+        let key = KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE);
+        assert_eq!(
+            editor.handle_key(&key),
+            Some(EditorRequest::Play(editor.setup()))
+        );
+        let key = KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE);
+        assert_eq!(editor.handle_key(&key), Some(EditorRequest::Back));
+    }
+}
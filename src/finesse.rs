@@ -0,0 +1,337 @@
+use std::collections::{HashSet, VecDeque};
+
+use crate::config::Action;
+use crate::modes::Ruleset;
+use crate::pieces::Shape;
+use crate::tetris_engine::{Lock, Rules, TetrisEngine, piece_cells};
+
+// The DAS moves the piece to the wall. The finesse is checked only on the standard
+// 10 wide board, the piece can't move further than that.
+const DAS_MOVES: usize = 10;
+
+// A single input of the optimal sequences, the DAS counts as one input like the tap
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Input {
+    Left,
+    Right,
+    DasLeft,
+    DasRight,
+    RotateCw,
+    RotateCcw,
+    Rotate180,
+}
+
+const INPUTS: [Input; 7] = [
+    Input::Left,
+    Input::Right,
+    Input::DasLeft,
+    Input::DasRight,
+    Input::RotateCw,
+    Input::RotateCcw,
+    Input::Rotate180,
+];
+
+fn apply(engine: &mut TetrisEngine, input: Input) {
+    match input {
+        Input::Left => engine.move_current_shape(-1, 0),
+        Input::Right => engine.move_current_shape(1, 0),
+        Input::DasLeft => (0..DAS_MOVES).for_each(|_| engine.move_current_shape(-1, 0)),
+        Input::DasRight => (0..DAS_MOVES).for_each(|_| engine.move_current_shape(1, 0)),
+        Input::RotateCw => engine.rotate(),
+        Input::RotateCcw => engine.rotate_ccw(),
+        Input::Rotate180 => engine.rotate_180(),
+    }
+}
+
+// Where the piece can be hard dropped on the empty board
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Placement {
+    pub shape: Shape,
+    pub x: u8,
+    // The least number of inputs which bring the piece there from the spawn
+    pub inputs: usize,
+    // The cells of the dropped piece
+    pub cells: [u16; 20],
+}
+
+// All the placements of the piece which spawns as `piece`, found by the breadth-first
+// search over the inputs. The pieces with the same shape in two orientations
+// (e.g. S or I) have a single placement for both.
+pub fn placements(piece: Shape) -> Vec<Placement> {
+    let mut spawn = TetrisEngine::new();
+    spawn.set_piece(piece);
+    let key = |engine: &TetrisEngine| {
+        let state = engine.get_state();
+        (state.active_piece, state.piece_position[0])
+    };
+    let mut seen = HashSet::from([key(&spawn)]);
+    let mut queue = VecDeque::from([(spawn, 0)]);
+    let mut placements = Vec::new();
+    while let Some((engine, inputs)) = queue.pop_front() {
+        let (shape, x) = key(&engine);
+        let mut dropped = engine.clone();
+        dropped.hard_drop();
+        let lock = dropped.take_locks()[0];
+        placements.push(Placement {
+            shape,
+            x,
+            inputs,
+            cells: piece_cells(lock.shape, lock.position),
+        });
+        for input in INPUTS {
+            let mut next = engine.clone();
+            apply(&mut next, input);
+            if seen.insert(key(&next)) {
+                queue.push_back((next, inputs + 1));
+            }
+        }
+    }
+    placements
+}
+
+// How the placement of the locked piece compares to the optimal one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+    Perfect,
+    Fault { keys: usize, optimal: usize },
+    // The soft drop was used, e.g. for a tuck or a spin, which the check doesn't cover
+    Unchecked,
+}
+
+// Counts the inputs of every piece and compares them to the optimal ones on lock
+#[derive(Debug, Clone, Default)]
+pub struct Finesse {
+    keys: usize,
+    soft_dropped: bool,
+    // The verdict of the last piece, shown in the HUD until the next lock
+    last: Option<Verdict>,
+}
+
+impl Finesse {
+    pub fn on_action(&mut self, action: &Action) {
+        match action {
+            Action::MoveLeft
+            | Action::MoveRight
+            | Action::RotateCw
+            | Action::RotateCcw
+            | Action::Rotate180 => self.keys += 1,
+            Action::SoftDrop => self.soft_dropped = true,
+            // The piece from the hold starts from the spawn again
+            Action::Hold => *self = Self::default(),
+            _ => {}
+        }
+    }
+
+    // The optimal inputs are known only for the standard pieces on the standard board,
+    // the placements of the other games aren't `checked`
+    pub fn on_lock(&mut self, lock: &Lock, checked: bool) -> Verdict {
+        let verdict = if self.soft_dropped || !checked {
+            Verdict::Unchecked
+        } else {
+            let optimal = placements(lock.piece)
+                .into_iter()
+                .find(|p| p.shape == lock.shape && p.x == lock.position[0])
+                .map(|p| p.inputs);
+            match optimal {
+                Some(optimal) if self.keys > optimal => Verdict::Fault {
+                    keys: self.keys,
+                    optimal,
+                },
+                Some(_) => Verdict::Perfect,
+                None => Verdict::Unchecked,
+            }
+        };
+        *self = Self {
+            last: Some(verdict),
+            ..Self::default()
+        };
+        verdict
+    }
+
+    pub fn hud(&self) -> Vec<String> {
+        match self.last {
+            Some(Verdict::Fault { keys, optimal }) => vec![
+                String::from("Finesse fault"),
+                format!("{} keys, {} needed", keys, optimal),
+            ],
+            _ => Vec::new(),
+        }
+    }
+}
+
+// The finesse drill: the piece has to be placed onto the outline with the least inputs,
+// it's repeated on the empty board until it's done right
+#[derive(Debug, Default)]
+pub struct Drill {
+    target: Option<Placement>,
+    streak: usize,
+    best_streak: usize,
+    misses: usize,
+}
+
+impl Drill {
+    // A random placement of the piece which is at the spawn now
+    fn choose_target(&mut self, engine: &mut TetrisEngine) {
+        let mut options = placements(engine.get_state().active_piece);
+        let target = options.swap_remove(rand::random_range(0..options.len()));
+        self.target = Some(target);
+    }
+}
+
+impl Ruleset for Drill {
+    fn rules(&self) -> Rules {
+        Rules {
+            lines_per_level: None,
+            hold: false,
+            ..Rules::default()
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    fn setup(&mut self, engine: &mut TetrisEngine) {
+        self.choose_target(engine);
+    }
+
+    fn on_lock(&mut self, engine: &mut TetrisEngine, lock: &Lock, verdict: &Verdict) {
+        engine.clear_playfield();
+        let placed = self
+            .target
+            .is_some_and(|target| target.shape == lock.shape && target.x == lock.position[0]);
+        if placed && *verdict == Verdict::Perfect {
+            self.streak += 1;
+            self.best_streak = self.best_streak.max(self.streak);
+            self.choose_target(engine);
+        } else {
+            self.streak = 0;
+            self.misses += 1;
+            engine.set_piece(lock.piece);
+        }
+    }
+
+    fn overlay(&self) -> Vec<u16> {
+        self.target
+            .map_or(Vec::new(), |target| target.cells.to_vec())
+    }
+
+    fn hud(&self, _engine: &TetrisEngine, _frame: u64) -> Vec<String> {
+        vec![
+            String::from("Streak"),
+            format!("{} (best {})", self.streak, self.best_streak),
+            String::new(),
+            String::from("Misses"),
+            self.misses.to_string(),
+        ]
+    }
+
+    fn finish(&mut self, _engine: &TetrisEngine, _standard: bool) -> (String, Vec<String>) {
+        let info = vec![
+            format!("Best streak: {}", self.best_streak),
+            format!("Misses: {}", self.misses),
+        ];
+        (String::from("Finesse drill"), info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tetris_engine::{piece_by_name, piece_rotations};
+
+    const T: Shape = 0b_00000_11100_01000;
+    const O: Shape = 0b_00000_11000_11000;
+    const I: Shape = 0b_10000_10000_10000_10000;
+
+    #[test]
+    fn shapes_are_the_spawn_orientations() {
+        assert_eq!(piece_by_name('T'), Some(T));
+        assert_eq!(piece_by_name('O'), Some(O));
+        assert_eq!(piece_by_name('I'), Some(I));
+    }
+
+    fn optimal(piece: Shape, shape: Shape, x: u8) -> usize {
+        placements(piece)
+            .into_iter()
+            .find(|p| p.shape == shape && p.x == x)
+            .unwrap()
+            .inputs
+    }
+
+    #[test]
+    fn every_placement_is_reachable() {
+        // 4 orientations of the T piece in 8 or 9 columns
+        assert_eq!(placements(T).len(), 8 + 9 + 8 + 9);
+        assert_eq!(placements(O).len(), 9);
+        // The vertical and the horizontal I
+        assert_eq!(placements(I).len(), 10 + 7);
+    }
+
+    #[test]
+    fn walls_are_reached_with_das() {
+        // The spawn column, one tap, the DAS to the wall, the DAS and a tap back
+        assert_eq!(optimal(O, O, 4), 0);
+        assert_eq!(optimal(O, O, 3), 1);
+        assert_eq!(optimal(O, O, 0), 1);
+        assert_eq!(optimal(O, O, 8), 1);
+        assert_eq!(optimal(O, O, 1), 2);
+        // The rotation and the DAS
+        assert_eq!(optimal(T, piece_rotations(T).unwrap()[1], 0), 2);
+    }
+
+    fn lock(piece: Shape, shape: Shape, x: u8) -> Lock {
+        Lock {
+            piece,
+            shape,
+            position: [x, 0],
+            ..Lock::default()
+        }
+    }
+
+    #[test]
+    fn extra_inputs_are_faults() {
+        let mut finesse = Finesse::default();
+        for action in [Action::MoveLeft, Action::MoveLeft, Action::MoveRight] {
+            finesse.on_action(&action);
+        }
+        assert_eq!(
+            finesse.on_lock(&lock(O, O, 3), true),
+            Verdict::Fault {
+                keys: 3,
+                optimal: 1
+            }
+        );
+        assert_eq!(finesse.hud()[1], "3 keys, 1 needed");
+        // The counts start over with the next piece
+        finesse.on_action(&Action::MoveLeft);
+        assert_eq!(finesse.on_lock(&lock(O, O, 3), true), Verdict::Perfect);
+        assert!(finesse.hud().is_empty());
+        finesse.on_action(&Action::SoftDrop);
+        assert_eq!(finesse.on_lock(&lock(O, O, 4), true), Verdict::Unchecked);
+        // The custom games aren't checked
+        finesse.on_action(&Action::MoveLeft);
+        finesse.on_action(&Action::MoveLeft);
+        assert_eq!(finesse.on_lock(&lock(O, O, 3), false), Verdict::Unchecked);
+    }
+
+    #[test]
+    fn drill_repeats_the_piece_until_it_is_placed_right() {
+        let mut drill = Drill::default();
+        let mut engine = TetrisEngine::with_rules(drill.rules());
+        engine.set_piece(T);
+        drill.setup(&mut engine);
+        let target = drill.target.unwrap();
+        assert_ne!(drill.overlay(), [0; 20]);
+
+        let placed = lock(T, target.shape, target.x);
+        drill.on_lock(&mut engine, &placed, &Verdict::Unchecked);
+        assert_eq!(engine.get_state().active_piece, T);
+        assert_eq!(drill.target, Some(target));
+        assert_eq!((drill.streak, drill.misses), (0, 1));
+
+        drill.on_lock(&mut engine, &placed, &Verdict::Perfect);
+        assert_eq!((drill.streak, drill.misses), (1, 1));
+    }
+}
@@ -0,0 +1,554 @@
+use std::io::{self, ErrorKind};
+
+use crate::pieces::Shape;
+use crate::tetris_engine::{
+    GameState, Lock, Setup, piece_by_name, piece_cells, piece_name, standard_playfield,
+};
+
+// The fumen data (https://harddrop.com/fumen/) of the version 1.15, the one used today.
+// The numbers are written with 64 characters, the least significant digit first.
+const VERSION: &str = "115@";
+const DIGITS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+// The editor breaks the long data with `?` after 42 and then every 47 characters
+const FIRST_CHUNK: usize = 42;
+const CHUNK: usize = 47;
+
+// The fumen field is 23 rows high with the garbage row under it,
+// the rows are stored from the top one
+const WIDTH: usize = 10;
+const BLOCKS: usize = 24 * WIDTH;
+// The rows above the 20 rows of the playfield
+const HIDDEN_ROWS: usize = 3;
+const GARBAGE_ROW: usize = 23;
+// The changes of the cells are stored shifted by 8, so 8 is no change
+const NO_CHANGE: usize = 8;
+const GRAY: u8 = 8;
+// The pieces by their numbers, 0 is no piece
+const PIECES: [char; 8] = [' ', 'I', 'L', 'O', 'Z', 'T', 'J', 'S'];
+// The numbers of the rotations
+const REVERSE: usize = 0;
+const RIGHT: usize = 1;
+const SPAWN: usize = 2;
+const LEFT: usize = 3;
+// The flags of the page: the garbage row rises, the field is mirrored,
+// the guideline colours, the page has a comment, the piece isn't locked
+const RISE: usize = 1;
+const MIRROR: usize = 2;
+const COMMENT: usize = 8;
+const NO_LOCK: usize = 16;
+const COLORIZE: usize = 4;
+
+// The colours of the cells, 0 is empty, 8 is gray
+type Field = [u8; BLOCKS];
+
+// The piece placed on the page
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Operation {
+    // The piece as it spawns
+    pub piece: Shape,
+    pub cells: [u16; 20],
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Page {
+    pub playfield: [u16; 20],
+    pub operation: Option<Operation>,
+}
+
+impl Page {
+    // The board with the current piece in the air, if it's the standard size
+    pub fn from_state(state: &GameState) -> Option<Self> {
+        let playfield = standard_playfield(&state.playfield, state.width)?;
+        let operation = piece_name(state.active_piece)
+            .and_then(piece_by_name)
+            .map(|piece| Operation {
+                piece,
+                cells: piece_cells(state.active_piece, state.piece_position),
+            });
+        Some(Self {
+            playfield,
+            operation,
+        })
+    }
+
+    // The board before the piece was locked, with the locked piece
+    pub fn from_lock(playfield: [u16; 20], lock: &Lock) -> Self {
+        Self {
+            playfield,
+            operation: Some(Operation {
+                piece: lock.piece,
+                cells: piece_cells(lock.shape, lock.position),
+            }),
+        }
+    }
+}
+
+// The board of the first page and the pieces of all the pages
+pub fn setup(pages: &[Page]) -> Setup {
+    Setup {
+        playfield: pages
+            .first()
+            .map_or(vec![0; 20], |page| page.playfield.to_vec()),
+        queue: pages
+            .iter()
+            .filter_map(|page| page.operation.map(|operation| operation.piece))
+            .collect(),
+        ..Setup::default()
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+// The cells of the piece around its centre, `y` goes up
+fn offsets(name: char, rotation: usize) -> [(isize, isize); 4] {
+    let spawn = match name {
+        'I' => [(0, 0), (-1, 0), (1, 0), (2, 0)],
+        'T' => [(0, 0), (-1, 0), (1, 0), (0, 1)],
+        'O' => [(0, 0), (1, 0), (0, 1), (1, 1)],
+        'L' => [(0, 0), (-1, 0), (1, 0), (1, 1)],
+        'J' => [(0, 0), (-1, 0), (1, 0), (-1, 1)],
+        'S' => [(0, 0), (-1, 0), (0, 1), (1, 1)],
+        _ => [(0, 0), (1, 0), (0, 1), (-1, 1)],
+    };
+    spawn.map(|(x, y)| match rotation {
+        RIGHT => (y, -x),
+        REVERSE => (-x, -y),
+        LEFT => (-y, x),
+        _ => (x, y),
+    })
+}
+
+// The data stores some pieces around another centre than the one of the offsets,
+// the centre is the stored cell moved by the shift
+fn stored_shift(name: char, rotation: usize) -> (isize, isize) {
+    match (name, rotation) {
+        ('O', LEFT) => (1, -1),
+        ('O', REVERSE) | ('I', REVERSE) => (1, 0),
+        ('O', SPAWN) | ('I', LEFT) | ('S', SPAWN) | ('Z', SPAWN) => (0, -1),
+        ('S', RIGHT) => (-1, 0),
+        ('Z', LEFT) => (1, 0),
+        _ => (0, 0),
+    }
+}
+
+// The index of the cell in the field, `y` is counted from the floor
+fn index(x: isize, y: isize) -> Option<usize> {
+    let row = GARBAGE_ROW as isize - 1 - y;
+    ((0..WIDTH as isize).contains(&x) && (0..=GARBAGE_ROW as isize).contains(&row))
+        .then(|| row as usize * WIDTH + x as usize)
+}
+
+fn to_playfield(field: &Field) -> io::Result<[u16; 20]> {
+    if field[..HIDDEN_ROWS * WIDTH].iter().any(|&cell| cell != 0) {
+        return Err(invalid_data("The board is higher than 20 rows"));
+    }
+    let mut playfield = [0; 20];
+    for (y, row) in playfield.iter_mut().enumerate() {
+        let start = (HIDDEN_ROWS + y) * WIDTH;
+        for (x, &cell) in field[start..start + WIDTH].iter().enumerate() {
+            if cell != 0 {
+                *row |= 1 << (9 - x);
+            }
+        }
+    }
+    Ok(playfield)
+}
+
+// The cells keep their colours when they are filled on both, the new ones are gray
+fn to_field(playfield: &[u16; 20], previous: &Field) -> Field {
+    let mut field = [0; BLOCKS];
+    for (y, row) in playfield.iter().enumerate() {
+        for x in 0..WIDTH {
+            if row >> (9 - x) & 1 == 1 {
+                let i = (HIDDEN_ROWS + y) * WIDTH + x;
+                field[i] = if previous[i] != 0 { previous[i] } else { GRAY };
+            }
+        }
+    }
+    field
+}
+
+// Removes the filled rows above the garbage row
+fn clear_lines(field: &mut Field) {
+    let rows: Vec<[u8; WIDTH]> = field[..GARBAGE_ROW * WIDTH]
+        .chunks(WIDTH)
+        .filter(|row| row.contains(&0))
+        .map(|row| row.try_into().unwrap())
+        .collect();
+    let start = (GARBAGE_ROW - rows.len()) * WIDTH;
+    field[..start].fill(0);
+    for (i, row) in rows.iter().enumerate() {
+        field[start + i * WIDTH..start + (i + 1) * WIDTH].copy_from_slice(row);
+    }
+}
+
+// The garbage row is pushed into the field from below
+fn rise(field: &mut Field) {
+    field.copy_within(WIDTH.., 0);
+    field[GARBAGE_ROW * WIDTH..].fill(0);
+}
+
+fn mirror(field: &mut Field) {
+    for row in field[..GARBAGE_ROW * WIDTH].chunks_mut(WIDTH) {
+        row.reverse();
+    }
+}
+
+struct Reader {
+    digits: Vec<usize>,
+    position: usize,
+}
+
+impl Reader {
+    fn is_empty(&self) -> bool {
+        self.position >= self.digits.len()
+    }
+
+    fn poll(&mut self, len: usize) -> io::Result<usize> {
+        let Some(digits) = self.digits.get(self.position..self.position + len) else {
+            return Err(invalid_data("The fumen is cut short"));
+        };
+        self.position += len;
+        Ok(digits
+            .iter()
+            .rev()
+            .fold(0, |value, digit| value * 64 + digit))
+    }
+
+    fn skip(&mut self, len: usize) -> io::Result<()> {
+        if self.position + len > self.digits.len() {
+            return Err(invalid_data("The fumen is cut short"));
+        }
+        self.position += len;
+        Ok(())
+    }
+}
+
+// The fields are stored as the runs of the same changes from the previous page
+fn read_field(reader: &mut Reader, previous: &Field) -> io::Result<(Field, bool)> {
+    let mut field = *previous;
+    let mut changed = false;
+    let mut i = 0;
+    while i < BLOCKS {
+        let run = reader.poll(2)?;
+        let (change, len) = (run / BLOCKS, run % BLOCKS + 1);
+        if i + len > BLOCKS {
+            return Err(invalid_data("The field of the fumen is too big"));
+        }
+        changed |= change != NO_CHANGE;
+        for cell in &mut field[i..i + len] {
+            let color = *cell as usize + change;
+            if !(NO_CHANGE..=NO_CHANGE + GRAY as usize).contains(&color) {
+                return Err(invalid_data("Unknown cell in the fumen"));
+            }
+            *cell = (color - NO_CHANGE) as u8;
+        }
+        i += len;
+    }
+    Ok((field, changed))
+}
+
+fn write_field(digits: &mut Vec<usize>, field: &Field, previous: &Field) -> bool {
+    let changes: Vec<usize> = field
+        .iter()
+        .zip(previous)
+        .map(|(&cell, &old)| cell as usize + NO_CHANGE - old as usize)
+        .collect();
+    let mut start = 0;
+    for end in 1..=BLOCKS {
+        if end == BLOCKS || changes[end] != changes[start] {
+            write(digits, changes[start] * BLOCKS + end - start - 1, 2);
+            start = end;
+        }
+    }
+    changes.iter().any(|&change| change != NO_CHANGE)
+}
+
+fn write(digits: &mut Vec<usize>, mut value: usize, len: usize) {
+    for _ in 0..len {
+        digits.push(value % 64);
+        value /= 64;
+    }
+}
+
+// The pages of the fumen, the data can be preceded by the URL of the editor
+pub fn decode(data: &str) -> io::Result<Vec<Page>> {
+    let data = data.trim();
+    let data = match data.find(VERSION) {
+        Some(start) if start > 0 && b"vmd".contains(&data.as_bytes()[start - 1]) => {
+            &data[start + VERSION.len()..]
+        }
+        _ => return Err(invalid_data("Only the fumen v115 data is supported")),
+    };
+    let digits = data
+        .bytes()
+        .filter(|&c| c != b'?')
+        .map(|c| DIGITS.iter().position(|&digit| digit == c))
+        .collect::<Option<Vec<usize>>>()
+        .ok_or_else(|| invalid_data("The fumen has an unknown character"))?;
+    let mut reader = Reader {
+        digits,
+        position: 0,
+    };
+
+    let mut pages = Vec::new();
+    let mut previous = [0; BLOCKS];
+    // The pages which repeat the field of the previous page
+    let mut repeats = 0;
+    while !reader.is_empty() {
+        let field = if repeats > 0 {
+            repeats -= 1;
+            previous
+        } else {
+            let (field, changed) = read_field(&mut reader, &previous)?;
+            if !changed {
+                repeats = reader.poll(1)?;
+            }
+            field
+        };
+
+        let action = reader.poll(3)?;
+        let kind = action % 8;
+        let rotation = action / 8 % 4;
+        let coordinate = action / 32 % BLOCKS;
+        let flags = action / 32 / BLOCKS;
+        if flags & COMMENT != 0 {
+            // Every 4 characters of the comment take 5 digits
+            let len = reader.poll(2)?;
+            reader.skip(len.div_ceil(4) * 5)?;
+        }
+
+        let mut cells = Vec::new();
+        if (1..PIECES.len()).contains(&kind) {
+            let name = PIECES[kind];
+            let (dx, dy) = stored_shift(name, rotation);
+            let x = (coordinate % WIDTH) as isize + dx;
+            let y = GARBAGE_ROW as isize - 1 - (coordinate / WIDTH) as isize + dy;
+            for (ox, oy) in offsets(name, rotation) {
+                let i = index(x + ox, y + oy)
+                    .filter(|&i| i < GARBAGE_ROW * WIDTH)
+                    .ok_or_else(|| invalid_data("The piece is outside of the field"))?;
+                cells.push(i);
+            }
+        }
+        let operation = if cells.is_empty() {
+            None
+        } else {
+            let mut piece_field = [0; BLOCKS];
+            cells.iter().for_each(|&i| piece_field[i] = GRAY);
+            Some(Operation {
+                piece: piece_by_name(PIECES[kind]).unwrap_or(0),
+                cells: to_playfield(&piece_field)?,
+            })
+        };
+        pages.push(Page {
+            playfield: to_playfield(&field)?,
+            operation,
+        });
+
+        previous = field;
+        if flags & NO_LOCK == 0 {
+            cells.iter().for_each(|&i| previous[i] = kind as u8);
+            clear_lines(&mut previous);
+            if flags & RISE != 0 {
+                rise(&mut previous);
+            }
+            if flags & MIRROR != 0 {
+                mirror(&mut previous);
+            }
+        }
+    }
+    if pages.is_empty() {
+        return Err(invalid_data("The fumen has no pages"));
+    }
+    Ok(pages)
+}
+
+// The number of the piece, its rotation and its cells in the field
+fn find_operation(operation: &Operation) -> Option<(usize, usize, Vec<usize>)> {
+    let name = piece_name(operation.piece)?;
+    let kind = PIECES.iter().position(|&piece| piece == name)?;
+    let mut cells = Vec::new();
+    for (y, row) in operation.cells.iter().enumerate() {
+        for x in 0..WIDTH {
+            if row >> (9 - x) & 1 == 1 {
+                cells.push((x as isize, (19 - y) as isize));
+            }
+        }
+    }
+    let &(x0, y0) = cells.first()?;
+    for rotation in [SPAWN, RIGHT, REVERSE, LEFT] {
+        let offsets = offsets(name, rotation);
+        for (ox, oy) in offsets {
+            let (x, y) = (x0 - ox, y0 - oy);
+            let placed: Vec<(isize, isize)> =
+                offsets.iter().map(|(ox, oy)| (x + ox, y + oy)).collect();
+            if cells.len() == 4 && cells.iter().all(|cell| placed.contains(cell)) {
+                let (dx, dy) = stored_shift(name, rotation);
+                let coordinate = index(x - dx, y - dy)?;
+                let cells = placed.iter().filter_map(|&(x, y)| index(x, y)).collect();
+                return Some((kind, rotation * 8 + kind + coordinate * 32, cells));
+            }
+        }
+    }
+    None
+}
+
+pub fn encode(pages: &[Page]) -> String {
+    let mut digits = Vec::new();
+    let mut previous = [0; BLOCKS];
+    // The digit which counts the pages with the same field
+    let mut repeats: Option<usize> = None;
+    for page in pages {
+        let field = to_field(&page.playfield, &previous);
+        let mut field_digits = Vec::new();
+        if write_field(&mut field_digits, &field, &previous) {
+            digits.extend(field_digits);
+            repeats = None;
+        } else {
+            match repeats {
+                Some(i) if digits[i] < 63 => digits[i] += 1,
+                _ => {
+                    digits.extend(field_digits);
+                    digits.push(0);
+                    repeats = Some(digits.len() - 1);
+                }
+            }
+        }
+
+        let operation = page.operation.as_ref().and_then(find_operation);
+        let (kind, action, cells) = operation.unwrap_or((0, 0, Vec::new()));
+        write(&mut digits, COLORIZE * BLOCKS * 32 + action, 3);
+
+        previous = field;
+        cells.iter().for_each(|&i| previous[i] = kind as u8);
+        clear_lines(&mut previous);
+    }
+
+    let data: Vec<char> = digits.iter().map(|&digit| DIGITS[digit] as char).collect();
+    let mut text = format!("v{}", VERSION);
+    for (i, c) in data.iter().enumerate() {
+        if i >= FIRST_CHUNK && (i - FIRST_CHUNK).is_multiple_of(CHUNK) {
+            text.push('?');
+        }
+        text.push(*c);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const T: Shape = 0b_00000_11100_01000;
+
+    #[test]
+    fn empty_board_is_the_shortest_fumen() {
+        let pages = vec![Page::default()];
+        assert_eq!(encode(&pages), "v115@vhAAgH");
+        assert_eq!(
+            decode("https://harddrop.com/fumen/?v115@vhAAgH").unwrap(),
+            pages
+        );
+        assert!(decode("v110@vhAAgH").is_err());
+        assert!(decode("v115@vh").is_err());
+    }
+
+    #[test]
+    fn editor_data_is_decoded() {
+        // 4 rows of 6 gray cells at the bottom
+        let pages = decode("v115@9gF8DeF8DeF8DeF8NeAgH").unwrap();
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].playfield[..16], [0; 16]);
+        assert_eq!(pages[0].playfield[16..], [0b1111110000; 4]);
+        assert_eq!(pages[0].operation, None);
+    }
+
+    #[test]
+    fn boards_and_pieces_survive_the_round_trip() {
+        //    0123456789
+        // 17 ░░░░░░░░░░
+        // 18 ███▒▒▒████
+        // 19 ████▒█████
+        let mut playfield = [0; 20];
+        playfield[18] = 0b1110001111;
+        playfield[19] = 0b1111011111;
+        let mut cells = [0; 20];
+        cells[18] = 0b0001110000;
+        cells[19] = 0b0000100000;
+        let lock = Page {
+            playfield,
+            operation: Some(Operation { piece: T, cells }),
+        };
+        // The T-spin double clears the board, the next page is empty
+        let pages = vec![lock, Page::default(), Page::default()];
+        let data = encode(&pages);
+        assert_eq!(decode(&data).unwrap(), pages);
+
+        let setup = setup(&pages);
+        assert_eq!(setup.playfield, playfield);
+        assert_eq!(setup.queue, vec![T]);
+    }
+
+    #[test]
+    fn long_data_is_broken_with_question_marks() {
+        let pages: Vec<Page> = (0..20)
+            .map(|y| {
+                let mut playfield = [0; 20];
+                playfield[y] = 1;
+                Page {
+                    playfield,
+                    operation: None,
+                }
+            })
+            .collect();
+        let data = encode(&pages);
+        assert_eq!(data.as_bytes()[5 + FIRST_CHUNK], b'?');
+        assert_eq!(decode(&data).unwrap(), pages);
+    }
+
+    #[test]
+    fn pieces_are_read_in_every_rotation() {
+        // The I piece standing in the left column
+        let data = encode(&[Page {
+            playfield: [0; 20],
+            operation: Some(Operation {
+                piece: piece_by_name('I').unwrap(),
+                cells: piece_cells(0b_10000_10000_10000_10000, [0, 16]),
+            }),
+        }]);
+        let page = &decode(&data).unwrap()[0];
+        assert_eq!(
+            page.operation.unwrap().cells,
+            piece_cells(0b_10000_10000_10000_10000, [0, 16])
+        );
+    }
+
+    #[test]
+    fn pieces_on_the_floor_are_stored_above_their_centre() {
+        // The O piece in the bottom left corner is stored at its top left cell
+        let o = Operation {
+            piece: piece_by_name('O').unwrap(),
+            cells: piece_cells(0b_11000_11000, [0, 18]),
+        };
+        let (_, action, _) = find_operation(&o).unwrap();
+        assert_eq!(action / 32, (GARBAGE_ROW - 2) * WIDTH);
+
+        let pages: Vec<Page> = ['O', 'S', 'Z']
+            .into_iter()
+            .map(|name| {
+                let piece = piece_by_name(name).unwrap();
+                Page {
+                    playfield: [0; 20],
+                    operation: Some(Operation {
+                        piece,
+                        cells: piece_cells(piece, [0, 18]),
+                    }),
+                }
+            })
+            .collect();
+        assert_eq!(decode(&encode(&pages)).unwrap(), pages);
+    }
+}
@@ -0,0 +1,586 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use crate::config::{Action, Config, KeyBindings};
+use crate::finesse::{Finesse, Verdict};
+use crate::fumen::{self, Operation, Page};
+use crate::input::InputHandler;
+use crate::modes::{Mode, Ruleset};
+use crate::modifiers::Modifiers;
+use crate::perfect_clear::{self, Pieces};
+use crate::pieces;
+use crate::stats::Stats;
+use crate::tetris_engine::{
+    FRAMES_PER_SECOND, GameState, Lock, TetrisEngine, UNDO_LIMIT, piece_by_name, piece_cells,
+    piece_name, standard_playfield,
+};
+
+pub const FRAME: Duration = Duration::from_nanos(1_000_000_000 / FRAMES_PER_SECOND as u64);
+// The hint looks for the perfect clears which take up to that many lines,
+// the longer ones could take too long to find
+const HINT_LINES: usize = 4;
+// If the app falls behind more than that (e.g. the process was suspended),
+// the missed frames are dropped instead of being simulated all at once
+const MAX_CATCH_UP_FRAMES: u32 = 10;
+
+// A key event which is applied on the frame when it happened
+#[derive(Debug)]
+enum TimedInput {
+    Press(u64, KeyCode, Action),
+    Release(u64, KeyCode),
+}
+
+// What the game screen keeps track of after a placement, the undo goes back to it
+// together with the engine
+#[derive(Debug, Clone)]
+struct Progress {
+    stats: Stats,
+    finesse: Finesse,
+    pages: usize,
+}
+
+impl TimedInput {
+    fn frame(&self) -> u64 {
+        match self {
+            TimedInput::Press(frame, _, _) | TimedInput::Release(frame, _) => *frame,
+        }
+    }
+}
+
+// What the game asks the app to do after a key press
+#[derive(Debug, PartialEq)]
+pub enum GameRequest {
+    Pause,
+    Quit,
+    KeyBindings,
+}
+
+// The actions which change the game state are applied by the simulation on the exact frame,
+// the rest of them are performed as soon as the key is pressed
+fn is_gameplay(action: &Action) -> bool {
+    !matches!(
+        action,
+        Action::Pause
+            | Action::Quit
+            | Action::Restart
+            | Action::KeyBindings
+            | Action::Hint
+            | Action::Undo
+            | Action::Redo
+    )
+}
+
+// The in-game screen: the engine together with the fixed-timestep simulation around it.
+#[derive(Debug)]
+pub struct GameScreen {
+    pub engine: TetrisEngine,
+    pub mode: Mode,
+    ruleset: Box<dyn Ruleset>,
+    input: InputHandler,
+    // Whether the terminal reports key releases
+    key_releases: bool,
+    pending_input: VecDeque<TimedInput>,
+    // The next frame to simulate and the time when it starts
+    frame: u64,
+    frame_start: Instant,
+    // The side panel is redrawn when its text changes, e.g. the clock is ticking
+    last_hud: Vec<String>,
+    pub stats: Stats,
+    finesse: Finesse,
+    // The board before the next lock and the pages of the locks so far, for the fumen
+    // of the standard playfield
+    board: Vec<u16>,
+    pages: Vec<Page>,
+    // The perfect clear shown by the hint, the placements which aren't done yet
+    hint: Vec<Operation>,
+    // The hint has found no perfect clear, it's shown until the next lock
+    no_hint: bool,
+    modifiers: Modifiers,
+    // The big mode of the settings is left out of this game
+    big_off: bool,
+    // The cells hidden by the modifiers, the board is redrawn when they change
+    hidden: Vec<u16>,
+    // The progress after every placement which can be taken back, the last one is the current
+    // one, and the progress of the placements taken back with their pages
+    history: VecDeque<Progress>,
+    redo: Vec<(Progress, Vec<Page>)>,
+    // The locks which the online match hasn't sent to the opponent yet,
+    // `None` when they aren't recorded
+    recorded: Option<Vec<Lock>>,
+}
+
+impl GameScreen {
+    pub fn new(config: &Config, key_releases: bool, mode: Mode) -> Self {
+        Self::with_ruleset(config, key_releases, mode, mode.ruleset())
+    }
+
+    // The mode with the ruleset which needs more than the mode options, e.g. the custom board
+    pub fn with_ruleset(
+        config: &Config,
+        key_releases: bool,
+        mode: Mode,
+        mut ruleset: Box<dyn Ruleset>,
+    ) -> Self {
+        // The big mode needs a board of its own, so it's left out where the board is given
+        let custom = mode.uses_game_settings();
+        let modifiers = Modifiers {
+            big: custom && config.modifiers.big,
+            ..config.modifiers
+        };
+        let mut rules = modifiers.rules(ruleset.rules());
+        rules.cascade |= custom && config.cascade;
+        let mut engine = TetrisEngine::with_rules(rules);
+        if custom {
+            engine.set_pieces(pieces::load(&config.pieces));
+        }
+        ruleset.setup(&mut engine);
+        let mut game = Self {
+            board: engine.get_state().playfield,
+            engine,
+            mode,
+            ruleset,
+            input: InputHandler::new(config.handling, key_releases),
+            key_releases,
+            pending_input: VecDeque::new(),
+            frame: 0,
+            frame_start: Instant::now(),
+            last_hud: Vec::new(),
+            stats: Stats::default(),
+            finesse: Finesse::default(),
+            pages: Vec::new(),
+            hint: Vec::new(),
+            no_hint: false,
+            modifiers,
+            big_off: config.modifiers.big && !modifiers.big,
+            hidden: Vec::new(),
+            history: VecDeque::new(),
+            redo: Vec::new(),
+            recorded: None,
+        };
+        game.remember_progress();
+        game
+    }
+
+    // The time when the current frame is over
+    pub fn frame_end(&self) -> Instant {
+        self.frame_start + FRAME
+    }
+
+    // The frame which is going on at the given moment
+    fn frame_at(&self, time: Instant) -> u64 {
+        let elapsed = time.saturating_duration_since(self.frame_start);
+        self.frame + (elapsed.as_nanos() / FRAME.as_nanos()) as u64
+    }
+
+    pub fn restart(&mut self) {
+        self.engine.restart();
+        self.ruleset.reset();
+        self.ruleset.setup(&mut self.engine);
+        self.stats = Stats::default();
+        self.finesse = Finesse::default();
+        self.board = self.engine.get_state().playfield;
+        self.pages.clear();
+        self.hint.clear();
+        self.no_hint = false;
+        self.clear_input();
+        self.history.clear();
+        self.redo.clear();
+        self.remember_progress();
+    }
+
+    // The title and the summary of the game which reached the goal of the mode
+    pub fn finish(&mut self) -> (String, Vec<String>) {
+        let standard = self.is_standard();
+        self.ruleset.finish(&self.engine, standard)
+    }
+
+    // The lines shown in the side panel: the mode's information, the statistics,
+    // the finesse fault of the last piece and the hint
+    pub fn hud(&self) -> Vec<String> {
+        let mut hud = self.ruleset.hud(&self.engine, self.frame);
+        hud.push(String::new());
+        hud.extend(self.stats.hud(self.engine.frames()));
+        let finesse = self.finesse.hud();
+        if !finesse.is_empty() {
+            hud.push(String::new());
+            hud.extend(finesse);
+        }
+        if self.big_off {
+            hud.extend([
+                String::new(),
+                String::from("Big mode is off"),
+                String::from("in this mode"),
+            ]);
+        }
+        if self.no_hint {
+            hud.extend([String::new(), String::from("No perfect clear")]);
+        } else if let Some(step) = self.hint.first() {
+            hud.push(String::new());
+            hud.push(String::from("Perfect clear"));
+            if let Some(name) = piece_name(step.piece) {
+                hud.push(format!("Place the {}", name));
+            }
+            hud.push(format!("{} pieces to go", self.hint.len()));
+        }
+        hud
+    }
+
+    // The board with the outline of the mode and the next placement of the hint
+    pub fn state(&self) -> GameState {
+        let mut overlay = self.ruleset.overlay();
+        if let Some(step) = self.hint.first() {
+            overlay.resize(self.engine.height(), 0);
+            for (row, cells) in overlay.iter_mut().zip(step.cells) {
+                *row |= cells;
+            }
+        }
+        let state = GameState {
+            overlay,
+            ..self.engine.get_state()
+        };
+        self.modifiers
+            .apply(state, &self.modifiers.hidden(&self.engine))
+    }
+
+    // The standard pieces, gravity and board, the hint and the records are only for them
+    pub fn is_standard(&self) -> bool {
+        self.engine.pieces().is_standard() && !self.engine.rules().cascade && !self.modifiers.big
+    }
+
+    // Looks for the perfect clear with the pieces the player can see.
+    // The rules of the modes with the records don't allow it.
+    fn show_hint(&mut self) {
+        if !self.engine.rules().hint {
+            return;
+        }
+        // The solver knows only the standard game
+        if !self.is_standard() {
+            self.no_hint = true;
+            return;
+        }
+        let Some(board) = standard_playfield(&self.board, self.engine.width()) else {
+            self.no_hint = true;
+            return;
+        };
+        let state = self.engine.get_state();
+        let Some(active) = piece_name(state.active_piece).and_then(piece_by_name) else {
+            return;
+        };
+        let pieces = Pieces {
+            active,
+            hold: state.hold_piece,
+            // The pieces which come next aren't shown
+            queue: Vec::new(),
+            can_hold: self.engine.rules().hold,
+        };
+        self.hint = perfect_clear::find(&board, &pieces, HINT_LINES).unwrap_or_default();
+        self.no_hint = self.hint.is_empty();
+        self.engine.changed = true;
+    }
+
+    // Takes back the last placement, or makes the one taken back again.
+    // The rules of the competitive modes don't allow it.
+    fn undo(&mut self, redo: bool) {
+        let done = if redo {
+            self.engine.redo()
+        } else {
+            self.engine.undo()
+        };
+        if !done {
+            return;
+        }
+        if redo {
+            if let Some((progress, pages)) = self.redo.pop() {
+                self.pages.extend(pages);
+                self.history.push_back(progress);
+            }
+        } else if let Some(current) = self.history.pop_back() {
+            let pages = self.history.back().map_or(0, |previous| previous.pages);
+            let pages = self.pages.split_off(pages.min(self.pages.len()));
+            self.redo.push((current, pages));
+        }
+        if let Some(progress) = self.history.back() {
+            self.stats = progress.stats.clone();
+            self.finesse = progress.finesse.clone();
+        }
+        self.ruleset.on_undo(redo);
+        self.board = self.engine.get_state().playfield;
+        self.hint.clear();
+        self.no_hint = false;
+        self.clear_input();
+    }
+
+    // The big mode of the settings is left out, e.g. by the online match
+    pub fn leave_out_big(&mut self) {
+        self.big_off = true;
+    }
+
+    // The locks are kept from now on until they're taken
+    pub fn record_locks(&mut self) {
+        self.recorded = Some(Vec::new());
+    }
+
+    pub fn take_recorded_locks(&mut self) -> Vec<Lock> {
+        self.recorded
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    // Every lock of the game on its own page
+    pub fn fumen(&self) -> String {
+        fumen::encode(&self.pages)
+    }
+
+    pub fn stats_summary(&self) -> Vec<String> {
+        self.stats.summary(self.engine.frames())
+    }
+
+    pub fn pause(&mut self) {
+        self.engine.set_paused(true);
+        self.clear_input();
+    }
+
+    // Continues the game after the other screens, the settings could be changed meanwhile
+    pub fn resume(&mut self, config: &Config) {
+        self.input = InputHandler::new(config.handling, self.key_releases);
+        self.pending_input.clear();
+        self.frame_start = Instant::now();
+        self.engine.set_paused(false);
+    }
+
+    fn clear_input(&mut self) {
+        self.input.clear();
+        self.pending_input.clear();
+    }
+
+    pub fn handle_key(&mut self, key: &KeyEvent, keys: &KeyBindings) -> Option<GameRequest> {
+        let frame = self.frame_at(Instant::now());
+        if key.kind == KeyEventKind::Release {
+            // The modifiers could be released first, so only the key code is matched
+            self.pending_input
+                .push_back(TimedInput::Release(frame, key.code));
+            return None;
+        }
+        // The terminal's own auto-repeat is ignored, the held keys are
+        // repeated by the input handler according to the handling settings
+        if key.kind != KeyEventKind::Press {
+            return None;
+        }
+
+        match keys.action_for(key)? {
+            Action::Pause => Some(GameRequest::Pause),
+            Action::Quit => Some(GameRequest::Quit),
+            Action::KeyBindings => Some(GameRequest::KeyBindings),
+            Action::Restart => {
+                self.restart();
+                None
+            }
+            Action::Hint => {
+                self.show_hint();
+                None
+            }
+            action @ (Action::Undo | Action::Redo) => {
+                self.undo(action == Action::Redo);
+                None
+            }
+            action if is_gameplay(&action) => {
+                self.pending_input
+                    .push_back(TimedInput::Press(frame, key.code, action));
+                None
+            }
+            _ => None,
+        }
+    }
+
+    // Simulates the frames which are over by the given time
+    pub fn advance(&mut self, now: Instant) {
+        let mut simulated = 0;
+        while self.frame_start + FRAME <= now {
+            self.simulate_frame();
+            self.frame_start += FRAME;
+            simulated += 1;
+            if simulated == MAX_CATCH_UP_FRAMES {
+                self.frame_start = now;
+            }
+        }
+
+        let hidden = self.modifiers.hidden(&self.engine);
+        if hidden != self.hidden {
+            self.hidden = hidden;
+            self.engine.changed = true;
+        }
+
+        let hud = self.hud();
+        if hud != self.last_hud {
+            self.last_hud = hud;
+            self.engine.changed = true;
+        }
+    }
+
+    fn perform(&mut self, action: Action) {
+        let engine = &mut self.engine;
+        match action {
+            Action::MoveLeft => engine.move_current_shape(-1, 0),
+            Action::MoveRight => engine.move_current_shape(1, 0),
+            Action::SoftDrop => engine.move_current_shape(0, 1),
+            Action::HardDrop => engine.hard_drop(),
+            Action::RotateCw => engine.rotate(),
+            Action::RotateCcw => engine.rotate_ccw(),
+            Action::Rotate180 => engine.rotate_180(),
+            Action::Hold => engine.hold(),
+            _ => {}
+        }
+
+        if matches!(
+            action,
+            Action::HardDrop
+                | Action::RotateCw
+                | Action::RotateCcw
+                | Action::Rotate180
+                | Action::Hold
+        ) {
+            self.input.cut_das(self.frame);
+        }
+        self.take_locks();
+    }
+
+    // The hard drop is judged before the next key press is counted for the next piece
+    fn take_locks(&mut self) {
+        let checked =
+            self.is_standard() && standard_playfield(&self.board, self.engine.width()).is_some();
+        for lock in self.engine.take_locks() {
+            if let Some(board) = standard_playfield(&self.board, self.engine.width()) {
+                self.pages.push(Page::from_lock(board, &lock));
+            }
+            self.stats.on_lock(&lock);
+            if let Some(recorded) = &mut self.recorded {
+                recorded.push(lock);
+            }
+            let verdict = self.finesse.on_lock(&lock, checked);
+            if let Verdict::Fault { .. } = verdict {
+                self.stats.on_finesse_fault();
+            }
+            self.ruleset.on_lock(&mut self.engine, &lock, &verdict);
+            // The hint stays while it's followed
+            let placed = piece_cells(lock.shape, lock.position);
+            if self.hint.first().is_some_and(|step| step.cells == placed) {
+                self.hint.remove(0);
+            } else {
+                self.hint.clear();
+            }
+            self.no_hint = false;
+            self.remember_progress();
+        }
+        // The garbage could be added after the lock
+        self.board = self.engine.get_state().playfield;
+    }
+
+    // The placements are taken back only where the engine keeps its history
+    fn remember_progress(&mut self) {
+        if !self.engine.rules().undo {
+            return;
+        }
+        // The new placement replaces the ones taken back
+        self.redo.clear();
+        self.history.push_back(Progress {
+            stats: self.stats.clone(),
+            finesse: self.finesse.clone(),
+            pages: self.pages.len(),
+        });
+        if self.history.len() > UNDO_LIMIT + 1 {
+            self.history.pop_front();
+        }
+    }
+
+    // Applies the input which happened during the current frame and advances the engine
+    fn simulate_frame(&mut self) {
+        while self
+            .pending_input
+            .front()
+            .is_some_and(|input| input.frame() <= self.frame)
+        {
+            match self.pending_input.pop_front() {
+                Some(TimedInput::Press(_, code, action)) => {
+                    self.ruleset.on_input(self.frame);
+                    // The repeats of the held keys aren't counted
+                    if self.input.press(code, action, self.frame) {
+                        self.stats.on_action(&action);
+                        self.finesse.on_action(&action);
+                        self.perform(action);
+                    }
+                }
+                Some(TimedInput::Release(_, code)) => self.input.release(code, self.frame),
+                _ => {}
+            }
+        }
+
+        self.input.set_gravity(self.engine.gravity_frames());
+        for action in self.input.tick(self.frame) {
+            self.perform(action);
+        }
+        // The clock and the gravity of the timed modes wait for the first input
+        if !self.ruleset.is_waiting() {
+            self.engine.update();
+        }
+        self.frame += 1;
+        self.ruleset.update(&mut self.engine, self.frame);
+        self.take_locks();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undo_takes_back_the_pages_and_the_stats() {
+        let mut game = GameScreen::new(&Config::default(), true, Mode::Practice);
+        game.perform(Action::HardDrop);
+        let (fumen, summary) = (game.fumen(), game.stats_summary());
+        game.perform(Action::MoveLeft);
+        game.perform(Action::HardDrop);
+        assert_eq!(game.pages.len(), 2);
+
+        game.undo(false);
+        assert_eq!(game.fumen(), fumen);
+        assert_eq!(game.stats_summary(), summary);
+        game.undo(true);
+        assert_eq!(game.pages.len(), 2);
+        // The placement after the undo replaces the one taken back
+        game.undo(false);
+        game.perform(Action::HardDrop);
+        game.undo(true);
+        assert_eq!(game.pages.len(), 2);
+        game.undo(false);
+        game.undo(false);
+        assert!(game.pages.is_empty());
+    }
+
+    #[test]
+    fn big_mode_left_out_is_shown() {
+        let mut config = Config::default();
+        config.modifiers.big = true;
+        let big_off = |mode| {
+            let game = GameScreen::new(&config, true, mode);
+            game.hud().iter().any(|line| line == "Big mode is off")
+        };
+        assert!(big_off(Mode::Practice));
+        assert!(!big_off(Mode::Endless));
+    }
+
+    #[test]
+    fn hint_is_only_in_the_practice() {
+        let hint = |mode| {
+            let mut game = GameScreen::new(&Config::default(), true, mode);
+            game.show_hint();
+            // The piece on the screen can't clear the empty board
+            game.no_hint
+        };
+        assert!(hint(Mode::Practice));
+        assert!(!hint(Mode::Sprint(40)));
+    }
+}
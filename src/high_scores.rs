@@ -0,0 +1,425 @@
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    env, fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::config;
+use crate::modes::{Mode, format_time};
+
+// The number of the records kept for every mode
+pub const MAX_ENTRIES: usize = 10;
+pub const MAX_NAME_LEN: usize = 12;
+const FILE_NAME: &str = "scores.txt";
+// The other games wait for the lock that long before giving up
+const LOCK_TIMEOUT: Duration = Duration::from_secs(2);
+const LOCK_POLL: Duration = Duration::from_millis(20);
+// The lock of a crashed game is removed after that time
+const STALE_LOCK: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HighScore {
+    pub name: String,
+    pub score: usize,
+    pub lines: usize,
+    // Milliseconds of play
+    pub time: u64,
+    // Seconds since the Unix epoch
+    pub date: u64,
+    // The fumen replay of the game, if it was recorded
+    pub replay: Option<String>,
+}
+
+impl HighScore {
+    // The line of the table, e.g. ` 1. alice     12300  40 1:02.500 2024-03-01`
+    pub fn format(&self, rank: usize) -> String {
+        format!(
+            "{:>2}. {:<width$} {:>8} {:>4} {:>9} {}",
+            rank + 1,
+            self.name,
+            self.score,
+            self.lines,
+            format_time(self.time),
+            format_date(self.date),
+            width = MAX_NAME_LEN
+        )
+    }
+}
+
+// The column names of `HighScore::format`
+pub fn header() -> String {
+    format!(
+        "    {:<width$} {:>8} {:>4} {:>9} {}",
+        "Name",
+        "Score",
+        "Lines",
+        "Time",
+        "Date",
+        width = MAX_NAME_LEN
+    )
+}
+
+// The name offered on the name entry
+pub fn default_name() -> String {
+    let name = env::var("USER")
+        .or_else(|_| env::var("USERNAME"))
+        .unwrap_or_default();
+    clean_name(&name)
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+// `YYYY-MM-DD` of the Unix time, the days are converted to the civil calendar
+pub fn format_date(seconds: u64) -> String {
+    let days = (seconds / 86_400) as i64 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+// The names are stored in a tab-separated file, so the tabs and the line breaks are dropped
+pub fn clean_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_NAME_LEN)
+        .collect();
+    match name.trim() {
+        "" => String::from("anonymous"),
+        name => String::from(name),
+    }
+}
+
+// The records of all the modes. The file holds a line per record:
+// `<mode> <score> <lines> <time> <date> <replay or -> <name>`, separated by tabs.
+#[derive(Debug, Default)]
+pub struct HighScores {
+    tables: HashMap<String, Vec<HighScore>>,
+}
+
+impl HighScores {
+    fn path() -> Option<PathBuf> {
+        Some(config::data_dir()?.join(FILE_NAME))
+    }
+
+    // A missing file means that there are no records yet
+    pub fn load() -> io::Result<Self> {
+        match Self::path() {
+            Some(path) => Self::load_from(&path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    fn load_from(path: &Path) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => Ok(Self::parse(&text)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            // The file with a broken encoding is as good as the one with the broken lines
+            Err(e) if e.kind() == ErrorKind::InvalidData => {
+                Ok(Self::parse(&String::from_utf8_lossy(&fs::read(path)?)))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    // The broken lines are skipped, losing a record is better than refusing to play
+    fn parse(text: &str) -> Self {
+        let mut scores = Self::default();
+        for line in text.lines() {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [mode, score, lines, time, date, replay, name] = fields[..] else {
+                continue;
+            };
+            let (Ok(score), Ok(lines), Ok(time), Ok(date)) =
+                (score.parse(), lines.parse(), time.parse(), date.parse())
+            else {
+                continue;
+            };
+            let replay = (replay != "-").then(|| String::from(replay));
+            let entry = HighScore {
+                name: clean_name(name),
+                score,
+                lines,
+                time,
+                date,
+                replay,
+            };
+            scores
+                .tables
+                .entry(String::from(mode))
+                .or_default()
+                .push(entry);
+        }
+        // The hand edited or merged files aren't in the order of the records
+        for (key, table) in &mut scores.tables {
+            let by_time = Mode::from_key(key).is_some_and(|mode| mode.ranked_by_time());
+            table.sort_by(|entry, other| {
+                if is_better(entry, other, by_time) {
+                    Ordering::Less
+                } else if is_better(other, entry, by_time) {
+                    Ordering::Greater
+                } else {
+                    Ordering::Equal
+                }
+            });
+            table.truncate(MAX_ENTRIES);
+        }
+        scores
+    }
+
+    fn to_text(&self) -> String {
+        let mut modes: Vec<&String> = self.tables.keys().collect();
+        modes.sort();
+        let mut text = String::new();
+        for mode in modes {
+            for entry in &self.tables[mode] {
+                text.push_str(&format!(
+                    "{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                    mode,
+                    entry.score,
+                    entry.lines,
+                    entry.time,
+                    entry.date,
+                    entry.replay.as_deref().unwrap_or("-"),
+                    entry.name
+                ));
+            }
+        }
+        text
+    }
+
+    // The records with a replay and their ranks, by the mode key
+    pub fn replays(&self) -> Vec<(&str, usize, &HighScore)> {
+        let mut modes: Vec<&String> = self.tables.keys().collect();
+        modes.sort();
+        modes
+            .into_iter()
+            .flat_map(|mode| {
+                self.tables[mode]
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, entry)| entry.replay.is_some())
+                    .map(move |(rank, entry)| (mode.as_str(), rank, entry))
+            })
+            .collect()
+    }
+
+    pub fn table(&self, mode: &Mode) -> &[HighScore] {
+        self.tables.get(&mode.key()).map_or(&[], Vec::as_slice)
+    }
+
+    // The rank which the result would take, `None` if it's too low for the table
+    pub fn rank(&self, mode: &Mode, entry: &HighScore) -> Option<usize> {
+        let table = self.table(mode);
+        let rank = table
+            .iter()
+            .position(|other| is_better(entry, other, mode.ranked_by_time()))
+            .unwrap_or(table.len());
+        (rank < MAX_ENTRIES).then_some(rank)
+    }
+
+    fn insert(&mut self, mode: &Mode, entry: HighScore) -> Option<usize> {
+        let rank = self.rank(mode, &entry)?;
+        let table = self.tables.entry(mode.key()).or_default();
+        table.insert(rank, entry);
+        table.truncate(MAX_ENTRIES);
+        Some(rank)
+    }
+
+    // Adds the result to the file and returns the records with it and its rank.
+    // The file is read again under the lock, so the records of the other games aren't lost.
+    pub fn submit(mode: &Mode, entry: HighScore) -> io::Result<(Self, Option<usize>)> {
+        let Some(path) = Self::path() else {
+            return Err(io::Error::new(ErrorKind::NotFound, "no data directory"));
+        };
+        Self::submit_to(&path, mode, entry)
+    }
+
+    fn submit_to(path: &Path, mode: &Mode, entry: HighScore) -> io::Result<(Self, Option<usize>)> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let _lock = FileLock::acquire(&path.with_extension("lock"))?;
+        let mut scores = Self::load_from(path)?;
+        let rank = scores.insert(mode, entry);
+        if rank.is_some() {
+            // The file is replaced at once, so a crash can't leave half of it
+            let temp = path.with_extension("tmp");
+            fs::write(&temp, scores.to_text())?;
+            fs::rename(&temp, path)?;
+        }
+        Ok((scores, rank))
+    }
+}
+
+fn is_better(entry: &HighScore, other: &HighScore, by_time: bool) -> bool {
+    if by_time {
+        entry.time < other.time
+    } else {
+        entry.score > other.score
+    }
+}
+
+// Only one game at a time updates the records, the lock file is removed when it's dropped
+#[derive(Debug)]
+struct FileLock {
+    path: PathBuf,
+}
+
+impl FileLock {
+    fn acquire(path: &Path) -> io::Result<Self> {
+        let start = SystemTime::now();
+        loop {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(path)
+            {
+                Ok(_) => {
+                    return Ok(Self {
+                        path: path.to_path_buf(),
+                    });
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e),
+            }
+            let age = fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok());
+            if age.is_some_and(|age| age > STALE_LOCK) {
+                let _ = fs::remove_file(path);
+                continue;
+            }
+            if start.elapsed().is_ok_and(|waited| waited > LOCK_TIMEOUT) {
+                return Err(io::Error::new(
+                    ErrorKind::TimedOut,
+                    "the records are locked by another game",
+                ));
+            }
+            thread::sleep(LOCK_POLL);
+        }
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, score: usize, time: u64) -> HighScore {
+        HighScore {
+            name: String::from(name),
+            score,
+            lines: 40,
+            time,
+            date: 1_700_000_000,
+            replay: None,
+        }
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ratrix-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join(FILE_NAME)
+    }
+
+    #[test]
+    fn dates_are_shown_in_the_civil_calendar() {
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(951_782_400), "2000-02-29");
+        assert_eq!(format_date(1_700_000_000), "2023-11-14");
+    }
+
+    #[test]
+    fn modes_are_ranked_by_score_or_time() {
+        let mut scores = HighScores::default();
+        let sprint = Mode::Sprint(40);
+        let endless = Mode::Endless;
+        assert_eq!(scores.insert(&sprint, entry("a", 100, 60_000)), Some(0));
+        assert_eq!(scores.insert(&sprint, entry("b", 50, 50_000)), Some(0));
+        assert_eq!(scores.insert(&endless, entry("a", 100, 60_000)), Some(0));
+        assert_eq!(scores.insert(&endless, entry("b", 50, 50_000)), Some(1));
+        for i in 0..MAX_ENTRIES {
+            scores.insert(&endless, entry("c", 1000 + i, 0));
+        }
+        assert_eq!(scores.table(&endless).len(), MAX_ENTRIES);
+        assert_eq!(scores.rank(&endless, &entry("d", 1, 0)), None);
+        assert_eq!(scores.table(&Mode::Sprint(20)), &[]);
+    }
+
+    #[test]
+    fn corrupted_lines_are_skipped() {
+        let text = "sprint-40\t0\t40\t61000\t1700000000\t-\tbob\n\
+                    sprint-40\t0\t40\toops\t1700000000\t-\tbad\n\
+                    garbage\n\
+                    sprint-40\t0\t40\t59000\t1700000000\treplay-1.txt\talice\n";
+        let scores = HighScores::parse(text);
+        let table = scores.table(&Mode::Sprint(40));
+        assert_eq!(table.len(), 2);
+        assert_eq!(table[0].name, "alice");
+        assert_eq!(table[0].replay.as_deref(), Some("replay-1.txt"));
+        assert_eq!(scores.replays(), vec![("sprint-40", 0, &table[0])]);
+        assert_eq!(
+            HighScores::parse(&scores.to_text()).table(&Mode::Sprint(40)),
+            table
+        );
+        // The merged tables are cut to the best records
+        let text: String = (0..MAX_ENTRIES + 2)
+            .map(|i| format!("endless\t{}\t0\t0\t0\t-\tp{}\n", i, i))
+            .collect();
+        let table = HighScores::parse(&text).table(&Mode::Endless).to_vec();
+        assert_eq!(table.len(), MAX_ENTRIES);
+        assert_eq!(table[0].score, MAX_ENTRIES + 1);
+    }
+
+    #[test]
+    fn submitted_scores_are_merged_under_the_lock() {
+        let path = temp_path("scores");
+        let mode = Mode::Endless;
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let path = path.clone();
+                thread::spawn(move || HighScores::submit_to(&path, &mode, entry("p", i, 0)))
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap().unwrap();
+        }
+        let scores = HighScores::load_from(&path).unwrap();
+        assert_eq!(scores.table(&mode).len(), 4);
+        assert!(!path.with_extension("lock").exists());
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn names_fit_the_file() {
+        assert_eq!(clean_name("a\tb\nc"), "abc");
+        assert_eq!(clean_name("   "), "anonymous");
+        assert_eq!(clean_name("a very long name indeed"), "a very long");
+    }
+}
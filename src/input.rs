@@ -0,0 +1,319 @@
+use std::time::Duration;
+
+use crossterm::event::KeyCode;
+
+use crate::config::{Action, Handling};
+use crate::tetris_engine::{FRAMES_PER_SECOND, MAX_HEIGHT, MAX_WIDTH};
+
+// When the terminal doesn't report key releases, a key is considered held
+// only after the OS starts to repeat it, and released when the repeats stop.
+const FALLBACK_REPEAT_WINDOW: u64 = 42; // 700 ms
+const FALLBACK_RELEASE_TIMEOUT: u64 = 9; // 150 ms
+
+// The piece can't move further than the largest playfield anyway
+const MAX_REPEATS: u32 = if MAX_WIDTH > MAX_HEIGHT {
+    MAX_WIDTH as u32
+} else {
+    MAX_HEIGHT as u32
+};
+
+#[derive(Debug)]
+struct HeldKey {
+    code: KeyCode,
+    action: Action,
+    pressed_at: u64,
+    last_seen: u64,
+    // The frame of the next automatic move, `None` until the key is known to be held
+    next_move: Option<u64>,
+}
+
+impl HeldKey {
+    // Without the key releases, the key is released when it isn't seen again in time:
+    // before the OS repeat starts, or between the repeats
+    fn is_released(&self, now: u64) -> bool {
+        let timeout = match self.next_move {
+            Some(_) => FALLBACK_RELEASE_TIMEOUT,
+            None => FALLBACK_REPEAT_WINDOW,
+        };
+        now >= self.last_seen + timeout
+    }
+}
+
+// The handling settings converted into frames
+#[derive(Debug)]
+struct FrameHandling {
+    das: u64,
+    arr: u64,
+    sdf: u32,
+    dcd: u64,
+}
+
+fn to_frames(duration: Duration) -> u64 {
+    (duration.as_secs_f64() * FRAMES_PER_SECOND as f64).round() as u64
+}
+
+// Turns the key presses and releases into the repeated moves
+// according to the DAS, ARR, SDF and DCD settings.
+// The time is measured in the engine frames, so the moves happen on the exact frames.
+#[derive(Debug)]
+pub struct InputHandler {
+    handling: FrameHandling,
+    // Whether the terminal reports key releases (keyboard enhancement protocol)
+    key_releases: bool,
+    soft_drop_interval: u64,
+    // In the order of pressing
+    held: Vec<HeldKey>,
+}
+
+fn is_repeatable(action: &Action) -> bool {
+    matches!(
+        action,
+        Action::MoveLeft | Action::MoveRight | Action::SoftDrop
+    )
+}
+
+fn is_horizontal(action: &Action) -> bool {
+    matches!(action, Action::MoveLeft | Action::MoveRight)
+}
+
+impl InputHandler {
+    pub fn new(handling: Handling, key_releases: bool) -> Self {
+        Self {
+            handling: FrameHandling {
+                das: to_frames(handling.das),
+                arr: to_frames(handling.arr),
+                sdf: handling.sdf,
+                dcd: to_frames(handling.dcd),
+            },
+            key_releases,
+            soft_drop_interval: 0,
+            held: Vec::new(),
+        }
+    }
+
+    // Gravity in frames per row. Zero soft drop interval means that the piece drops at once.
+    pub fn set_gravity(&mut self, gravity: u32) {
+        self.soft_drop_interval = (gravity / self.handling.sdf) as u64;
+    }
+
+    fn first_delay(&self, action: &Action) -> u64 {
+        if is_horizontal(action) {
+            self.handling.das
+        } else {
+            self.soft_drop_interval
+        }
+    }
+
+    // Registers the key press. Returns `true` if the action should be performed right away.
+    pub fn press(&mut self, code: KeyCode, action: Action, now: u64) -> bool {
+        if !is_repeatable(&action) {
+            return true;
+        }
+
+        let delay = self.first_delay(&action);
+        if let Some(index) = self.held.iter().position(|key| key.code == code) {
+            let key = &mut self.held[index];
+            if !self.key_releases && key.is_released(now) {
+                // Pressed again after it was let go
+                key.pressed_at = now;
+                key.last_seen = now;
+                key.next_move = None;
+                return true;
+            }
+            // Without the key releases, the same key pressed again soon is the OS auto-repeat.
+            // The OS repeat rate is ignored, the moves are timed by the handling settings.
+            key.last_seen = now;
+            if key.next_move.is_none() {
+                key.next_move = Some(now.max(key.pressed_at + delay));
+            }
+            return false;
+        }
+
+        self.held.push(HeldKey {
+            code,
+            action,
+            pressed_at: now,
+            last_seen: now,
+            next_move: self.key_releases.then_some(now + delay),
+        });
+        true
+    }
+
+    pub fn release(&mut self, code: KeyCode, now: u64) {
+        let was_active = self.active_horizontal().is_some_and(|key| key.code == code);
+        self.held.retain(|key| key.code != code);
+        if was_active {
+            // The key which is still held takes over after the full DAS
+            let das = self.handling.das;
+            if let Some(key) = self
+                .held
+                .iter_mut()
+                .rev()
+                .find(|key| is_horizontal(&key.action))
+            {
+                key.next_move = Some(now + das);
+            }
+        }
+    }
+
+    // Forgets all the held keys, e.g. when the game is paused
+    pub fn clear(&mut self) {
+        self.held.clear();
+    }
+
+    // DAS cut: postpones the automatic horizontal moves after a rotation, hold or hard drop
+    pub fn cut_das(&mut self, now: u64) {
+        let dcd = self.handling.dcd;
+        for key in self
+            .held
+            .iter_mut()
+            .filter(|key| is_horizontal(&key.action))
+        {
+            if let Some(next_move) = key.next_move.as_mut() {
+                *next_move = (*next_move).max(now + dcd);
+            }
+        }
+    }
+
+    fn active_horizontal(&self) -> Option<&HeldKey> {
+        self.held
+            .iter()
+            .rev()
+            .find(|key| is_horizontal(&key.action))
+    }
+
+    // Returns the automatic moves which are due at the given time
+    pub fn tick(&mut self, now: u64) -> Vec<Action> {
+        if !self.key_releases {
+            self.held.retain(|key| !key.is_released(now));
+        }
+
+        let mut result = Vec::new();
+        let active = self.active_horizontal().map(|key| key.code);
+        let (arr, soft_drop_interval) = (self.handling.arr, self.soft_drop_interval);
+        for key in self.held.iter_mut() {
+            let interval = if is_horizontal(&key.action) {
+                if Some(key.code) != active {
+                    continue;
+                }
+                arr
+            } else {
+                soft_drop_interval
+            };
+            let Some(next_move) = key.next_move else {
+                continue;
+            };
+            if next_move > now {
+                continue;
+            }
+
+            let moves = match (now - next_move).checked_div(interval) {
+                Some(elapsed) => (elapsed.min(MAX_REPEATS as u64) as u32 + 1).min(MAX_REPEATS),
+                // Zero interval means the instant move
+                None => MAX_REPEATS,
+            };
+            key.next_move = Some(now + interval);
+            for _ in 0..moves {
+                result.push(key.action);
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 6 frames of DAS, 3 frames of ARR and DCD
+    fn handling() -> Handling {
+        Handling {
+            das: Duration::from_millis(100),
+            arr: Duration::from_millis(50),
+            sdf: 10,
+            dcd: Duration::from_millis(50),
+        }
+    }
+
+    #[test]
+    fn held_key_is_repeated_after_das() {
+        let mut input = InputHandler::new(handling(), true);
+        assert!(input.press(KeyCode::Left, Action::MoveLeft, 0));
+        assert!(input.tick(5).is_empty());
+        assert_eq!(input.tick(6), vec![Action::MoveLeft]);
+        // Two ARR periods later, two more moves are due
+        assert_eq!(input.tick(12), vec![Action::MoveLeft, Action::MoveLeft]);
+        input.release(KeyCode::Left, 13);
+        assert!(input.tick(30).is_empty());
+    }
+
+    #[test]
+    fn latest_direction_wins() {
+        let mut input = InputHandler::new(handling(), true);
+        input.press(KeyCode::Left, Action::MoveLeft, 0);
+        input.press(KeyCode::Right, Action::MoveRight, 3);
+        assert_eq!(input.tick(9), vec![Action::MoveRight]);
+        // Left has to be charged again after Right is released
+        input.release(KeyCode::Right, 10);
+        assert!(input.tick(15).is_empty());
+        assert_eq!(input.tick(16), vec![Action::MoveLeft]);
+    }
+
+    #[test]
+    fn das_is_cut_after_rotation() {
+        let mut input = InputHandler::new(handling(), true);
+        input.press(KeyCode::Left, Action::MoveLeft, 0);
+        input.cut_das(5);
+        assert!(input.tick(7).is_empty());
+        assert_eq!(input.tick(8), vec![Action::MoveLeft]);
+    }
+
+    #[test]
+    fn soft_drop_is_faster_than_gravity() {
+        let mut input = InputHandler::new(handling(), true);
+        input.set_gravity(30);
+        assert!(input.press(KeyCode::Down, Action::SoftDrop, 0));
+        assert!(input.tick(2).is_empty());
+        assert_eq!(input.tick(3), vec![Action::SoftDrop]);
+    }
+
+    #[test]
+    fn os_repeat_is_detected_without_key_releases() {
+        let mut input = InputHandler::new(handling(), false);
+        assert!(input.press(KeyCode::Left, Action::MoveLeft, 0));
+        // A single tap is never repeated
+        assert!(input.tick(20).is_empty());
+        // The OS repeat starts after its delay, the DAS has already passed by then.
+        // The piece moves at the ARR instead of the OS rate.
+        assert!(!input.press(KeyCode::Left, Action::MoveLeft, 24));
+        assert_eq!(input.tick(24), vec![Action::MoveLeft]);
+        assert!(!input.press(KeyCode::Left, Action::MoveLeft, 26));
+        assert!(input.tick(26).is_empty());
+        assert_eq!(input.tick(27), vec![Action::MoveLeft]);
+        // The repeats have stopped, so the key was released
+        assert!(input.tick(40).is_empty());
+    }
+
+    #[test]
+    fn held_key_is_pressed_once_without_key_releases() {
+        let mut input = InputHandler::new(handling(), false);
+        let presses = [0, 30, 32, 34, 36, 38, 40]
+            .into_iter()
+            .filter(|&now| input.press(KeyCode::Left, Action::MoveLeft, now))
+            .count();
+        assert_eq!(presses, 1);
+        // Pressed again after the repeats have stopped
+        assert!(input.press(KeyCode::Left, Action::MoveLeft, 49));
+    }
+
+    #[test]
+    fn zero_arr_moves_to_the_wall() {
+        let handling = Handling {
+            arr: Duration::ZERO,
+            ..handling()
+        };
+        let mut input = InputHandler::new(handling, true);
+        input.press(KeyCode::Right, Action::MoveRight, 0);
+        assert_eq!(input.tick(6).len() as u32, MAX_REPEATS);
+    }
+}
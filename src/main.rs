@@ -1,12 +1,131 @@
+mod config;
+mod editor;
+mod finesse;
+mod fumen;
+mod game_screen;
+mod high_scores;
+mod input;
+mod modes;
+mod modifiers;
+mod netplay;
+mod openers;
+mod perfect_clear;
+mod pieces;
+mod protocol;
+mod puzzles;
 mod renderer;
+mod screens;
+mod spectate;
+mod sprint;
+mod stats;
 mod terminal_app;
 mod tetris_engine;
+mod versus;
 
-fn main() -> std::io::Result<()> {
-    let mut app = terminal_app::App::new();
-    let mut engine = tetris_engine::TetrisEngine::new();
+use std::{io, process::ExitCode};
+
+const USAGE: &str = "Usage:
+  ratrix                 play
+  ratrix host [port]     wait for the opponent of an online match
+  ratrix join <addr>     join the online match, e.g. `ratrix join 192.168.0.2:7340`
+  ratrix publish [port]  play and let the other terminals watch the game
+  ratrix watch [port]    watch the game published on this computer
+  ratrix fumen [port]    print the fumen of the board of the published game
+  ratrix fumen <fumen>   play from the board and the pieces of the fumen, e.g. `v115@vhAAgH`";
+
+// How the app starts, the connections are made before the terminal is taken over,
+// so the waiting and the connection errors are printed as usual
+enum Start {
+    Title,
+    Netplay(netplay::Session),
+    Publish(spectate::Publisher),
+    Watch(spectate::WatchScreen),
+    Practice(tetris_engine::Setup),
+    // Prints the text without starting the game
+    Print(String),
+}
+
+fn parse_port(port: &str) -> io::Result<u16> {
+    port.parse().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid port: {}", port),
+        )
+    })
+}
+
+fn with_context(context: &str, e: io::Error) -> io::Error {
+    io::Error::new(e.kind(), format!("{}: {}", context, e))
+}
+
+fn published_fumen(port: u16) -> io::Result<Start> {
+    let state = spectate::published_board(port)?;
+    let page = fumen::Page::from_state(&state).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "The fumen can't have a board of that size",
+        )
+    })?;
+    Ok(Start::Print(fumen::encode(&[page])))
+}
+
+// `None` if the arguments make no sense
+fn start(args: &[&str]) -> Option<io::Result<Start>> {
+    if let ["fumen", data] = args
+        && data.contains("115@")
+    {
+        let setup = fumen::decode(data).map(|pages| Start::Practice(fumen::setup(&pages)));
+        return Some(setup.map_err(|e| with_context("Couldn't read the fumen", e)));
+    }
+    let start = match args {
+        [] => Ok(Start::Title),
+        ["host"] => netplay::host(netplay::DEFAULT_PORT).map(Start::Netplay),
+        ["host", port] => parse_port(port).and_then(netplay::host).map(Start::Netplay),
+        ["join", address] => netplay::join(address).map(Start::Netplay),
+        ["publish"] => spectate::Publisher::bind(spectate::DEFAULT_PORT).map(Start::Publish),
+        ["publish", port] => parse_port(port)
+            .and_then(spectate::Publisher::bind)
+            .map(Start::Publish),
+        ["watch"] => spectate::watch(spectate::DEFAULT_PORT).map(Start::Watch),
+        ["watch", port] => parse_port(port).and_then(spectate::watch).map(Start::Watch),
+        ["fumen"] => published_fumen(spectate::DEFAULT_PORT),
+        ["fumen", port] => parse_port(port).and_then(published_fumen),
+        _ => return None,
+    };
+    Some(start.map_err(|e| with_context("Couldn't connect", e)))
+}
+
+fn main() -> io::Result<ExitCode> {
+    let config = config::Config::load()?;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let start = match start(&args) {
+        Some(Ok(Start::Print(text))) => {
+            println!("{}", text);
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Ok(start)) => start,
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            return Ok(ExitCode::FAILURE);
+        }
+        None => {
+            eprintln!("{}", USAGE);
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+
+    let mut app = terminal_app::App::new(config);
     app.setup()?;
-    app.run(&mut engine)?;
+    match start {
+        Start::Title => {}
+        Start::Netplay(session) => app.start_netplay(session),
+        Start::Publish(publisher) => app.publish(publisher),
+        Start::Watch(watch) => app.start_watching(watch),
+        Start::Practice(setup) => app.start_practice(setup),
+        Start::Print(_) => {}
+    }
+    app.run()?;
     app.teardown()?;
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
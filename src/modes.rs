@@ -0,0 +1,507 @@
+use std::fmt;
+
+use crate::finesse::{Drill, Verdict};
+use crate::openers::Guide;
+use crate::puzzles::Attempt;
+use crate::sprint::Sprint;
+use crate::tetris_engine::{FRAMES_PER_SECOND, GarbageHoles, Lock, Rules, Setup, TetrisEngine};
+use crate::versus::Versus;
+
+const SPRINT_LINES: [usize; 3] = [20, 40, 100];
+const ULTRA_MINUTES: [u64; 2] = [2, 3];
+const MARATHON_LINES: [usize; 2] = [150, 200];
+const DIG_OPTIONS: [(usize, GarbageHoles); 6] = [
+    (10, GarbageHoles::Clean),
+    (10, GarbageHoles::Messy),
+    (10, GarbageHoles::Random),
+    (18, GarbageHoles::Messy),
+    (18, GarbageHoles::Random),
+    (100, GarbageHoles::Random),
+];
+// The dig board is refilled up to this many garbage rows
+const DIG_ROWS: usize = 10;
+
+pub fn frames_to_millis(frames: u64) -> u64 {
+    frames * 1000 / FRAMES_PER_SECOND as u64
+}
+
+// `m:ss.mmm`
+pub fn format_time(millis: u64) -> String {
+    format!(
+        "{}:{:02}.{:03}",
+        millis / 60_000,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+// The game modes together with the option chosen in the menu
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Endless,
+    // Clear the given number of lines as fast as possible
+    Sprint(usize),
+    // Score as much as possible in the given number of minutes
+    Ultra(u64),
+    // Clear the given number of lines while the speed goes up every level
+    Marathon(usize),
+    // Clear the given number of garbage lines as fast as possible
+    Dig(usize, GarbageHoles),
+    // Place the pieces onto the outlines with the least inputs
+    Finesse,
+    // Play from a given board, e.g. a fumen, it isn't in the mode menu
+    Practice,
+    // Meet the goal of a puzzle, they are chosen from the puzzle menu
+    Puzzle,
+    // Place the pieces of an opener onto the guide, chosen from the opener menu
+    Opener,
+    // Two players send the garbage to each other, it isn't in the mode menu
+    Versus,
+}
+
+fn holes_name(holes: &GarbageHoles) -> &'static str {
+    match holes {
+        GarbageHoles::Clean => "clean",
+        GarbageHoles::Messy => "messy",
+        GarbageHoles::Random => "random",
+    }
+}
+
+// The item after `current` in `options`, or the one before it
+fn cycle<T: Copy + PartialEq>(options: &[T], current: T, forward: bool) -> T {
+    let len = options.len();
+    let index = options.iter().position(|o| *o == current).unwrap_or(0);
+    let next = if forward { index + 1 } else { index + len - 1 };
+    options[next % len]
+}
+
+impl Mode {
+    // The modes in the menu with their default options
+    pub const ALL: [Mode; 6] = [
+        Mode::Endless,
+        Mode::Sprint(40),
+        Mode::Ultra(3),
+        Mode::Marathon(150),
+        Mode::Dig(10, GarbageHoles::Messy),
+        Mode::Finesse,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Mode::Endless => "Endless",
+            Mode::Sprint(_) => "Sprint",
+            Mode::Ultra(_) => "Ultra",
+            Mode::Marathon(_) => "Marathon",
+            Mode::Dig(_, _) => "Dig",
+            Mode::Finesse => "Finesse",
+            Mode::Practice => "Practice",
+            Mode::Puzzle => "Puzzle",
+            Mode::Opener => "Opener",
+            Mode::Versus => "Versus",
+        }
+    }
+
+    // The option shown in the menu, e.g. `40 lines`
+    pub fn option(&self) -> Option<String> {
+        match self {
+            Mode::Endless
+            | Mode::Finesse
+            | Mode::Practice
+            | Mode::Puzzle
+            | Mode::Opener
+            | Mode::Versus => None,
+            Mode::Sprint(lines) | Mode::Marathon(lines) => Some(format!("{} lines", lines)),
+            Mode::Ultra(minutes) => Some(format!("{} min", minutes)),
+            Mode::Dig(lines, holes) => Some(format!("{} lines, {}", lines, holes_name(holes))),
+        }
+    }
+
+    // The mode with its option as a single word, e.g. `sprint-40`, used in the records
+    pub fn key(&self) -> String {
+        let name = self.name().to_lowercase();
+        match self {
+            Mode::Endless
+            | Mode::Finesse
+            | Mode::Practice
+            | Mode::Puzzle
+            | Mode::Opener
+            | Mode::Versus => name,
+            Mode::Sprint(lines) | Mode::Marathon(lines) => format!("{}-{}", name, lines),
+            Mode::Ultra(minutes) => format!("{}-{}", name, minutes),
+            Mode::Dig(lines, holes) => format!("{}-{}-{}", name, lines, holes_name(holes)),
+        }
+    }
+
+    // The mode of the menu with the given key, `None` for the modes without the options
+    pub fn from_key(key: &str) -> Option<Mode> {
+        Mode::ALL.into_iter().find_map(|first| {
+            let mut mode = first;
+            loop {
+                if mode.key() == key {
+                    return Some(mode);
+                }
+                mode = mode.cycled(true);
+                if mode == first {
+                    return None;
+                }
+            }
+        })
+    }
+
+    // The matches, the drills, the puzzles and the custom boards have no high scores
+    pub fn has_records(&self) -> bool {
+        !matches!(
+            self,
+            Mode::Finesse | Mode::Practice | Mode::Puzzle | Mode::Opener | Mode::Versus
+        )
+    }
+
+    // The race modes are won by the time, the rest of them by the score
+    pub fn ranked_by_time(&self) -> bool {
+        matches!(self, Mode::Sprint(_) | Mode::Dig(_, _))
+    }
+
+    // The piece set, the gravity and the big mode of the settings are used by the modes which
+    // don't come with their own board or pieces, the practice, the drills, the puzzles
+    // and the network games stay standard
+    pub fn uses_game_settings(&self) -> bool {
+        !matches!(
+            self,
+            Mode::Finesse | Mode::Practice | Mode::Puzzle | Mode::Opener
+        )
+    }
+
+    // The same mode with the next (or the previous) option
+    pub fn cycled(self, forward: bool) -> Mode {
+        match self {
+            Mode::Endless
+            | Mode::Finesse
+            | Mode::Practice
+            | Mode::Puzzle
+            | Mode::Opener
+            | Mode::Versus => self,
+            Mode::Sprint(lines) => Mode::Sprint(cycle(&SPRINT_LINES, lines, forward)),
+            Mode::Ultra(minutes) => Mode::Ultra(cycle(&ULTRA_MINUTES, minutes, forward)),
+            Mode::Marathon(lines) => Mode::Marathon(cycle(&MARATHON_LINES, lines, forward)),
+            Mode::Dig(lines, holes) => {
+                let (lines, holes) = cycle(&DIG_OPTIONS, (lines, holes), forward);
+                Mode::Dig(lines, holes)
+            }
+        }
+    }
+
+    pub fn ruleset(&self) -> Box<dyn Ruleset> {
+        match *self {
+            Mode::Endless => Box::new(Endless),
+            Mode::Sprint(lines) => Box::new(Sprint::new(lines)),
+            Mode::Ultra(minutes) => Box::new(Ultra {
+                minutes,
+                started: false,
+            }),
+            Mode::Marathon(lines) => Box::new(Marathon { lines }),
+            Mode::Dig(lines, holes) => Box::new(Dig {
+                lines,
+                holes,
+                started: false,
+            }),
+            Mode::Finesse => Box::new(Drill::default()),
+            Mode::Practice => Box::new(Practice::default()),
+            Mode::Puzzle => Box::new(Attempt::default()),
+            Mode::Opener => Box::new(Guide::default()),
+            Mode::Versus => Box::new(Versus),
+        }
+    }
+}
+
+// A game mode: configures the goals, gravity and end conditions of the engine
+// and keeps track of the things the engine doesn't know about (e.g. the records).
+// A new mode only has to implement this trait and be added to `Mode`.
+pub trait Ruleset: fmt::Debug {
+    fn rules(&self) -> Rules;
+
+    // Forgets the previous game when the game is started over
+    fn reset(&mut self) {}
+
+    // Prepares the board of a new game
+    fn setup(&mut self, _engine: &mut TetrisEngine) {}
+
+    // The timed modes don't start until the first input,
+    // so the player can look at the first pieces
+    fn is_waiting(&self) -> bool {
+        false
+    }
+
+    // Called on every gameplay key press with the frame of the press
+    fn on_input(&mut self, _frame: u64) {}
+
+    // Called after every simulated frame with the number of the frames so far
+    fn update(&mut self, _engine: &mut TetrisEngine, _frame: u64) {}
+
+    // Called for every locked piece with the finesse check of its placement
+    fn on_lock(&mut self, _engine: &mut TetrisEngine, _lock: &Lock, _verdict: &Verdict) {}
+
+    // Called when a placement is taken back, or made again by the redo
+    fn on_undo(&mut self, _redo: bool) {}
+
+    // The cells outlined on the board, the rows from the top
+    fn overlay(&self) -> Vec<u16> {
+        Vec::new()
+    }
+
+    // The lines shown in the side panel
+    fn hud(&self, engine: &TetrisEngine, frame: u64) -> Vec<String>;
+
+    // The title and the summary of the game which reached its goal,
+    // the records are kept only for the standard game
+    fn finish(&mut self, engine: &TetrisEngine, standard: bool) -> (String, Vec<String>);
+}
+
+// The summary of the game which is over for any reason
+pub fn summary(engine: &TetrisEngine) -> Vec<String> {
+    vec![
+        format!("Score: {}", engine.score()),
+        format!("Lines: {}", engine.lines()),
+        format!("Level: {}", engine.level()),
+    ]
+}
+
+// Play until the top out
+#[derive(Debug)]
+struct Endless;
+
+impl Ruleset for Endless {
+    fn rules(&self) -> Rules {
+        Rules::default()
+    }
+
+    fn hud(&self, engine: &TetrisEngine, _frame: u64) -> Vec<String> {
+        vec![String::from("Level"), engine.level().to_string()]
+    }
+
+    fn finish(&mut self, engine: &TetrisEngine, _standard: bool) -> (String, Vec<String>) {
+        (String::from("Game over"), summary(engine))
+    }
+}
+
+// The endless game from the given board and pieces
+#[derive(Debug, Default)]
+pub struct Practice {
+    pub setup: Setup,
+}
+
+impl Ruleset for Practice {
+    fn rules(&self) -> Rules {
+        Rules {
+            undo: true,
+            hint: true,
+            ..Rules::default()
+        }
+    }
+
+    fn setup(&mut self, engine: &mut TetrisEngine) {
+        engine.load(&self.setup);
+    }
+
+    fn hud(&self, engine: &TetrisEngine, _frame: u64) -> Vec<String> {
+        vec![String::from("Level"), engine.level().to_string()]
+    }
+
+    fn finish(&mut self, engine: &TetrisEngine, _standard: bool) -> (String, Vec<String>) {
+        (String::from("Game over"), summary(engine))
+    }
+}
+
+// The time attack: the level doesn't change, only the score matters
+#[derive(Debug)]
+struct Ultra {
+    minutes: u64,
+    started: bool,
+}
+
+impl Ultra {
+    fn frame_limit(&self) -> u64 {
+        self.minutes * 60 * FRAMES_PER_SECOND as u64
+    }
+}
+
+impl Ruleset for Ultra {
+    fn rules(&self) -> Rules {
+        Rules {
+            frame_limit: Some(self.frame_limit()),
+            lines_per_level: None,
+            ..Rules::default()
+        }
+    }
+
+    fn reset(&mut self) {
+        self.started = false;
+    }
+
+    fn is_waiting(&self) -> bool {
+        !self.started
+    }
+
+    fn on_input(&mut self, _frame: u64) {
+        self.started = true;
+    }
+
+    fn hud(&self, engine: &TetrisEngine, _frame: u64) -> Vec<String> {
+        let left = self.frame_limit().saturating_sub(engine.frames());
+        vec![String::from("Time"), format_time(frames_to_millis(left))]
+    }
+
+    fn finish(&mut self, engine: &TetrisEngine, _standard: bool) -> (String, Vec<String>) {
+        (String::from("Time's up"), summary(engine))
+    }
+}
+
+// The classic game with the win condition
+#[derive(Debug)]
+struct Marathon {
+    lines: usize,
+}
+
+impl Ruleset for Marathon {
+    fn rules(&self) -> Rules {
+        Rules {
+            line_goal: Some(self.lines),
+            ..Rules::default()
+        }
+    }
+
+    fn hud(&self, engine: &TetrisEngine, _frame: u64) -> Vec<String> {
+        vec![
+            String::from("Level"),
+            engine.level().to_string(),
+            String::new(),
+            String::from("Lines"),
+            format!("{}/{}", engine.lines(), self.lines),
+        ]
+    }
+
+    fn finish(&mut self, engine: &TetrisEngine, _standard: bool) -> (String, Vec<String>) {
+        let mut info = summary(engine);
+        let time = frames_to_millis(engine.frames());
+        info.push(format!("Time: {}", format_time(time)));
+        (String::from("Marathon complete"), info)
+    }
+}
+
+// The cheese race: dig through the garbage as fast as possible
+#[derive(Debug)]
+struct Dig {
+    lines: usize,
+    holes: GarbageHoles,
+    started: bool,
+}
+
+impl Dig {
+    // Keeps the board filled with the garbage until the rest of the lines are on it
+    fn refill(&self, engine: &mut TetrisEngine) {
+        let on_board = engine.garbage_rows();
+        let left = self.lines - engine.garbage_cleared() - on_board;
+        let count = left.min(DIG_ROWS.saturating_sub(on_board));
+        if count > 0 {
+            engine.insert_garbage(count, self.holes);
+        }
+    }
+}
+
+impl Ruleset for Dig {
+    fn rules(&self) -> Rules {
+        Rules {
+            garbage_goal: Some(self.lines),
+            lines_per_level: None,
+            ..Rules::default()
+        }
+    }
+
+    fn reset(&mut self) {
+        self.started = false;
+    }
+
+    fn setup(&mut self, engine: &mut TetrisEngine) {
+        self.refill(engine);
+    }
+
+    fn is_waiting(&self) -> bool {
+        !self.started
+    }
+
+    fn on_input(&mut self, _frame: u64) {
+        self.started = true;
+    }
+
+    fn update(&mut self, engine: &mut TetrisEngine, _frame: u64) {
+        self.refill(engine);
+    }
+
+    fn hud(&self, engine: &TetrisEngine, _frame: u64) -> Vec<String> {
+        vec![
+            String::from("Time"),
+            format_time(frames_to_millis(engine.frames())),
+            String::new(),
+            String::from("Garbage"),
+            format!("{}/{}", engine.garbage_cleared(), self.lines),
+        ]
+    }
+
+    fn finish(&mut self, engine: &TetrisEngine, _standard: bool) -> (String, Vec<String>) {
+        let time = frames_to_millis(engine.frames());
+        let info = vec![
+            format!("Time: {}", format_time(time)),
+            format!("Lines: {}", engine.lines()),
+        ];
+        (format!("Dig {} lines", self.lines), info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mode_options_are_cycled_both_ways() {
+        assert_eq!(Mode::Sprint(40).cycled(true), Mode::Sprint(100));
+        assert_eq!(Mode::Sprint(100).cycled(true), Mode::Sprint(20));
+        assert_eq!(Mode::Sprint(20).cycled(false), Mode::Sprint(100));
+        assert_eq!(Mode::Ultra(3).cycled(true), Mode::Ultra(2));
+        assert_eq!(Mode::Endless.cycled(true), Mode::Endless);
+    }
+
+    #[test]
+    fn mode_keys_include_the_options() {
+        assert_eq!(Mode::Sprint(40).key(), "sprint-40");
+        assert_eq!(Mode::Dig(18, GarbageHoles::Messy).key(), "dig-18-messy");
+        assert_eq!(
+            Mode::from_key("dig-18-messy"),
+            Some(Mode::Dig(18, GarbageHoles::Messy))
+        );
+        assert_eq!(Mode::from_key("sprint-7"), None);
+        assert_eq!(Mode::Endless.key(), "endless");
+    }
+
+    #[test]
+    fn ultra_waits_for_the_first_input() {
+        let mut ultra = Mode::Ultra(2).ruleset();
+        assert_eq!(ultra.rules().frame_limit, Some(2 * 60 * 60));
+        assert!(ultra.is_waiting());
+        ultra.on_input(0);
+        assert!(!ultra.is_waiting());
+        let engine = TetrisEngine::with_rules(ultra.rules());
+        assert_eq!(ultra.hud(&engine, 0), vec!["Time", "2:00.000"]);
+        ultra.reset();
+        assert!(ultra.is_waiting());
+    }
+
+    #[test]
+    fn dig_board_is_refilled_with_garbage() {
+        let mut dig = Mode::Dig(18, GarbageHoles::Clean).ruleset();
+        let mut engine = TetrisEngine::with_rules(dig.rules());
+        dig.setup(&mut engine);
+        assert_eq!(engine.garbage_rows(), 10);
+        // Nothing is added until some garbage is cleared
+        dig.update(&mut engine, 1);
+        assert_eq!(engine.garbage_rows(), 10);
+    }
+}
@@ -0,0 +1,185 @@
+use crate::pieces::{Color, PIECE_SIZE, shape_row};
+use crate::tetris_engine::{FRAMES_PER_SECOND, GameState, Rules, TetrisEngine};
+
+// The delays of the fading which can be chosen in the settings, in seconds
+pub const FADE_SECONDS: [u64; 3] = [3, 5, 10];
+
+// The challenges which can be added to the games. They change only what is shown,
+// the big mode also plays on a board of half the size. The modes with a board of their
+// own (the practice, the drills, the puzzles and the openers) and the online matches
+// need the standard board, so they are played without the big mode.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Modifiers {
+    // The locked cells vanish at once
+    pub invisible: bool,
+    // The locked cells vanish after this many seconds
+    pub fade: Option<u64>,
+    // The cells and the pieces are all drawn in one color
+    pub monochrome: bool,
+    // Every cell is drawn as 2x2 cells
+    pub big: bool,
+}
+
+impl Modifiers {
+    // The board of the big mode has half the width and the height, so it's drawn
+    // in the same size
+    pub fn rules(&self, rules: Rules) -> Rules {
+        if !self.big {
+            return rules;
+        }
+        Rules {
+            width: rules.width / 2,
+            height: rules.height / 2,
+            ..rules
+        }
+    }
+
+    // How long the locked cells are shown, `None` if they don't vanish
+    fn visible_frames(&self) -> Option<u64> {
+        if self.invisible {
+            return Some(0);
+        }
+        self.fade.map(|seconds| seconds * FRAMES_PER_SECOND as u64)
+    }
+
+    // The locked cells which aren't shown, the rows as in the playfield.
+    // The whole stack is revealed when the game is over.
+    pub fn hidden(&self, engine: &TetrisEngine) -> Vec<u16> {
+        match self.visible_frames() {
+            Some(frames)
+                if engine.frames() >= frames && !engine.is_game_over() && !engine.is_finished() =>
+            {
+                engine.cells_locked_by(engine.frames() - frames)
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    // The state as it's drawn
+    pub fn apply(&self, mut state: GameState, hidden: &[u16]) -> GameState {
+        for (row, hidden) in state.playfield.iter_mut().zip(hidden) {
+            *row &= !hidden;
+        }
+        if self.monochrome {
+            for row in state.colors.iter_mut() {
+                row.fill(Color::default());
+            }
+            state.active_color = Color::default();
+            state.hold_color = Color::default();
+        }
+        if self.big { enlarged(state) } else { state }
+    }
+}
+
+// The active piece is drawn as a part of the board, the board is twice as large
+// as the piece could be
+fn enlarged(state: GameState) -> GameState {
+    let width = state.width;
+    let mut playfield = state.playfield.clone();
+    let mut colors = state.colors.clone();
+    let [left, top] = state.piece_position.map(|n| n as usize);
+    for i in 0..PIECE_SIZE {
+        let row = shape_row(state.active_piece, i);
+        for x in (0..PIECE_SIZE).filter(|x| row >> (PIECE_SIZE - 1 - x) & 1 == 1) {
+            let (x, y) = (left + x, top + i);
+            if x < width && y < playfield.len() {
+                playfield[y] |= 1 << (width - 1 - x);
+                colors[y][x] = state.active_color;
+            }
+        }
+    }
+    // Every cell becomes two cells in a row, and every row two rows
+    let double = |row: &u16| -> [u16; 2] {
+        let doubled = (0..width)
+            .filter(|x| row >> x & 1 == 1)
+            .map(|x| 0b11 << (2 * x))
+            .sum();
+        [doubled; 2]
+    };
+    GameState {
+        playfield: playfield.iter().flat_map(double).collect(),
+        width: width * 2,
+        colors: colors
+            .iter()
+            .flat_map(|row| {
+                let row: Vec<Color> = row.iter().flat_map(|&color| [color; 2]).collect();
+                [row.clone(), row]
+            })
+            .collect(),
+        overlay: state.overlay.iter().flat_map(double).collect(),
+        active_piece: 0,
+        piece_position: [0, 0],
+        ..state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tetris_engine::piece_by_name;
+
+    #[test]
+    fn cells_vanish_after_the_delay() {
+        let fading = Modifiers {
+            fade: Some(1),
+            ..Modifiers::default()
+        };
+        let mut engine = TetrisEngine::new();
+        engine.hard_drop();
+        assert!(fading.hidden(&engine).iter().all(|&row| row == 0));
+        for _ in 0..FRAMES_PER_SECOND {
+            engine.update();
+        }
+        let hidden = fading.hidden(&engine);
+        assert_eq!(hidden, engine.get_state().playfield);
+        let state = fading.apply(engine.get_state(), &hidden);
+        assert!(state.playfield.iter().all(|&row| row == 0));
+
+        // The invisible cells vanish at once
+        let invisible = Modifiers {
+            invisible: true,
+            ..Modifiers::default()
+        };
+        let mut engine = TetrisEngine::new();
+        engine.hard_drop();
+        assert_eq!(invisible.hidden(&engine), engine.get_state().playfield);
+    }
+
+    #[test]
+    fn big_cells_are_doubled() {
+        let big = Modifiers {
+            big: true,
+            ..Modifiers::default()
+        };
+        let rules = big.rules(Rules::default());
+        assert_eq!((rules.width, rules.height), (5, 10));
+        let mut state = TetrisEngine::with_rules(rules).get_state();
+        state.playfield[9] = 0b10001;
+        state.active_piece = piece_by_name('O').unwrap();
+        state.piece_position = [1, 0];
+        let state = big.apply(state, &[]);
+        assert_eq!((state.width, state.playfield.len()), (10, 20));
+        assert_eq!(state.playfield[..3], [0b0011110000; 3]);
+        assert_eq!(state.playfield[18..], [0b1100000011; 2]);
+        assert_eq!(state.active_piece, 0);
+    }
+
+    #[test]
+    fn monochrome_paints_everything_alike() {
+        let monochrome = Modifiers {
+            monochrome: true,
+            ..Modifiers::default()
+        };
+        let mut engine = TetrisEngine::new();
+        engine.hard_drop();
+        let state = monochrome.apply(engine.get_state(), &[]);
+        assert!(
+            state
+                .colors
+                .iter()
+                .flatten()
+                .all(|&c| c == Color::default())
+        );
+        assert_eq!(state.active_color, Color::default());
+    }
+}
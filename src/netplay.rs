@@ -0,0 +1,340 @@
+use crossterm::event::KeyEvent;
+use std::{
+    io::{self, ErrorKind},
+    net::{TcpListener, TcpStream},
+    time::Instant,
+};
+
+use crate::config::{Action, Config, KeyBindings};
+use crate::game_screen::{GameRequest, GameScreen};
+use crate::modes::Mode;
+use crate::modifiers::Modifiers;
+use crate::pieces;
+use crate::protocol::{Connection, Message, PROTOCOL_VERSION};
+use crate::tetris_engine::{GameState, TetrisEngine};
+
+pub const DEFAULT_PORT: u16 = 7340;
+
+// The connected opponent and the seed of the pieces of both players
+#[derive(Debug)]
+pub struct Session {
+    connection: Connection,
+    seed: u64,
+}
+
+// Waits for the opponent on the given port
+pub fn host(port: u16) -> io::Result<Session> {
+    let listener = TcpListener::bind(("0.0.0.0", port))?;
+    println!("Waiting for the opponent on port {}...", port);
+    let (stream, address) = listener.accept()?;
+    println!("{} has joined", address);
+    handshake(stream, Some(rand::random()))
+}
+
+// Connects to the host, the port can be omitted
+pub fn join(address: &str) -> io::Result<Session> {
+    let stream = if address.contains(':') {
+        TcpStream::connect(address)?
+    } else {
+        TcpStream::connect((address, DEFAULT_PORT))?
+    };
+    handshake(stream, None)
+}
+
+// Both sides introduce themselves, the host also chooses the seed
+fn handshake(stream: TcpStream, seed: Option<u64>) -> io::Result<Session> {
+    let mut connection = Connection::new(stream)?;
+    connection.send(&Message::Hello {
+        version: PROTOCOL_VERSION,
+        seed: seed.unwrap_or(0),
+    })?;
+    let host_seed = connection.receive_hello()?;
+    Ok(Session {
+        connection,
+        seed: seed.unwrap_or(host_seed),
+    })
+}
+
+// The board of the opponent as it's shown
+#[derive(Debug, Default)]
+pub struct RemoteBoard {
+    pub state: GameState,
+    pub pending: usize,
+    pub sent: usize,
+    pub game_over: bool,
+}
+
+// A versus match against the opponent on another computer. Every side simulates its own board
+// and sends its locks and attacks to the other side, which rebuilds the board from the locks
+// with the shared seed.
+#[derive(Debug)]
+pub struct NetplayScreen {
+    pub player: GameScreen,
+    pub opponent: RemoteBoard,
+    // The opponent's game, played with the opponent's locks and the player's attacks
+    replica: TetrisEngine,
+    // The attacks sent to the opponent, and how many of them the replica has received
+    attacks: Vec<usize>,
+    delivered: usize,
+    // The attacks received from the opponent
+    received: usize,
+    connection: Connection,
+    // The opponent's board is redrawn when it's received
+    opponent_changed: bool,
+    game_over_sent: bool,
+    // The reason why the connection was lost
+    pub error: Option<io::Error>,
+}
+
+impl NetplayScreen {
+    pub fn new(config: &Config, key_releases: bool, session: Session) -> Self {
+        // Only the seed is shared, so both sides play the standard game
+        let big = config.modifiers.big;
+        let config = Config {
+            pieces: pieces::STANDARD_SET.to_string(),
+            cascade: false,
+            modifiers: Modifiers {
+                big: false,
+                ..config.modifiers
+            },
+            ..config.clone()
+        };
+        let mut player = GameScreen::new(&config, key_releases, Mode::Versus);
+        if big {
+            player.leave_out_big();
+        }
+        player.engine.set_seed(session.seed);
+        player.record_locks();
+        // Both games start the same way
+        let replica = player.engine.clone();
+        Self {
+            player,
+            opponent: RemoteBoard {
+                state: replica.get_state(),
+                ..RemoteBoard::default()
+            },
+            replica,
+            attacks: Vec::new(),
+            delivered: 0,
+            received: 0,
+            connection: session.connection,
+            opponent_changed: true,
+            game_over_sent: false,
+            error: None,
+        }
+    }
+
+    pub fn frame_end(&self) -> Instant {
+        self.player.frame_end()
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.player.engine.is_game_over() || self.opponent.game_over || self.error.is_some()
+    }
+
+    pub fn needs_render(&self) -> bool {
+        self.player.engine.changed || self.opponent_changed
+    }
+
+    // The opponent's board, the flag is reset as the board is drawn
+    pub fn take_opponent(&mut self) -> &RemoteBoard {
+        self.opponent_changed = false;
+        &self.opponent
+    }
+
+    // The game can't be paused or started over without the opponent, only left,
+    // and there are no hints against the opponent
+    pub fn handle_key(&mut self, key: &KeyEvent, keys: &KeyBindings) -> Option<GameRequest> {
+        if keys
+            .action_for(key)
+            .is_some_and(|action| matches!(action, Action::Restart | Action::Hint))
+        {
+            return None;
+        }
+        match self.player.handle_key(key, keys) {
+            Some(GameRequest::Quit) => Some(GameRequest::Quit),
+            _ => None,
+        }
+    }
+
+    // Simulates the player's frames and exchanges the locks and the attacks
+    pub fn advance(&mut self, now: Instant) {
+        self.player.advance(now);
+        if let Err(e) = self.exchange() {
+            self.error = Some(e);
+        }
+    }
+
+    fn exchange(&mut self) -> io::Result<()> {
+        let attack = self.player.engine.take_attack();
+        if attack > 0 {
+            self.connection.send(&Message::Garbage(attack))?;
+            self.attacks.push(attack);
+        }
+        // The attacks received now come after the locks which are sent now
+        for lock in self.player.take_recorded_locks() {
+            self.connection.send(&Message::Lock {
+                piece: lock.piece,
+                orientation: lock.orientation,
+                position: lock.position,
+                held: lock.held,
+                t_spin: lock.t_spin,
+                received: self.received,
+            })?;
+        }
+        if self.player.engine.is_game_over() && !self.game_over_sent {
+            self.connection.send(&Message::GameOver)?;
+            self.game_over_sent = true;
+        }
+
+        for message in self.connection.poll()? {
+            match message {
+                Message::Lock {
+                    piece,
+                    orientation,
+                    position,
+                    held,
+                    t_spin,
+                    received,
+                } => {
+                    // The attacks arrive at the opponent's game when they were received there
+                    while self.delivered < received.min(self.attacks.len()) {
+                        self.replica.receive_garbage(self.attacks[self.delivered]);
+                        self.delivered += 1;
+                    }
+                    if !self
+                        .replica
+                        .replay_lock(piece, orientation, position, held, t_spin)
+                    {
+                        return Err(io::Error::new(
+                            ErrorKind::InvalidData,
+                            "The opponent's game is out of sync",
+                        ));
+                    }
+                    // The opponent sends its own attacks
+                    self.replica.take_locks();
+                    self.replica.take_attack();
+                    self.opponent.state = self.replica.get_state();
+                    self.opponent.pending = self.replica.pending_garbage();
+                    self.opponent.sent = self.replica.attack_sent();
+                    self.opponent_changed = true;
+                }
+                Message::Garbage(lines) => {
+                    self.player.engine.receive_garbage(lines);
+                    self.received += 1;
+                }
+                Message::GameOver => self.opponent.game_over = true,
+                Message::Hello { .. } | Message::Board { .. } => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::ErrorKind, thread};
+
+    fn connect(host_hello: Message) -> (io::Result<Session>, io::Result<Session>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let host = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut connection = Connection::new(stream)?;
+            connection.send(&host_hello)?;
+            let Message::Hello { seed, .. } = host_hello else {
+                unreachable!()
+            };
+            connection.receive_hello()?;
+            Ok(Session { connection, seed })
+        });
+        let guest = join(&address);
+        (host.join().unwrap(), guest)
+    }
+
+    #[test]
+    fn players_share_the_host_seed() {
+        let (host, guest) = connect(Message::Hello {
+            version: PROTOCOL_VERSION,
+            seed: 1234,
+        });
+        assert_eq!(host.unwrap().seed, 1234);
+        assert_eq!(guest.unwrap().seed, 1234);
+    }
+
+    #[test]
+    fn version_mismatch_is_reported() {
+        let (_, guest) = connect(Message::Hello {
+            version: PROTOCOL_VERSION + 1,
+            seed: 1234,
+        });
+        assert_eq!(guest.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn attacks_are_delivered_and_disconnects_end_the_match() {
+        let (host, guest) = connect(Message::Hello {
+            version: PROTOCOL_VERSION,
+            seed: 1,
+        });
+        let config = Config::default();
+        let mut host = NetplayScreen::new(&config, false, host.unwrap());
+        let mut guest = guest.unwrap();
+        guest.connection.send(&Message::Garbage(3)).unwrap();
+        guest.connection.send(&Message::GameOver).unwrap();
+        let start = Instant::now();
+        while !host.opponent.game_over && start.elapsed().as_secs() < 5 {
+            host.advance(Instant::now());
+        }
+        assert_eq!(host.player.engine.pending_garbage(), 3);
+        assert!(host.is_over());
+        assert!(host.error.is_none());
+
+        drop(guest);
+        while host.error.is_none() && start.elapsed().as_secs() < 5 {
+            host.advance(Instant::now());
+        }
+        assert!(host.error.is_some());
+    }
+
+    #[test]
+    fn opponent_board_is_rebuilt_from_the_locks() {
+        let (host, guest) = connect(Message::Hello {
+            version: PROTOCOL_VERSION,
+            seed: 7,
+        });
+        let config = Config::default();
+        let mut host = NetplayScreen::new(&config, false, host.unwrap());
+        let mut guest = NetplayScreen::new(&config, false, guest.unwrap());
+        assert_eq!(host.opponent.state, guest.player.engine.get_state());
+
+        // The attack of the host arrives before the first lock of the guest
+        host.connection.send(&Message::Garbage(2)).unwrap();
+        host.attacks.push(2);
+        let start = Instant::now();
+        while guest.received == 0 && start.elapsed().as_secs() < 5 {
+            guest.advance(Instant::now());
+        }
+        let engine = &mut guest.player.engine;
+        engine.hold();
+        engine.rotate();
+        engine.move_current_shape(-1, 0);
+        engine.hard_drop();
+        engine.rotate_ccw();
+        engine.hard_drop();
+        let board = |state: &GameState| (state.playfield.clone(), state.colors.clone());
+        let expected = board(&guest.player.engine.get_state());
+        while board(&host.opponent.state) != expected && start.elapsed().as_secs() < 5 {
+            guest.advance(Instant::now());
+            host.advance(Instant::now());
+        }
+        assert!(host.error.is_none());
+        assert_eq!(board(&host.opponent.state), expected);
+        assert_eq!(
+            host.opponent.state.hold_piece,
+            guest.player.engine.get_state().hold_piece
+        );
+        assert_eq!(guest.player.engine.garbage_rows(), 2);
+    }
+}
@@ -0,0 +1,452 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, ErrorKind},
+    path::PathBuf,
+};
+
+use crate::config::{self, invalid_data};
+use crate::finesse::Verdict;
+use crate::fumen::{self, Operation};
+use crate::modes::Ruleset;
+use crate::pieces::{PIECE_SIZE, Shape};
+use crate::tetris_engine::{
+    Lock, Rules, Setup, TetrisEngine, piece_by_name, piece_cells, piece_name,
+};
+
+const BUILTIN_OPENERS: [(&str, &str); 2] = [
+    ("1-tki", include_str!("../openers/1-tki.toml")),
+    ("2-pco", include_str!("../openers/2-pco.toml")),
+];
+
+const FULL_ROW: u16 = 0b11_1111_1111;
+
+// The piece and the position which give the cells, if they make a single piece
+fn piece_at(cells: &[u16; 20]) -> Option<(Shape, [u8; 2])> {
+    let top = cells.iter().position(|&row| row != 0)?;
+    let left = cells.iter().map(|row| row.leading_zeros() - 6).min()?;
+    let shape = (0..PIECE_SIZE)
+        .filter(|i| top + i < 20)
+        .map(|i| (cells[top + i] << left >> (10 - PIECE_SIZE) & 0x1F) as Shape)
+        .enumerate()
+        .map(|(i, row)| row << (i * PIECE_SIZE))
+        .sum();
+    let position = [left as u8, top as u8];
+    (piece_cells(shape, position) == *cells).then_some((shape, position))
+}
+
+// The opener: the pieces in the order they are placed and where each of them goes
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Opener {
+    pub name: String,
+    pub setup: Setup,
+    steps: Vec<Operation>,
+}
+
+impl Opener {
+    // The opener file has the `name` and either the `board` rows from the top, which sit
+    // at the bottom of the playfield, together with the order of the `pieces`, or a `fumen`.
+    // The rows have the letter of the piece in each of its cells, `#` for the blocks
+    // which are there from the start and `.` for the empty cells, e.g.
+    //
+    //     pieces = "IO"
+    //     board = "OO........"
+    //     board = "OOIIII...."
+    //
+    // The fumen is needed when the same piece is placed twice or the lines are cleared:
+    // every page is a placement.
+    pub fn parse(id: &str, text: &str) -> io::Result<Opener> {
+        let mut name = id.to_string();
+        let mut pieces = None;
+        let mut rows = Vec::new();
+        let mut pages = None;
+
+        for entry in config::parse_toml(text)? {
+            if entry.key == "board" {
+                for row in &entry.values {
+                    if row.chars().count() != 10 {
+                        return Err(invalid_data(entry.line, "a board row is 10 cells"));
+                    }
+                    rows.push(row.to_ascii_uppercase());
+                }
+                continue;
+            }
+            let [value] = entry.values.as_slice() else {
+                return Err(invalid_data(entry.line, "expected a single value"));
+            };
+            match entry.key.as_str() {
+                "name" => name = value.clone(),
+                "pieces" => pieces = Some((entry.line, value.to_ascii_uppercase())),
+                "fumen" => {
+                    let decoded = fumen::decode(value)
+                        .map_err(|e| invalid_data(entry.line, &e.to_string()))?;
+                    pages = Some(decoded);
+                }
+                key => return Err(invalid_data(entry.line, &format!("unknown key `{}`", key))),
+            }
+        }
+
+        let error = |message: &str| io::Error::new(ErrorKind::InvalidData, message);
+        let (setup, steps) = match (pages, pieces) {
+            (Some(_), _) if !rows.is_empty() => {
+                return Err(error("the opener is either `board` rows or a `fumen`"));
+            }
+            (Some(_), Some((line, _))) => {
+                return Err(invalid_data(
+                    line,
+                    "the fumen gives the order of the pieces",
+                ));
+            }
+            (Some(pages), None) => {
+                let steps = pages.iter().filter_map(|page| page.operation).collect();
+                (fumen::setup(&pages), steps)
+            }
+            (None, None) => return Err(error("the opener has no `pieces`")),
+            (None, Some((line, pieces))) => Self::parse_board(&rows, line, &pieces)?,
+        };
+        if steps.is_empty() {
+            return Err(error("the opener has no pieces"));
+        }
+        Ok(Opener { name, setup, steps })
+    }
+
+    // The letters of the rows are the cells of the pieces, placed in the order of `pieces`
+    fn parse_board(
+        rows: &[String],
+        line: usize,
+        pieces: &str,
+    ) -> io::Result<(Setup, Vec<Operation>)> {
+        if rows.len() > 20 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "the board has more than 20 rows",
+            ));
+        }
+        let mut setup = Setup {
+            playfield: vec![0; 20],
+            ..Setup::default()
+        };
+        let mut cells: HashMap<char, [u16; 20]> = HashMap::new();
+        let top = 20 - rows.len();
+        for (i, row) in rows.iter().enumerate() {
+            for (x, cell) in row.chars().enumerate() {
+                let bit = 1 << (9 - x);
+                match cell {
+                    '.' => {}
+                    '#' => setup.playfield[top + i] |= bit,
+                    name => cells.entry(name).or_default()[top + i] |= bit,
+                }
+            }
+        }
+
+        let mut steps = Vec::new();
+        for name in pieces.chars() {
+            let piece = piece_by_name(name)
+                .ok_or_else(|| invalid_data(line, &format!("unknown piece `{}`", name)))?;
+            let placed = cells.remove(&name).ok_or_else(|| {
+                invalid_data(
+                    line,
+                    &format!("`{}` isn't on the board, or it's in the pieces twice", name),
+                )
+            })?;
+            if piece_at(&placed).and_then(|(shape, _)| piece_name(shape)) != Some(name) {
+                return Err(invalid_data(
+                    line,
+                    &format!("the cells of `{}` aren't the piece", name),
+                ));
+            }
+            steps.push(Operation {
+                piece,
+                cells: placed,
+            });
+        }
+        if let Some(name) = cells.keys().next() {
+            return Err(invalid_data(
+                line,
+                &format!("`{}` is on the board but not in the pieces", name),
+            ));
+        }
+        setup.queue = steps.iter().map(|step| step.piece).collect();
+        Ok((setup, steps))
+    }
+}
+
+// The user's openers are the files in `openers` of the data directory
+fn openers_dir() -> Option<PathBuf> {
+    Some(config::data_dir()?.join("openers"))
+}
+
+// The built-in openers and the user's ones, with the errors of the files which couldn't be read
+pub fn openers() -> (Vec<Opener>, Vec<String>) {
+    let mut openers: Vec<Opener> = BUILTIN_OPENERS
+        .iter()
+        .filter_map(|(id, text)| Opener::parse(id, text).ok())
+        .collect();
+    let mut errors = Vec::new();
+    let Some(dir) = openers_dir() else {
+        return (openers, errors);
+    };
+    let files = match config::toml_files(&dir) {
+        Ok(files) => files,
+        Err(e) if e.kind() == ErrorKind::NotFound => return (openers, errors),
+        Err(e) => {
+            errors.push(format!("Couldn't read {}: {}", dir.display(), e));
+            return (openers, errors);
+        }
+    };
+    for (id, path) in files {
+        match fs::read_to_string(&path).and_then(|text| Opener::parse(&id, &text)) {
+            Ok(opener) => openers.push(opener),
+            Err(e) => errors.push(format!("Couldn't read {}: {}", path.display(), e)),
+        }
+    }
+    (openers, errors)
+}
+
+// The opener practice: the pieces come in the order of the opener and the next
+// placements are outlined. A piece placed off the guide is taken back and comes again.
+#[derive(Debug, Default)]
+pub struct Guide {
+    opener: Opener,
+    // The placement which is expected next
+    step: usize,
+    // The board with the placements done so far
+    board: Vec<u16>,
+    misses: usize,
+    // Whether the last piece matched the guide
+    last: Option<bool>,
+}
+
+impl Guide {
+    pub fn new(opener: Opener) -> Self {
+        Self {
+            opener,
+            ..Self::default()
+        }
+    }
+}
+
+impl Ruleset for Guide {
+    fn rules(&self) -> Rules {
+        Rules {
+            lines_per_level: None,
+            hold: false,
+            ..Rules::default()
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new(std::mem::take(&mut self.opener));
+    }
+
+    fn setup(&mut self, engine: &mut TetrisEngine) {
+        engine.load(&self.opener.setup);
+        self.board = engine.get_state().playfield;
+    }
+
+    fn on_lock(&mut self, engine: &mut TetrisEngine, lock: &Lock, _verdict: &Verdict) {
+        let Some(expected) = self.opener.steps.get(self.step) else {
+            return;
+        };
+        let matches = piece_cells(lock.shape, lock.position) == expected.cells;
+        self.last = Some(matches);
+        if matches {
+            self.step += 1;
+            self.board = engine.get_state().playfield;
+            if self.step == self.opener.steps.len() {
+                engine.stop();
+            }
+        } else {
+            self.misses += 1;
+            let queue = self.opener.steps[self.step..]
+                .iter()
+                .map(|step| step.piece)
+                .collect();
+            engine.load(&Setup {
+                playfield: self.board.clone(),
+                queue,
+                ..Setup::default()
+            });
+        }
+    }
+
+    // The next placements up to the one which clears lines, the cells after it move down
+    fn overlay(&self) -> Vec<u16> {
+        let mut overlay = vec![0; 20];
+        let mut board = self.board.clone();
+        for step in &self.opener.steps[self.step..] {
+            for ((overlay, board), cells) in overlay.iter_mut().zip(&mut board).zip(step.cells) {
+                *overlay |= cells;
+                *board |= cells;
+            }
+            if board.contains(&FULL_ROW) {
+                break;
+            }
+        }
+        overlay
+    }
+
+    fn hud(&self, _engine: &TetrisEngine, _frame: u64) -> Vec<String> {
+        let mut hud = vec![
+            String::from("Opener"),
+            self.opener.name.clone(),
+            String::new(),
+            String::from("Placed"),
+            format!("{}/{}", self.step, self.opener.steps.len()),
+            String::new(),
+            String::from("Misses"),
+            self.misses.to_string(),
+        ];
+        match self.last {
+            Some(true) => hud.extend([String::new(), String::from("On the guide")]),
+            Some(false) => hud.extend([String::new(), String::from("Off the guide")]),
+            None => {}
+        }
+        hud
+    }
+
+    fn finish(&mut self, _engine: &TetrisEngine, _standard: bool) -> (String, Vec<String>) {
+        let info = vec![self.opener.name.clone(), format!("Misses: {}", self.misses)];
+        (String::from("Opener complete"), info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builtin(name: &str) -> Opener {
+        let (id, text) = BUILTIN_OPENERS
+            .into_iter()
+            .find(|(id, _)| *id == name)
+            .unwrap();
+        Opener::parse(id, text).unwrap()
+    }
+
+    #[test]
+    fn board_letters_are_the_placements() {
+        let text = "pieces = \"IO\"\nboard = \"OO.......#\"\nboard = \"OOIIII...#\"";
+        let opener = Opener::parse("01", text).unwrap();
+        assert_eq!(opener.name, "01");
+        assert_eq!(
+            opener.setup.queue,
+            vec![piece_by_name('I').unwrap(), piece_by_name('O').unwrap()]
+        );
+        assert_eq!(opener.setup.playfield[18..], [0b1, 0b1]);
+        assert_eq!(opener.steps[0].cells[19], 0b00_1111_0000);
+        assert_eq!(opener.steps[1].cells[18..], [0b11_0000_0000; 2]);
+    }
+
+    #[test]
+    fn opener_errors_report_the_line() {
+        let error = |text: &str| Opener::parse("01", text).unwrap_err().to_string();
+        assert_eq!(
+            error("pieces = \"IO\"\nboard = \"IIII......\""),
+            "line 1: `O` isn't on the board, or it's in the pieces twice"
+        );
+        assert_eq!(
+            error("pieces = \"I\"\nboard = \"III.I.....\""),
+            "line 1: the cells of `I` aren't the piece"
+        );
+        assert_eq!(
+            error("pieces = \"I\"\nboard = \"IIIIO.....\""),
+            "line 1: `O` is on the board but not in the pieces"
+        );
+        assert_eq!(
+            error("board = \"IIII......\""),
+            "the opener has no `pieces`"
+        );
+    }
+
+    #[test]
+    fn builtin_openers_are_valid() {
+        for (id, text) in BUILTIN_OPENERS {
+            assert!(Opener::parse(id, text).is_ok(), "{}", id);
+        }
+    }
+
+    // Drops the active piece where it covers the cells, if it can get there. The piece
+    // can be turned after it lands, the T pieces spin into their slots when they can.
+    fn place(engine: &mut TetrisEngine, cells: &[u16; 20]) -> Option<Lock> {
+        let mut placed: Option<(TetrisEngine, Lock)> = None;
+        for turn in 0..3 {
+            for rotations in 0..4 {
+                for x in 0..10 {
+                    let mut test = engine.clone();
+                    (0..rotations).for_each(|_| test.rotate());
+                    (0..10).for_each(|_| test.move_current_shape(-1, 0));
+                    (0..x).for_each(|_| test.move_current_shape(1, 0));
+                    if turn > 0 {
+                        (0..20).for_each(|_| test.move_current_shape(0, 1));
+                        if turn == 1 {
+                            test.rotate_ccw()
+                        } else {
+                            test.rotate()
+                        }
+                    }
+                    test.hard_drop();
+                    let lock = test.take_locks().pop()?;
+                    let better = placed
+                        .as_ref()
+                        .is_none_or(|(_, best)| lock.t_spin && !best.t_spin);
+                    if piece_cells(lock.shape, lock.position) == *cells && better {
+                        placed = Some((test, lock));
+                    }
+                }
+            }
+        }
+        let (test, lock) = placed?;
+        *engine = test;
+        Some(lock)
+    }
+
+    #[test]
+    fn builtin_openers_can_be_placed() {
+        for (id, _) in BUILTIN_OPENERS {
+            let mut guide = Guide::new(builtin(id));
+            let mut engine = TetrisEngine::with_rules(guide.rules());
+            guide.setup(&mut engine);
+            let mut t_spins = Vec::new();
+            for step in guide.opener.steps.clone() {
+                let lock = place(&mut engine, &step.cells);
+                assert!(lock.is_some(), "{}", id);
+                t_spins.extend(lock.filter(|lock| lock.t_spin).map(|lock| lock.cleared));
+            }
+            let expected: &[usize] = if id == "2-pco" { &[] } else { &[2] };
+            assert_eq!(t_spins, expected, "{}", id);
+            if id == "2-pco" {
+                assert_eq!(engine.get_state().playfield, [0; 20]);
+            }
+        }
+    }
+
+    #[test]
+    fn piece_off_the_guide_comes_again() {
+        let mut guide = Guide::new(builtin("2-pco"));
+        let mut engine = TetrisEngine::with_rules(guide.rules());
+        guide.setup(&mut engine);
+        let first = guide.opener.steps[0];
+        assert_eq!(guide.overlay(), {
+            let mut all = [0; 20];
+            for step in &guide.opener.steps {
+                (0..20).for_each(|y| all[y] |= step.cells[y]);
+            }
+            all
+        });
+
+        // The J goes to the right wall instead of the left one
+        (0..10).for_each(|_| engine.move_current_shape(1, 0));
+        engine.hard_drop();
+        for lock in engine.take_locks() {
+            guide.on_lock(&mut engine, &lock, &Verdict::Unchecked);
+        }
+        assert_eq!((guide.step, guide.misses, guide.last), (0, 1, Some(false)));
+        assert_eq!(engine.get_state().playfield, [0; 20]);
+        assert_eq!(engine.get_state().active_piece, first.piece);
+
+        let lock = place(&mut engine, &first.cells).unwrap();
+        guide.on_lock(&mut engine, &lock, &Verdict::Unchecked);
+        assert_eq!((guide.step, guide.misses, guide.last), (1, 1, Some(true)));
+        assert_eq!(guide.board, first.cells);
+    }
+}
@@ -0,0 +1,405 @@
+use std::collections::HashSet;
+
+use crate::fumen::Operation;
+use crate::pieces::{self, PIECE_SIZE, shape_row};
+use crate::tetris_engine::{KICKS, piece_rotations};
+
+// The most lines the perfect clear can take, the board of that many lines fits in a `u64`
+pub const MAX_LINES: usize = 6;
+
+const WIDTH: usize = 10;
+const FULL_ROW: u64 = (1 << WIDTH) - 1;
+// The piece is turned and moved above the board, the rows there are empty
+const SKY: usize = 4;
+const LEFT_COLUMN: u64 = column(0);
+const RIGHT_COLUMN: u64 = column(WIDTH - 1);
+
+const fn column(x: usize) -> u64 {
+    let mut mask = 0;
+    let mut row = 0;
+    while row < MAX_LINES {
+        mask |= 1 << (row * WIDTH + x);
+        row += 1;
+    }
+    mask
+}
+
+// The pieces the solver can use: the active one, the held one and the ones which come next,
+// all of them as they spawn
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pieces {
+    pub active: pieces::Shape,
+    pub hold: Option<pieces::Shape>,
+    pub queue: Vec<pieces::Shape>,
+    pub can_hold: bool,
+}
+
+// The piece in one orientation: the cells from its top left corner, one bit per cell
+// as on the board, and its size
+#[derive(Debug, Clone, Copy)]
+struct Shape {
+    cells: u128,
+    width: usize,
+    height: usize,
+}
+
+impl Shape {
+    fn new(mask: pieces::Shape) -> Self {
+        let mut shape = Shape {
+            cells: 0,
+            width: 0,
+            height: 0,
+        };
+        for row in 0..PIECE_SIZE {
+            for x in 0..PIECE_SIZE {
+                if shape_row(mask, row) >> (PIECE_SIZE - 1 - x) & 1 != 0 {
+                    shape.cells |= 1 << (row * WIDTH + x);
+                    shape.width = shape.width.max(x + 1);
+                    shape.height = shape.height.max(row + 1);
+                }
+            }
+        }
+        shape
+    }
+}
+
+// The lines which have to be cleared: bit `x` of row `y` is the cell in the column `x`,
+// row 0 is the top one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Board {
+    cells: u64,
+    lines: usize,
+}
+
+impl Board {
+    // The bottom lines of the playfield, if nothing is above them
+    fn from_playfield(playfield: &[u16; 20], lines: usize) -> Option<Board> {
+        let top = 20 - lines;
+        if playfield[..top].iter().any(|&row| row != 0) {
+            return None;
+        }
+        let mut cells = 0;
+        for (y, row) in playfield[top..].iter().enumerate() {
+            for x in 0..WIDTH {
+                if row & (1 << (WIDTH - 1 - x)) != 0 {
+                    cells |= 1 << (y * WIDTH + x);
+                }
+            }
+        }
+        Some(Board { cells, lines })
+    }
+
+    // The piece on the playfield, the lines are at the bottom of it
+    fn to_playfield(self, piece: u64) -> [u16; 20] {
+        let mut playfield = [0; 20];
+        let top = 20 - self.lines;
+        for (y, row) in playfield[top..].iter_mut().enumerate() {
+            for x in 0..WIDTH {
+                if piece & (1 << (y * WIDTH + x)) != 0 {
+                    *row |= 1 << (WIDTH - 1 - x);
+                }
+            }
+        }
+        playfield
+    }
+
+    fn empty_cells(&self) -> usize {
+        self.lines * WIDTH - self.cells.count_ones() as usize
+    }
+
+    // Locks the piece and removes the full lines
+    fn place(self, piece: u64) -> Board {
+        let mut board = Board {
+            cells: self.cells | piece,
+            ..self
+        };
+        let mut y = 0;
+        while y < board.lines {
+            if board.cells >> (y * WIDTH) & FULL_ROW == FULL_ROW {
+                let above = (1 << (y * WIDTH)) - 1;
+                board.cells = board.cells & above | board.cells >> WIDTH & !above;
+                board.lines -= 1;
+            } else {
+                y += 1;
+            }
+        }
+        board
+    }
+
+    // Every separate area of the empty cells can be filled with whole pieces
+    fn is_fillable(&self) -> bool {
+        let mut empty = !self.cells & ((1 << (self.lines * WIDTH)) - 1);
+        while empty != 0 {
+            let mut area = empty & empty.wrapping_neg();
+            loop {
+                let grown = (area
+                    | (area << 1 & !LEFT_COLUMN)
+                    | (area >> 1 & !RIGHT_COLUMN)
+                    | area << WIDTH
+                    | area >> WIDTH)
+                    & empty;
+                if grown == area {
+                    break;
+                }
+                area = grown;
+            }
+            if !area.count_ones().is_multiple_of(4) {
+                return false;
+            }
+            empty &= !area;
+        }
+        true
+    }
+
+    // Where the piece can be locked, found by moving and turning it the way the engine does,
+    // from anywhere above the board
+    fn placements(&self, shapes: &[Shape; 4]) -> Vec<u64> {
+        let rows = SKY + self.lines;
+        let board = (self.cells as u128) << (SKY * WIDTH);
+        let cells = |turn: usize, x: isize, y: usize| {
+            let shape = &shapes[turn];
+            let x = usize::try_from(x).ok()?;
+            if x + shape.width > WIDTH || y + shape.height > rows {
+                return None;
+            }
+            let cells = shape.cells << (y * WIDTH + x);
+            (cells & board == 0).then_some(cells)
+        };
+
+        let mut seen = [[[false; SKY + MAX_LINES]; WIDTH]; 4];
+        let mut stack = Vec::new();
+        for (turn, seen) in seen.iter_mut().enumerate() {
+            for (x, seen) in seen.iter_mut().enumerate() {
+                if cells(turn, x as isize, 0).is_some() {
+                    seen[0] = true;
+                    stack.push((turn, x, 0));
+                }
+            }
+        }
+        let mut placements = Vec::new();
+        while let Some((turn, x, y)) = stack.pop() {
+            let x = x as isize;
+            let mut moves = vec![(turn, x - 1, y), (turn, x + 1, y), (turn, x, y + 1)];
+            for quarter_turns in 1..4 {
+                let turned = (turn + quarter_turns) % 4;
+                if let Some(dx) = KICKS
+                    .into_iter()
+                    .find(|dx| cells(turned, x + dx, y).is_some())
+                {
+                    moves.push((turned, x + dx, y));
+                }
+            }
+            for (turn, x, y) in moves {
+                if cells(turn, x, y).is_some() && !seen[turn][x as usize][y] {
+                    seen[turn][x as usize][y] = true;
+                    stack.push((turn, x as usize, y));
+                }
+            }
+
+            if cells(turn, x, y + 1).is_none() && y >= SKY {
+                let piece = (cells(turn, x, y).unwrap_or(0) >> (SKY * WIDTH)) as u64;
+                if !placements.contains(&piece) {
+                    placements.push(piece);
+                }
+            }
+        }
+        placements
+    }
+}
+
+// The depth-first search over the placements of the pieces in turn
+struct Solver<'a> {
+    pieces: &'a Pieces,
+    shapes: Vec<(pieces::Shape, [Shape; 4])>,
+    // The states which don't lead to the perfect clear
+    failed: HashSet<(Board, Option<pieces::Shape>, Option<pieces::Shape>, usize)>,
+    // The pieces placed so far and the boards they are placed on
+    steps: Vec<(pieces::Shape, Board, u64)>,
+}
+
+impl Solver<'_> {
+    fn shapes(&self, piece: pieces::Shape) -> Option<&[Shape; 4]> {
+        self.shapes
+            .iter()
+            .find(|(p, _)| *p == piece)
+            .map(|(_, shapes)| shapes)
+    }
+
+    // The current piece is either placed or held, then the held piece or the next one is placed
+    fn solve(
+        &mut self,
+        board: Board,
+        current: Option<pieces::Shape>,
+        hold: Option<pieces::Shape>,
+        next: usize,
+    ) -> bool {
+        if board.lines == 0 {
+            return true;
+        }
+        let left = usize::from(current.is_some())
+            + usize::from(hold.is_some() && self.pieces.can_hold)
+            + self.pieces.queue.len().saturating_sub(next);
+        if board.empty_cells() > left * 4 || !self.failed.insert((board, current, hold, next)) {
+            return false;
+        }
+
+        let queue = &self.pieces.queue;
+        let mut choices = Vec::new();
+        if let Some(piece) = current {
+            choices.push((piece, hold, queue.get(next).copied(), next + 1));
+        }
+        if self.pieces.can_hold {
+            match hold {
+                Some(held) if hold != current => {
+                    choices.push((held, current, queue.get(next).copied(), next + 1))
+                }
+                None => {
+                    if let (Some(&piece), Some(_)) = (queue.get(next), current) {
+                        choices.push((piece, current, queue.get(next + 1).copied(), next + 2));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for (piece, hold, current, next) in choices {
+            let Some(shapes) = self.shapes(piece).copied() else {
+                continue;
+            };
+            for placement in board.placements(&shapes) {
+                let placed = board.place(placement);
+                if !placed.is_fillable() {
+                    continue;
+                }
+                self.steps.push((piece, board, placement));
+                if self.solve(placed, current, hold, next) {
+                    return true;
+                }
+                self.steps.pop();
+            }
+        }
+        false
+    }
+}
+
+// The placements which empty the board within the given number of lines, in the order
+// the pieces are locked. The cells of each placement are on the board as it is
+// at the moment, the lines cleared before it are gone. The fewest lines are tried first.
+pub fn find(playfield: &[u16; 20], pieces: &Pieces, max_lines: usize) -> Option<Vec<Operation>> {
+    let all = [pieces.active]
+        .into_iter()
+        .chain(pieces.hold)
+        .chain(pieces.queue.iter().copied());
+    let mut shapes: Vec<(pieces::Shape, [Shape; 4])> = Vec::new();
+    for piece in all {
+        if shapes.iter().all(|(p, _)| *p != piece) {
+            shapes.push((piece, piece_rotations(piece)?.map(Shape::new)));
+        }
+    }
+
+    let filled: usize = playfield.iter().map(|row| row.count_ones() as usize).sum();
+    for lines in 1..=max_lines.min(MAX_LINES) {
+        let Some(board) = Board::from_playfield(playfield, lines) else {
+            continue;
+        };
+        if !(lines * WIDTH - filled).is_multiple_of(4) || !board.is_fillable() {
+            continue;
+        }
+        let mut solver = Solver {
+            pieces,
+            shapes: shapes.clone(),
+            failed: HashSet::new(),
+            steps: Vec::new(),
+        };
+        if solver.solve(board, Some(pieces.active), pieces.hold, 0) {
+            let steps = solver
+                .steps
+                .into_iter()
+                .map(|(piece, board, placement)| Operation {
+                    piece,
+                    cells: board.to_playfield(placement),
+                });
+            return Some(steps.collect());
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tetris_engine::piece_by_name;
+
+    fn playfield(rows: &[&str]) -> [u16; 20] {
+        let mut playfield = [0; 20];
+        for (row, text) in playfield[20 - rows.len()..].iter_mut().zip(rows) {
+            *row = u16::from_str_radix(&text.replace('#', "1").replace('.', "0"), 2).unwrap();
+        }
+        playfield
+    }
+
+    fn pieces(names: &str, hold: Option<char>) -> Pieces {
+        let mut names = names.chars().map(|name| piece_by_name(name).unwrap());
+        Pieces {
+            active: names.next().unwrap(),
+            hold: hold.and_then(piece_by_name),
+            queue: names.collect(),
+            can_hold: true,
+        }
+    }
+
+    // Locks the placements in turn and checks that nothing is left
+    fn assert_clears(playfield: &[u16; 20], steps: &[Operation]) {
+        let mut board = *playfield;
+        for step in steps {
+            for (row, cells) in board.iter_mut().zip(step.cells) {
+                assert_eq!(*row & cells, 0);
+                *row |= cells;
+            }
+            let rows: Vec<u16> = board.into_iter().filter(|&row| row != 0x3FF).collect();
+            board = [0; 20];
+            board[20 - rows.len()..].copy_from_slice(&rows);
+        }
+        assert_eq!(board, [0; 20]);
+    }
+
+    #[test]
+    fn the_hold_is_used_when_the_active_piece_does_not_fit() {
+        let board = playfield(&["######....", "######...."]);
+        let steps = find(&board, &pieces("TOO", None), 2).unwrap();
+        assert_clears(&board, &steps);
+        let o = piece_by_name('O').unwrap();
+        assert_eq!(
+            steps.iter().map(|step| step.piece).collect::<Vec<_>>(),
+            [o, o]
+        );
+
+        let no_hold = Pieces {
+            can_hold: false,
+            ..pieces("TOO", None)
+        };
+        assert_eq!(find(&board, &no_hold, 2), None);
+    }
+
+    #[test]
+    fn four_lines_are_cleared_from_the_empty_board() {
+        let board = [0; 20];
+        let steps = find(&board, &pieces("ILJOSZTILJ", None), 4).unwrap();
+        assert_clears(&board, &steps);
+        // The column on the left is 4 lines high
+        let board = playfield(&["#.........", "#.........", "#.........", "#........."]);
+        let steps = find(&board, &pieces("ILJOSZTIL", Some('T')), 4).unwrap();
+        assert_eq!(steps.len(), 9);
+        assert_clears(&board, &steps);
+    }
+
+    #[test]
+    fn the_covered_hole_has_no_perfect_clear() {
+        // The O can't fill the hole even after the line above it is cleared
+        let board = playfield(&["###.......", "#.########"]);
+        assert_eq!(find(&board, &pieces("OOOOOOOOOOOOOOO", Some('O')), 6), None);
+        // The cells above the lines can't be cleared within them
+        let board = playfield(&["##........", ".........."]);
+        assert_eq!(find(&board, &pieces("IIOO", None), 1), None);
+    }
+}
@@ -0,0 +1,488 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::PathBuf,
+    sync::{Arc, LazyLock},
+};
+
+use crate::config::{self, invalid_data};
+
+// The `u32` integer encodes a piece in one orientation within a 5x5 square: row `i` from
+// the top is in the bits `5 * i..5 * i + 5` and bit 4 of the row is the leftmost column.
+// The cells are aligned to the top-left corner of the square.
+//
+// Example: the L piece as it spawns
+//
+//  ###   →  0b11100  (row 0)
+//  #..   →  0b10000  (row 1)
+//
+//  0b_00000_00000_00000_10000_11100
+pub type Shape = u32;
+
+pub const PIECE_SIZE: usize = 5;
+const ROW_MASK: Shape = (1 << PIECE_SIZE) - 1;
+
+// The row of the shape, bit `PIECE_SIZE - 1` is the leftmost column
+pub fn shape_row(shape: Shape, i: usize) -> Shape {
+    shape >> (i * PIECE_SIZE) & ROW_MASK
+}
+
+// The columns and the rows the shape takes from its top-left corner
+pub fn shape_size(shape: Shape) -> (usize, usize) {
+    (0..PIECE_SIZE)
+        .filter(|&i| shape_row(shape, i) != 0)
+        .fold((0, 0), |(width, _), i| {
+            let row = shape_row(shape, i);
+            (width.max(PIECE_SIZE - row.trailing_zeros() as usize), i + 1)
+        })
+}
+
+// Moves the cells to the top-left corner of the square
+fn aligned(shape: Shape) -> Shape {
+    if shape == 0 {
+        return 0;
+    }
+    let top = (0..PIECE_SIZE)
+        .find(|&i| shape_row(shape, i) != 0)
+        .unwrap_or(0);
+    let shape = shape >> (top * PIECE_SIZE);
+    let left = (0..PIECE_SIZE)
+        .map(|i| shape_row(shape, i).leading_zeros() as usize - (32 - PIECE_SIZE))
+        .min()
+        .unwrap_or(0);
+    (0..PIECE_SIZE)
+        .map(|i| (shape_row(shape, i) << left & ROW_MASK) << (i * PIECE_SIZE))
+        .sum()
+}
+
+// The shape turned clockwise
+fn rotated(shape: Shape) -> Shape {
+    let (_, height) = shape_size(shape);
+    let mut turned = 0;
+    for y in 0..height {
+        for x in 0..PIECE_SIZE {
+            if shape_row(shape, y) >> (PIECE_SIZE - 1 - x) & 1 == 1 {
+                // The column `x` becomes the row `x`, the bottom row becomes the left column
+                turned |= 1 << (x * PIECE_SIZE + PIECE_SIZE - 1 - (height - 1 - y));
+            }
+        }
+    }
+    aligned(turned)
+}
+
+// The rows of `#` and `.` in the piece file, e.g. [".#.", "###"]
+fn parse_shape(rows: &[String]) -> Option<Shape> {
+    if rows.is_empty() || rows.len() > PIECE_SIZE {
+        return None;
+    }
+    let mut shape = 0;
+    for (i, row) in rows.iter().enumerate() {
+        if row.chars().count() > PIECE_SIZE {
+            return None;
+        }
+        for (x, cell) in row.chars().enumerate() {
+            match cell {
+                '.' => {}
+                '#' => shape |= 1 << (i * PIECE_SIZE + PIECE_SIZE - 1 - x),
+                _ => return None,
+            }
+        }
+    }
+    (shape != 0).then(|| aligned(shape))
+}
+
+// The colors the cells are drawn with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Color {
+    Red,
+    Orange,
+    Yellow,
+    Green,
+    Cyan,
+    Blue,
+    Purple,
+    Brown,
+    // The cells which aren't a part of any piece, e.g. the garbage
+    #[default]
+    Black,
+}
+
+impl Color {
+    pub const ALL: [Color; 9] = [
+        Color::Red,
+        Color::Orange,
+        Color::Yellow,
+        Color::Green,
+        Color::Cyan,
+        Color::Blue,
+        Color::Purple,
+        Color::Brown,
+        Color::Black,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Color::Red => "red",
+            Color::Orange => "orange",
+            Color::Yellow => "yellow",
+            Color::Green => "green",
+            Color::Cyan => "cyan",
+            Color::Blue => "blue",
+            Color::Purple => "purple",
+            Color::Brown => "brown",
+            Color::Black => "black",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Color> {
+        Color::ALL.into_iter().find(|color| color.name() == name)
+    }
+}
+
+// The default horizontal shifts which are tried in turn when a rotated piece doesn't fit
+const DEFAULT_KICKS: [isize; 5] = [0, -1, 1, -2, 2];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Piece {
+    pub name: char,
+    // The orientations N, E, S and W, the piece spawns in the first one
+    pub rotations: [Shape; 4],
+    // The shift of the spawn position from the middle of the top row, the rows go down
+    pub spawn: [i8; 2],
+    pub kicks: Vec<isize>,
+    pub color: Color,
+}
+
+impl Piece {
+    // The settings of the piece section of the file, e.g. `[T]`
+    fn parse(name: char, entries: &[&config::Entry]) -> io::Result<Piece> {
+        let mut piece = Piece {
+            name,
+            rotations: [0; 4],
+            spawn: [0, 0],
+            kicks: DEFAULT_KICKS.to_vec(),
+            // The color of the original game
+            color: Color::Orange,
+        };
+        let mut rotations = [None; 4];
+        for entry in entries {
+            let value = || match entry.values.as_slice() {
+                [value] => Ok(value.as_str()),
+                _ => Err(invalid_data(entry.line, "expected a single value")),
+            };
+            let numbers = || {
+                entry
+                    .values
+                    .iter()
+                    .map(|value| value.parse::<isize>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| invalid_data(entry.line, "expected a list of numbers"))
+            };
+            match entry.key.as_str() {
+                "color" => {
+                    piece.color = Color::from_name(value()?)
+                        .ok_or_else(|| invalid_data(entry.line, "unknown color"))?;
+                }
+                "spawn" => match numbers()?.as_slice() {
+                    &[x, y] if (-4..=4).contains(&x) && (0..=4).contains(&y) => {
+                        piece.spawn = [x as i8, y as i8];
+                    }
+                    _ => {
+                        return Err(invalid_data(
+                            entry.line,
+                            "the spawn is `[x, y]`, x within 4 cells and y from 0 to 4",
+                        ));
+                    }
+                },
+                "kicks" => {
+                    piece.kicks = numbers()?;
+                    if piece.kicks.is_empty() {
+                        return Err(invalid_data(entry.line, "the piece needs a kick"));
+                    }
+                }
+                key @ ("n" | "e" | "s" | "w") => {
+                    let index = ["n", "e", "s", "w"].iter().position(|k| *k == key);
+                    let shape = parse_shape(&entry.values).ok_or_else(|| {
+                        invalid_data(
+                            entry.line,
+                            "the cells are up to 5 rows of up to 5 `#` or `.` each",
+                        )
+                    })?;
+                    rotations[index.unwrap_or(0)] = Some(shape);
+                }
+                key => return Err(invalid_data(entry.line, &format!("unknown key `{}`", key))),
+            }
+        }
+
+        // The missing orientations are the clockwise turns of the previous ones
+        let Some(mut shape) = rotations[0] else {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("`{}` has no `n` cells", name),
+            ));
+        };
+        for (rotation, given) in piece.rotations.iter_mut().zip(rotations) {
+            shape = given.unwrap_or_else(|| rotated(shape));
+            *rotation = shape;
+        }
+        let cells = piece.rotations[0].count_ones();
+        if piece
+            .rotations
+            .iter()
+            .any(|shape| shape.count_ones() != cells)
+        {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("the orientations of `{}` have different cells", name),
+            ));
+        }
+        Ok(piece)
+    }
+}
+
+// The built-in sets, the first one is the standard one
+const BUILTIN_SETS: [(&str, &str); 4] = [
+    ("tetrominoes", include_str!("../pieces/tetrominoes.toml")),
+    ("pentominoes", include_str!("../pieces/pentominoes.toml")),
+    (
+        "tetrominoes-monomino",
+        include_str!("../pieces/tetrominoes-monomino.toml"),
+    ),
+    (
+        "tetrominoes-domino",
+        include_str!("../pieces/tetrominoes-domino.toml"),
+    ),
+];
+
+pub const STANDARD_SET: &str = BUILTIN_SETS[0].0;
+
+static STANDARD: LazyLock<Arc<PieceSet>> = LazyLock::new(|| {
+    let (id, text) = BUILTIN_SETS[0];
+    Arc::new(PieceSet::parse(id, text).expect("the standard pieces are valid"))
+});
+
+// The seven tetrominoes
+pub fn standard() -> Arc<PieceSet> {
+    STANDARD.clone()
+}
+
+// The pieces the game draws from, the file name without the extension tells the sets apart
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PieceSet {
+    pub id: String,
+    pub name: String,
+    pub pieces: Vec<Piece>,
+}
+
+impl PieceSet {
+    // The set file has the `name` and a section for every piece, named after its letter:
+    //
+    //     [T]
+    //     color = "purple"
+    //     n = [".#.", "###"]
+    //
+    // The cells are given for the orientations `n`, `e`, `s` and `w`, the missing ones
+    // are the clockwise turns of the previous ones. The `spawn` shift and the `kicks`
+    // are optional. The set can start with the pieces of a built-in `base` set,
+    // the sections with the same letters replace them.
+    pub fn parse(id: &str, text: &str) -> io::Result<PieceSet> {
+        let mut set = PieceSet {
+            id: id.to_string(),
+            name: id.to_string(),
+            pieces: Vec::new(),
+        };
+        let entries = config::parse_toml(text)?;
+        let mut sections: Vec<(char, Vec<&config::Entry>)> = Vec::new();
+        for entry in &entries {
+            if entry.section.is_empty() {
+                let [value] = entry.values.as_slice() else {
+                    return Err(invalid_data(entry.line, "expected a single value"));
+                };
+                match entry.key.as_str() {
+                    "name" => set.name = value.clone(),
+                    "base" => {
+                        let (id, text) = BUILTIN_SETS
+                            .into_iter()
+                            .find(|(base, _)| *base == value.as_str() && *base != id)
+                            .ok_or_else(|| invalid_data(entry.line, "unknown base set"))?;
+                        set.pieces = PieceSet::parse(id, text)?.pieces;
+                    }
+                    key => {
+                        return Err(invalid_data(entry.line, &format!("unknown key `{}`", key)));
+                    }
+                }
+                continue;
+            }
+            let mut chars = entry.section.chars();
+            let (Some(name), None) = (chars.next(), chars.next()) else {
+                return Err(invalid_data(
+                    entry.line,
+                    "the piece section is a single letter, e.g. `[T]`",
+                ));
+            };
+            match sections.iter_mut().find(|(section, _)| *section == name) {
+                Some((_, section)) => section.push(entry),
+                None => sections.push((name, vec![entry])),
+            }
+        }
+
+        for (name, entries) in sections {
+            let piece = Piece::parse(name, &entries)?;
+            match set.pieces.iter_mut().find(|p| p.name == name) {
+                Some(replaced) => *replaced = piece,
+                None => set.pieces.push(piece),
+            }
+        }
+        if set.pieces.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "the set has no pieces",
+            ));
+        }
+        Ok(set)
+    }
+
+    // The piece with the shape in any of its orientations
+    pub fn find(&self, shape: Shape) -> Option<usize> {
+        self.pieces
+            .iter()
+            .position(|piece| piece.rotations.contains(&shape))
+    }
+
+    pub fn by_name(&self, name: char) -> Option<usize> {
+        self.pieces.iter().position(|piece| piece.name == name)
+    }
+
+    pub fn is_standard(&self) -> bool {
+        self.pieces == STANDARD.pieces
+    }
+}
+
+// The user's sets are the files in `pieces` of the data directory
+fn sets_dir() -> Option<PathBuf> {
+    Some(config::data_dir()?.join("pieces"))
+}
+
+// The built-in sets and the user's ones, with the errors of the files which couldn't be read
+pub fn sets() -> (Vec<Arc<PieceSet>>, Vec<String>) {
+    let mut sets = vec![standard()];
+    sets.extend(
+        BUILTIN_SETS[1..]
+            .iter()
+            .filter_map(|(id, text)| PieceSet::parse(id, text).ok())
+            .map(Arc::new),
+    );
+    let mut errors = Vec::new();
+    let Some(dir) = sets_dir() else {
+        return (sets, errors);
+    };
+    let files = match config::toml_files(&dir) {
+        Ok(files) => files,
+        Err(e) if e.kind() == ErrorKind::NotFound => return (sets, errors),
+        Err(e) => {
+            errors.push(format!("Couldn't read {}: {}", dir.display(), e));
+            return (sets, errors);
+        }
+    };
+    for (id, path) in files {
+        match fs::read_to_string(&path).and_then(|text| PieceSet::parse(&id, &text)) {
+            // The user's set with the id of a built-in one takes its place
+            Ok(set) => match sets.iter().position(|builtin| builtin.id == id) {
+                Some(i) => sets[i] = Arc::new(set),
+                None => sets.push(Arc::new(set)),
+            },
+            Err(e) => errors.push(format!("Couldn't read {}: {}", path.display(), e)),
+        }
+    }
+    (sets, errors)
+}
+
+// The set with the id, the standard one if it can't be read
+pub fn load(id: &str) -> Arc<PieceSet> {
+    let (sets, _) = sets();
+    sets.into_iter()
+        .find(|set| set.id == id)
+        .unwrap_or_else(standard)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shape(rows: &[&str]) -> Shape {
+        let rows: Vec<String> = rows.iter().map(|row| row.to_string()).collect();
+        parse_shape(&rows).unwrap()
+    }
+
+    #[test]
+    fn shapes_are_aligned_to_the_top_left_corner() {
+        assert_eq!(shape(&["###", "#.."]), 0b_10000_11100);
+        assert_eq!(shape(&["...", ".##", "..#"]), 0b_01000_11000);
+        assert_eq!(shape_size(shape(&["#....", "#####"])), (5, 2));
+        assert_eq!(shape_size(shape(&["#", ".", "#"])), (1, 3));
+        assert!(parse_shape(&[String::from("#x")]).is_none());
+        assert!(parse_shape(&[String::from("######")]).is_none());
+    }
+
+    #[test]
+    fn shapes_are_turned_clockwise() {
+        let t = shape(&[".#.", "###"]);
+        assert_eq!(rotated(t), shape(&["#.", "##", "#."]));
+        assert_eq!(rotated(rotated(rotated(rotated(t)))), t);
+        assert_eq!(rotated(shape(&["#####"])), shape(&["#"; 5]));
+    }
+
+    #[test]
+    fn builtin_sets_are_valid() {
+        for (id, text) in BUILTIN_SETS {
+            assert!(PieceSet::parse(id, text).is_ok(), "{}", id);
+        }
+        assert_eq!(standard().pieces.len(), 7);
+        let pentominoes = PieceSet::parse(BUILTIN_SETS[1].0, BUILTIN_SETS[1].1).unwrap();
+        assert_eq!(pentominoes.pieces.len(), 12);
+        assert!(
+            pentominoes
+                .pieces
+                .iter()
+                .all(|piece| piece.rotations[0].count_ones() == 5)
+        );
+    }
+
+    #[test]
+    fn base_set_comes_first() {
+        let (id, text) = BUILTIN_SETS[2];
+        let set = PieceSet::parse(id, text).unwrap();
+        assert_eq!(set.pieces[..7], standard().pieces[..]);
+        assert_eq!(set.pieces[7].name, 'M');
+        assert_eq!(set.pieces[7].rotations, [1 << (PIECE_SIZE - 1); 4]);
+        assert!(!set.is_standard());
+
+        // The piece of the base set is replaced
+        let text = "base = \"tetrominoes\"\n[T]\ncolor = \"red\"\nn = [\"###\"]\n";
+        let set = PieceSet::parse("custom", text).unwrap();
+        assert_eq!(set.pieces.len(), 7);
+        assert_eq!(
+            set.by_name('T').map(|i| set.pieces[i].color),
+            Some(Color::Red)
+        );
+    }
+
+    #[test]
+    fn piece_errors_report_the_line() {
+        let error = |text: &str| PieceSet::parse("custom", text).unwrap_err().to_string();
+        assert_eq!(
+            error("[X]\nn = [\"#\"]\ncolor = \"pink\""),
+            "line 3: unknown color"
+        );
+        assert_eq!(
+            error("[XY]\nn = [\"#\"]"),
+            "line 2: the piece section is a single letter, e.g. `[T]`"
+        );
+        assert_eq!(error("[X]\ncolor = \"red\""), "`X` has no `n` cells");
+        assert_eq!(
+            error("[X]\nn = [\"##\"]\ne = [\"#\"]"),
+            "the orientations of `X` have different cells"
+        );
+        assert_eq!(error("name = \"Empty\""), "the set has no pieces");
+    }
+}
@@ -0,0 +1,440 @@
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::TcpStream,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::pieces::{Color, Shape};
+use crate::tetris_engine::{GameState, MAX_HEIGHT, MAX_WIDTH, MIN_HEIGHT, MIN_WIDTH};
+
+// Bumped on every change of the messages. The hello message has to stay the same
+// in all the versions, so the mismatch is always reported instead of garbled boards.
+pub const PROTOCOL_VERSION: u16 = 5;
+const MAGIC: [u8; 4] = *b"RTRX";
+
+// Every message is framed as `<kind: u8> <payload length: u16> <payload>`,
+// all the numbers are big-endian
+const HEADER_LEN: usize = 3;
+const HELLO: u8 = 1;
+const BOARD: u8 = 2;
+const GARBAGE: u8 = 3;
+const GAME_OVER: u8 = 4;
+const LOCK: u8 = 5;
+
+const RECEIVE_POLL: Duration = Duration::from_millis(10);
+// The other side is not a ratrix game if it doesn't introduce itself by then
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    // The first message of both sides, the seed of the host is used by both players
+    Hello {
+        version: u16,
+        seed: u64,
+    },
+    // The board of the published game every time it changes, with its size,
+    // for the spectators who don't know the game
+    Board {
+        state: GameState,
+        pending: usize,
+        sent: usize,
+    },
+    // The piece locked by the opponent of an online match, the opponent's board is rebuilt
+    // from the shared seed and these. The piece as it spawns, the orientation and the position
+    // where it's locked, whether the hold was used and whether it's a T-spin,
+    // and how many `Garbage` messages the sender has received before the lock.
+    Lock {
+        piece: Shape,
+        orientation: u8,
+        position: [u8; 2],
+        held: bool,
+        t_spin: bool,
+        received: usize,
+    },
+    // The lines sent to the receiver
+    Garbage(usize),
+    // The sender has topped out
+    GameOver,
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+// Reads the big-endian numbers from the payload
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let Some((head, tail)) = self.bytes.split_first_chunk::<N>() else {
+            return Err(invalid_data("The message is too short"));
+        };
+        self.bytes = tail;
+        Ok(*head)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_be_bytes(self.take()?))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_be_bytes(self.take()?))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_be_bytes(self.take()?))
+    }
+
+    fn color(&mut self) -> io::Result<Color> {
+        let index = self.u8()? as usize;
+        Color::ALL
+            .get(index)
+            .copied()
+            .ok_or_else(|| invalid_data("Unknown color"))
+    }
+}
+
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        let kind = match self {
+            Message::Hello { version, seed } => {
+                payload.extend(MAGIC);
+                payload.extend(version.to_be_bytes());
+                payload.extend(seed.to_be_bytes());
+                HELLO
+            }
+            Message::Board {
+                state,
+                pending,
+                sent,
+            } => {
+                payload.push(state.width as u8);
+                payload.push(state.playfield.len() as u8);
+                for row in &state.playfield {
+                    payload.extend(row.to_be_bytes());
+                }
+                // A color per cell, the missing ones are sent as the default
+                for y in 0..state.playfield.len() {
+                    let row = state.colors.get(y).map_or(&[][..], |row| &row[..]);
+                    payload.extend(
+                        (0..state.width).map(|x| row.get(x).copied().unwrap_or_default() as u8),
+                    );
+                }
+                payload.extend(state.piece_position);
+                payload.extend(state.active_piece.to_be_bytes());
+                payload.push(state.active_color as u8);
+                // An empty piece means that nothing is held
+                payload.extend(state.hold_piece.unwrap_or(0).to_be_bytes());
+                payload.push(state.hold_color as u8);
+                payload.extend((state.score as u32).to_be_bytes());
+                payload.extend((state.lines as u32).to_be_bytes());
+                payload.extend((*pending as u16).to_be_bytes());
+                payload.extend((*sent as u32).to_be_bytes());
+                BOARD
+            }
+            Message::Lock {
+                piece,
+                orientation,
+                position,
+                held,
+                t_spin,
+                received,
+            } => {
+                payload.extend(piece.to_be_bytes());
+                payload.push(*orientation);
+                payload.extend(position);
+                payload.push(*held as u8 | (*t_spin as u8) << 1);
+                payload.extend((*received as u32).to_be_bytes());
+                LOCK
+            }
+            Message::Garbage(lines) => {
+                payload.extend((*lines as u16).to_be_bytes());
+                GARBAGE
+            }
+            Message::GameOver => GAME_OVER,
+        };
+        let mut bytes = vec![kind];
+        bytes.extend((payload.len() as u16).to_be_bytes());
+        bytes.extend(payload);
+        bytes
+    }
+
+    // Decodes the first message of the buffer and returns its length,
+    // `None` if the message isn't received completely yet
+    pub fn decode(bytes: &[u8]) -> io::Result<Option<(Message, usize)>> {
+        let Some(&[kind, len_high, len_low]) = bytes.first_chunk::<HEADER_LEN>() else {
+            return Ok(None);
+        };
+        let len = HEADER_LEN + u16::from_be_bytes([len_high, len_low]) as usize;
+        let Some(payload) = bytes.get(HEADER_LEN..len) else {
+            return Ok(None);
+        };
+        let mut reader = Reader { bytes: payload };
+        let message = match kind {
+            HELLO => {
+                if reader.take::<4>()? != MAGIC {
+                    return Err(invalid_data("The other side isn't a ratrix game"));
+                }
+                Message::Hello {
+                    version: reader.u16()?,
+                    seed: reader.u64()?,
+                }
+            }
+            BOARD => {
+                let (width, height) = (reader.u8()? as usize, reader.u8()? as usize);
+                if !(MIN_WIDTH..=MAX_WIDTH).contains(&width)
+                    || !(MIN_HEIGHT..=MAX_HEIGHT).contains(&height)
+                {
+                    return Err(invalid_data("The board has no valid size"));
+                }
+                let mut state = GameState {
+                    width,
+                    ..GameState::default()
+                };
+                state.playfield = (0..height)
+                    .map(|_| reader.u16())
+                    .collect::<io::Result<_>>()?;
+                state.colors = (0..height)
+                    .map(|_| (0..width).map(|_| reader.color()).collect())
+                    .collect::<io::Result<_>>()?;
+                state.piece_position = [reader.u8()?, reader.u8()?];
+                state.active_piece = reader.u32()?;
+                state.active_color = reader.color()?;
+                state.hold_piece = Some(reader.u32()?).filter(|&piece| piece != 0);
+                state.hold_color = reader.color()?;
+                state.score = reader.u32()? as usize;
+                state.lines = reader.u32()? as usize;
+                Message::Board {
+                    state,
+                    pending: reader.u16()? as usize,
+                    sent: reader.u32()? as usize,
+                }
+            }
+            LOCK => {
+                let (piece, orientation) = (reader.u32()?, reader.u8()?);
+                let position = [reader.u8()?, reader.u8()?];
+                let flags = reader.u8()?;
+                Message::Lock {
+                    piece,
+                    orientation,
+                    position,
+                    held: flags & 1 != 0,
+                    t_spin: flags & 2 != 0,
+                    received: reader.u32()? as usize,
+                }
+            }
+            GARBAGE => Message::Garbage(reader.u16()? as usize),
+            GAME_OVER => Message::GameOver,
+            _ => return Err(invalid_data("Unknown message")),
+        };
+        Ok(Some((message, len)))
+    }
+}
+
+// A non-blocking connection which is polled by the game loop every frame
+#[derive(Debug)]
+pub struct Connection {
+    stream: TcpStream,
+    // The bytes which don't make a complete message yet
+    incoming: Vec<u8>,
+    // The bytes which the socket hasn't accepted yet
+    outgoing: Vec<u8>,
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+        Ok(Self {
+            stream,
+            incoming: Vec::new(),
+            outgoing: Vec::new(),
+        })
+    }
+
+    pub fn send(&mut self, message: &Message) -> io::Result<()> {
+        self.outgoing.extend(message.encode());
+        self.flush()
+    }
+
+    // The bytes which are waiting for the other side to read the ones sent before
+    pub fn backlog(&self) -> usize {
+        self.outgoing.len()
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(written) => {
+                    self.outgoing.drain(..written);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    // The messages received since the last call, an error when the other side is gone
+    pub fn poll(&mut self) -> io::Result<Vec<Message>> {
+        self.read_available()?;
+        let mut messages = Vec::new();
+        while let Some(message) = self.next_message()? {
+            messages.push(message);
+        }
+        Ok(messages)
+    }
+
+    fn next_message(&mut self) -> io::Result<Option<Message>> {
+        let Some((message, len)) = Message::decode(&self.incoming)? else {
+            return Ok(None);
+        };
+        self.incoming.drain(..len);
+        Ok(Some(message))
+    }
+
+    fn read_available(&mut self) -> io::Result<()> {
+        self.flush()?;
+        let mut buffer = [0; 4096];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(read) => self.incoming.extend(&buffer[..read]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    // Waits for the hello of the other side and returns its seed
+    pub fn receive_hello(&mut self) -> io::Result<u64> {
+        let Message::Hello { version, seed } = self.receive(HANDSHAKE_TIMEOUT)? else {
+            return Err(invalid_data("The other side hasn't introduced itself"));
+        };
+        if version != PROTOCOL_VERSION {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "The other game uses protocol version {}, this one uses {}, please update both",
+                    version, PROTOCOL_VERSION
+                ),
+            ));
+        }
+        Ok(seed)
+    }
+
+    // Waits for the next message, used only before the game is started.
+    // The messages after it stay in the buffer for the game.
+    pub fn receive(&mut self, timeout: Duration) -> io::Result<Message> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(message) = self.next_message()? {
+                return Ok(message);
+            }
+            if Instant::now() >= deadline {
+                return Err(ErrorKind::TimedOut.into());
+            }
+            thread::sleep(RECEIVE_POLL);
+            self.read_available()?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tetris_engine::piece_by_name;
+
+    #[test]
+    fn messages_survive_the_round_trip() {
+        let mut state = GameState::default();
+        state.playfield[19] = 0b1111111110;
+        state.piece_position = [3, 7];
+        state.colors[19][4] = Color::Purple;
+        state.active_piece = piece_by_name('T').unwrap();
+        state.active_color = Color::Purple;
+        state.hold_piece = piece_by_name('O');
+        state.hold_color = Color::Yellow;
+        state.score = 1200;
+        state.lines = 4;
+        let messages = [
+            Message::Hello {
+                version: PROTOCOL_VERSION,
+                seed: 42,
+            },
+            Message::Board {
+                state,
+                pending: 3,
+                sent: 7,
+            },
+            Message::Lock {
+                piece: piece_by_name('T').unwrap(),
+                orientation: 2,
+                position: [3, 18],
+                held: true,
+                t_spin: true,
+                received: 5,
+            },
+            Message::Garbage(4),
+            Message::GameOver,
+        ];
+        let bytes: Vec<u8> = messages.iter().flat_map(Message::encode).collect();
+        let mut rest = &bytes[..];
+        for message in messages {
+            let (decoded, len) = Message::decode(rest).unwrap().unwrap();
+            assert_eq!(decoded, message);
+            rest = &rest[len..];
+        }
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn boards_keep_their_size() {
+        let state = GameState {
+            playfield: vec![0b1110; 30],
+            colors: vec![vec![Color::Blue; 4]; 30],
+            width: 4,
+            ..GameState::default()
+        };
+        let message = Message::Board {
+            state,
+            pending: 0,
+            sent: 0,
+        };
+        let (decoded, _) = Message::decode(&message.encode()).unwrap().unwrap();
+        assert_eq!(decoded, message);
+    }
+
+    #[test]
+    fn incomplete_messages_wait_for_the_rest() {
+        let bytes = Message::Garbage(2).encode();
+        assert_eq!(Message::decode(&bytes[..2]).unwrap(), None);
+        assert_eq!(Message::decode(&bytes[..4]).unwrap(), None);
+        assert!(Message::decode(&[9, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn unknown_colors_are_rejected() {
+        let mut bytes = Message::Board {
+            state: GameState::default(),
+            pending: 0,
+            sent: 0,
+        }
+        .encode();
+        // The color of the top left cell, after the header, the size and the rows
+        bytes[HEADER_LEN + 2 + 20 * 2] = Color::ALL.len() as u8;
+        assert!(Message::decode(&bytes).is_err());
+    }
+}
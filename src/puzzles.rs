@@ -0,0 +1,765 @@
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use crate::config::{self, invalid_data};
+use crate::finesse::Verdict;
+use crate::fumen;
+use crate::modes::Ruleset;
+use crate::pieces::{self, Color, Shape};
+use crate::tetris_engine::{
+    Lock, Rules, Setup, TetrisEngine, piece_by_name, piece_color, piece_name,
+};
+
+// The pack which comes with the game
+const BUILTIN_PACK: &str = "Basics";
+// The pack of the puzzles made in the editor
+const EDITOR_PACK: &str = "Custom";
+const BUILTIN_PUZZLES: [(&str, &str); 3] = [
+    ("1-tetris", include_str!("../puzzles/basics/1-tetris.toml")),
+    (
+        "2-t-spin-double",
+        include_str!("../puzzles/basics/2-t-spin-double.toml"),
+    ),
+    (
+        "3-perfect-clear",
+        include_str!("../puzzles/basics/3-perfect-clear.toml"),
+    ),
+];
+
+const CLEAR_NAMES: [&str; 3] = ["single", "double", "triple"];
+
+// What has to be done with the pieces of the puzzle
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Goal {
+    // Clear this many lines in total
+    Lines(usize),
+    // A T-spin which clears this many lines
+    TSpin(usize),
+    // A clear which leaves the board empty
+    #[default]
+    PerfectClear,
+}
+
+impl Goal {
+    // `4 lines`, `t-spin double` or `perfect clear`
+    fn parse(text: &str) -> Option<Goal> {
+        let text = text.trim().to_lowercase();
+        if text == "perfect clear" {
+            return Some(Goal::PerfectClear);
+        }
+        if let Some(name) = text.strip_prefix("t-spin ") {
+            let index = CLEAR_NAMES.iter().position(|n| *n == name.trim())?;
+            return Some(Goal::TSpin(index + 1));
+        }
+        let (count, unit) = text.split_once(' ')?;
+        let lines = count.parse().ok().filter(|&lines| lines > 0)?;
+        matches!(unit.trim(), "line" | "lines").then_some(Goal::Lines(lines))
+    }
+
+    // The inverse of `parse`
+    fn key(&self) -> String {
+        match self {
+            Goal::Lines(1) => String::from("1 line"),
+            Goal::Lines(lines) => format!("{} lines", lines),
+            Goal::TSpin(lines) => format!("t-spin {}", CLEAR_NAMES[lines - 1]),
+            Goal::PerfectClear => String::from("perfect clear"),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Goal::Lines(1) => String::from("Clear 1 line"),
+            Goal::Lines(lines) => format!("Clear {} lines", lines),
+            Goal::TSpin(lines) => format!("T-spin {}", CLEAR_NAMES[lines - 1]),
+            Goal::PerfectClear => String::from("Perfect clear"),
+        }
+    }
+
+    // Checked after every lock, `engine` has the board after the lock
+    fn is_met(&self, engine: &TetrisEngine, lock: &Lock) -> bool {
+        match *self {
+            Goal::Lines(lines) => engine.lines() >= lines,
+            Goal::TSpin(lines) => lock.t_spin && lock.cleared == lines,
+            Goal::PerfectClear => {
+                lock.cleared > 0 && engine.get_state().playfield.iter().all(|&row| row == 0)
+            }
+        }
+    }
+}
+
+// A row of the board in the puzzle file: 10 cells, `.` is empty, `#` is a block
+// and the letter of a piece is a block in the color of the piece
+fn parse_row(text: &str) -> Option<(u16, Vec<Color>)> {
+    if text.chars().count() != 10 {
+        return None;
+    }
+    let mut row = 0;
+    let mut colors = Vec::new();
+    for cell in text.chars() {
+        let (filled, color) = match cell {
+            '.' => (0, Color::default()),
+            '#' => (1, Color::default()),
+            name => (1, piece_color(name)?),
+        };
+        row = row << 1 | filled;
+        colors.push(color);
+    }
+    Some((row, colors))
+}
+
+// The inverse of `parse_row`, the colors of no standard piece are written as the blocks
+fn format_row(row: u16, colors: &[Color]) -> String {
+    let pieces = pieces::standard();
+    (0..10)
+        .map(|x| {
+            if row >> (9 - x) & 1 == 0 {
+                return '.';
+            }
+            let color = colors.get(x).copied().unwrap_or_default();
+            pieces
+                .pieces
+                .iter()
+                .find(|piece| piece.color == color)
+                .map_or('#', |piece| piece.name)
+        })
+        .collect()
+}
+
+// The pieces of the file, e.g. "TIO"
+fn parse_pieces(text: &str) -> Option<Vec<Shape>> {
+    text.chars()
+        .map(|name| piece_by_name(name.to_ascii_uppercase()))
+        .collect()
+}
+
+fn format_pieces(pieces: &[Shape]) -> String {
+    pieces
+        .iter()
+        .filter_map(|&piece| piece_name(piece))
+        .collect()
+}
+
+// The file of the board and the pieces, with the goal it's a puzzle.
+// The empty rows at the top are left out, the board sits at the bottom anyway.
+pub fn to_toml(goal: Option<Goal>, setup: &Setup) -> String {
+    let mut text = String::new();
+    if let Some(goal) = goal {
+        text.push_str(&format!("goal = \"{}\"\n", goal.key()));
+    }
+    if !setup.queue.is_empty() {
+        text.push_str(&format!("pieces = \"{}\"\n", format_pieces(&setup.queue)));
+    }
+    if let Some(hold) = setup.hold.and_then(piece_name) {
+        text.push_str(&format!("held = \"{}\"\n", hold));
+    }
+    let top = setup
+        .playfield
+        .iter()
+        .position(|&row| row != 0)
+        .unwrap_or(setup.playfield.len());
+    for (i, row) in setup.playfield.iter().enumerate().skip(top) {
+        let colors = setup.colors.get(i).map_or(&[][..], |row| &row[..]);
+        text.push_str(&format!("board = \"{}\"\n", format_row(*row, colors)));
+    }
+    text
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Puzzle {
+    // The pack and the file name without the extension tell the solved puzzles apart
+    pub pack: String,
+    pub id: String,
+    pub name: String,
+    pub goal: Goal,
+    pub setup: Setup,
+    pub hold: bool,
+}
+
+impl Puzzle {
+    // The puzzle file has the `goal`, the `pieces` (e.g. "TIO") and the board,
+    // either as the `board` rows from the top, which sit at the bottom of the playfield,
+    // or as a `fumen` which also gives the pieces when there are no `pieces`.
+    // The `name`, `hold` (true by default) and the `held` piece are optional.
+    pub fn parse(pack: &str, id: &str, text: &str) -> io::Result<Puzzle> {
+        let (mut puzzle, goal) = Self::parse_fields(pack, id, text)?;
+        let missing = |message: &str| io::Error::new(ErrorKind::InvalidData, message);
+        puzzle.goal = goal.ok_or_else(|| missing("the puzzle has no `goal`"))?;
+        if puzzle.setup.queue.is_empty() {
+            return Err(missing("the puzzle has no `pieces`"));
+        }
+        Ok(puzzle)
+    }
+
+    // The starting position is the puzzle file without the goal, the pieces are optional too
+    pub fn parse_position(text: &str) -> io::Result<Setup> {
+        Ok(Self::parse_fields("", "", text)?.0.setup)
+    }
+
+    fn parse_fields(pack: &str, id: &str, text: &str) -> io::Result<(Puzzle, Option<Goal>)> {
+        let mut puzzle = Puzzle {
+            pack: pack.to_string(),
+            id: id.to_string(),
+            name: id.to_string(),
+            hold: true,
+            ..Puzzle::default()
+        };
+        let mut goal = None;
+        let mut pieces = None;
+        let mut held = None;
+        let mut rows = Vec::new();
+        let mut board = None;
+
+        for entry in config::parse_toml(text)? {
+            if entry.key == "board" {
+                for row in &entry.values {
+                    let row = parse_row(row).ok_or_else(|| {
+                        invalid_data(
+                            entry.line,
+                            "a board row is 10 cells of `.`, `#` and the piece letters",
+                        )
+                    })?;
+                    rows.push(row);
+                }
+                continue;
+            }
+            let [value] = entry.values.as_slice() else {
+                return Err(invalid_data(entry.line, "expected a single value"));
+            };
+            match entry.key.as_str() {
+                "name" => puzzle.name = value.clone(),
+                "goal" => {
+                    let parsed = Goal::parse(value).ok_or_else(|| {
+                        invalid_data(
+                            entry.line,
+                            "unknown goal, expected e.g. `4 lines`, `t-spin double` or `perfect clear`",
+                        )
+                    })?;
+                    goal = Some(parsed);
+                }
+                "pieces" => {
+                    let parsed = parse_pieces(value)
+                        .ok_or_else(|| invalid_data(entry.line, "unknown piece"))?;
+                    pieces = Some(parsed);
+                }
+                "held" => {
+                    let parsed = match parse_pieces(value).as_deref() {
+                        Some(&[piece]) => piece,
+                        _ => return Err(invalid_data(entry.line, "expected a single piece")),
+                    };
+                    held = Some(parsed);
+                }
+                "hold" => {
+                    puzzle.hold = value
+                        .parse()
+                        .map_err(|_| invalid_data(entry.line, "expected `true` or `false`"))?;
+                }
+                "fumen" => {
+                    let pages = fumen::decode(value)
+                        .map_err(|e| invalid_data(entry.line, &e.to_string()))?;
+                    board = Some(fumen::setup(&pages));
+                }
+                key => return Err(invalid_data(entry.line, &format!("unknown key `{}`", key))),
+            }
+        }
+
+        let missing = |message: &str| io::Error::new(ErrorKind::InvalidData, message);
+        if rows.len() > 20 {
+            return Err(missing("the board has more than 20 rows"));
+        }
+        puzzle.setup = match board {
+            Some(_) if !rows.is_empty() => {
+                return Err(missing("the board is either `board` rows or a `fumen`"));
+            }
+            Some(setup) => setup,
+            None => {
+                let mut playfield = vec![0; 20 - rows.len()];
+                let mut colors = vec![Vec::new(); 20 - rows.len()];
+                for (row, row_colors) in rows {
+                    playfield.push(row);
+                    colors.push(row_colors);
+                }
+                Setup {
+                    playfield,
+                    colors,
+                    ..Setup::default()
+                }
+            }
+        };
+        if let Some(pieces) = pieces {
+            puzzle.setup.queue = pieces;
+        }
+        puzzle.setup.hold = held;
+        Ok((puzzle, goal))
+    }
+}
+
+// A set of puzzles played in the order of their file names
+#[derive(Debug, Clone, Default)]
+pub struct Pack {
+    pub name: String,
+    pub puzzles: Vec<Puzzle>,
+}
+
+impl Pack {
+    fn builtin() -> Pack {
+        let puzzles = BUILTIN_PUZZLES
+            .iter()
+            .filter_map(|(id, text)| Puzzle::parse(BUILTIN_PACK, id, text).ok())
+            .collect();
+        Pack {
+            name: String::from(BUILTIN_PACK),
+            puzzles,
+        }
+    }
+
+    // The pack is a directory of `.toml` puzzle files, named after the directory
+    pub fn load(dir: &Path) -> io::Result<Pack> {
+        let name = dir
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut puzzles = Vec::new();
+        for (id, path) in config::toml_files(dir)? {
+            let puzzle = fs::read_to_string(&path)
+                .and_then(|text| Puzzle::parse(&name, &id, &text))
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+            puzzles.push(puzzle);
+        }
+        Ok(Pack { name, puzzles })
+    }
+}
+
+// The user's packs are the directories in `puzzles` of the data directory
+fn packs_dir() -> Option<PathBuf> {
+    Some(config::data_dir()?.join("puzzles"))
+}
+
+// The built-in pack and the user's packs, with the errors of the packs which couldn't be read
+pub fn packs() -> (Vec<Pack>, Vec<String>) {
+    let mut packs = vec![Pack::builtin()];
+    let mut errors = Vec::new();
+    let Some(dir) = packs_dir() else {
+        return (packs, errors);
+    };
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return (packs, errors),
+        Err(e) => {
+            errors.push(format!("Couldn't read {}: {}", dir.display(), e));
+            return (packs, errors);
+        }
+    };
+    let mut dirs: Vec<PathBuf> = entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.is_dir())
+        .collect();
+    dirs.sort();
+    for dir in dirs {
+        match Pack::load(&dir) {
+            Ok(pack) if pack.puzzles.is_empty() => {}
+            Ok(pack) => packs.push(pack),
+            Err(e) => errors.push(format!("Couldn't read the pack: {}", e)),
+        }
+    }
+    (packs, errors)
+}
+
+// The starting positions saved in the editor are the files in `positions` of the data directory
+fn positions_dir() -> Option<PathBuf> {
+    Some(config::data_dir()?.join("positions"))
+}
+
+// The saved starting positions in the order they were saved, none if there is no directory
+pub fn positions() -> io::Result<Vec<Setup>> {
+    let Some(dir) = positions_dir() else {
+        return Ok(Vec::new());
+    };
+    let files = match config::toml_files(&dir) {
+        Ok(files) => files,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    files
+        .iter()
+        .map(|(_, path)| {
+            fs::read_to_string(path)
+                .and_then(|text| Puzzle::parse_position(&text))
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+        })
+        .collect()
+}
+
+// Saves the board and the pieces as a new puzzle of the editor's pack, or as a new
+// starting position when there is no goal. Returns the path of the file.
+pub fn save(goal: Option<Goal>, setup: &Setup) -> io::Result<PathBuf> {
+    let dir = match goal {
+        Some(_) => packs_dir().map(|dir| dir.join(EDITOR_PACK)),
+        None => positions_dir(),
+    }
+    .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "no data directory"))?;
+    fs::create_dir_all(&dir)?;
+    // The files are numbered, so they are listed in the order they were saved
+    let number = config::toml_files(&dir)?
+        .iter()
+        .filter_map(|(id, _)| id.parse::<usize>().ok())
+        .max()
+        .map_or(1, |number| number + 1);
+    let path = dir.join(format!("{:03}.toml", number));
+    fs::write(&path, to_toml(goal, setup))?;
+    Ok(path)
+}
+
+// The puzzles solved so far. The file has a `<pack>/<puzzle>` line for each of them.
+#[derive(Debug, Default)]
+pub struct Solved {
+    puzzles: HashSet<String>,
+}
+
+impl Solved {
+    fn path() -> Option<PathBuf> {
+        Some(config::data_dir()?.join("puzzles.txt"))
+    }
+
+    // A missing file means that nothing is solved yet
+    pub fn load() -> io::Result<Self> {
+        let Some(path) = Self::path() else {
+            return Ok(Self::default());
+        };
+        match fs::read_to_string(path) {
+            Ok(text) => Ok(Self::parse(&text)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let Some(path) = Self::path() else {
+            return Err(io::Error::new(ErrorKind::NotFound, "no data directory"));
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_text())
+    }
+
+    fn parse(text: &str) -> Self {
+        let puzzles = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect();
+        Self { puzzles }
+    }
+
+    fn to_text(&self) -> String {
+        let mut puzzles: Vec<&String> = self.puzzles.iter().collect();
+        puzzles.sort();
+        puzzles
+            .iter()
+            .map(|puzzle| format!("{}\n", puzzle))
+            .collect()
+    }
+
+    fn key(puzzle: &Puzzle) -> String {
+        format!("{}/{}", puzzle.pack, puzzle.id)
+    }
+
+    pub fn contains(&self, puzzle: &Puzzle) -> bool {
+        self.puzzles.contains(&Self::key(puzzle))
+    }
+
+    // Returns `true` when the puzzle wasn't solved before
+    fn insert(&mut self, puzzle: &Puzzle) -> bool {
+        self.puzzles.insert(Self::key(puzzle))
+    }
+
+    // Keeps the puzzle in the file of the solved ones
+    fn mark(puzzle: &Puzzle) -> io::Result<()> {
+        let mut solved = Self::load()?;
+        if solved.insert(puzzle) {
+            solved.save()?;
+        }
+        Ok(())
+    }
+}
+
+// A try at the puzzle: the game ends as soon as the goal is met or the pieces run out
+#[derive(Debug, Default)]
+pub struct Attempt {
+    puzzle: Puzzle,
+    locks: usize,
+    solved: bool,
+}
+
+impl Attempt {
+    pub fn new(puzzle: Puzzle) -> Self {
+        Self {
+            puzzle,
+            ..Self::default()
+        }
+    }
+
+    fn pieces_left(&self) -> usize {
+        self.puzzle.setup.queue.len().saturating_sub(self.locks)
+    }
+}
+
+impl Ruleset for Attempt {
+    fn rules(&self) -> Rules {
+        Rules {
+            lines_per_level: None,
+            hold: self.puzzle.hold,
+            undo: true,
+            ..Rules::default()
+        }
+    }
+
+    fn reset(&mut self) {
+        self.locks = 0;
+        self.solved = false;
+    }
+
+    fn setup(&mut self, engine: &mut TetrisEngine) {
+        engine.load(&self.puzzle.setup);
+    }
+
+    fn on_lock(&mut self, engine: &mut TetrisEngine, lock: &Lock, _verdict: &Verdict) {
+        // The pieces locked in the same frame after the end don't count
+        if engine.is_finished() {
+            return;
+        }
+        self.locks += 1;
+        if self.puzzle.goal.is_met(engine, lock) {
+            self.solved = true;
+            engine.stop();
+        } else if self.pieces_left() == 0 {
+            engine.stop();
+        }
+    }
+
+    fn on_undo(&mut self, redo: bool) {
+        if redo {
+            self.locks += 1;
+        } else {
+            self.locks = self.locks.saturating_sub(1);
+        }
+    }
+
+    fn hud(&self, _engine: &TetrisEngine, _frame: u64) -> Vec<String> {
+        vec![
+            String::from("Goal"),
+            self.puzzle.goal.describe(),
+            String::new(),
+            String::from("Pieces"),
+            self.pieces_left().to_string(),
+        ]
+    }
+
+    fn finish(&mut self, _engine: &TetrisEngine, _standard: bool) -> (String, Vec<String>) {
+        let mut info = vec![self.puzzle.name.clone(), self.puzzle.goal.describe()];
+        if !self.solved {
+            return (String::from("Puzzle failed"), info);
+        }
+        if let Err(e) = Solved::mark(&self.puzzle) {
+            info.push(String::new());
+            info.push(format!("Couldn't save the progress: {}", e));
+        }
+        (String::from("Puzzle solved"), info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn goals_are_parsed() {
+        assert_eq!(Goal::parse("4 lines"), Some(Goal::Lines(4)));
+        assert_eq!(Goal::parse("1 line"), Some(Goal::Lines(1)));
+        assert_eq!(Goal::parse("T-spin Double"), Some(Goal::TSpin(2)));
+        assert_eq!(Goal::parse("perfect clear"), Some(Goal::PerfectClear));
+        assert_eq!(Goal::parse("0 lines"), None);
+        assert_eq!(Goal::parse("t-spin quad"), None);
+        assert_eq!(Goal::TSpin(3).describe(), "T-spin triple");
+    }
+
+    #[test]
+    fn board_rows_sit_at_the_bottom() {
+        let text = [
+            "name = \"Test\"",
+            "goal = \"2 lines\"",
+            "pieces = \"oi\"",
+            "board = \"#........#\"",
+            "board = [\"#########.\", \"##########\"]",
+        ]
+        .join("\n");
+        let puzzle = Puzzle::parse("pack", "01", &text).unwrap();
+        assert_eq!(puzzle.name, "Test");
+        assert_eq!(puzzle.goal, Goal::Lines(2));
+        assert_eq!(
+            puzzle.setup.queue,
+            vec![piece_by_name('O').unwrap(), piece_by_name('I').unwrap()]
+        );
+        assert_eq!(puzzle.setup.playfield[..17], [0; 17]);
+        assert_eq!(
+            puzzle.setup.playfield[17..],
+            [0b10_0000_0001, 0b11_1111_1110, 0b11_1111_1111]
+        );
+        assert!(puzzle.hold);
+    }
+
+    #[test]
+    fn puzzle_errors_report_the_line() {
+        let error = |text: &str| Puzzle::parse("pack", "01", text).unwrap_err().to_string();
+        assert_eq!(
+            error("goal = \"4 lines\"\npieces = \"I\"\nboard = \"###\""),
+            "line 3: a board row is 10 cells of `.`, `#` and the piece letters"
+        );
+        assert_eq!(
+            error("goal = \"4 lines\"\npieces = \"X\""),
+            "line 2: unknown piece"
+        );
+        assert_eq!(error("pieces = \"I\""), "the puzzle has no `goal`");
+        assert_eq!(error("goal = \"4 lines\""), "the puzzle has no `pieces`");
+    }
+
+    #[test]
+    fn fumen_gives_the_board_and_the_pieces() {
+        // The I piece on the left of the empty board
+        let puzzle = Puzzle::parse("pack", "01", "goal = \"1 line\"\nfumen = \"v115@vhAAgH\"");
+        assert_eq!(
+            puzzle.unwrap_err().to_string(),
+            "the puzzle has no `pieces`"
+        );
+        let text = "goal = \"1 line\"\nfumen = \"v115@9gF8DeF8DeF8DeF8NeAgH\"\npieces = \"I\"";
+        let puzzle = Puzzle::parse("pack", "01", text).unwrap();
+        assert_ne!(puzzle.setup.playfield, [0; 20]);
+    }
+
+    #[test]
+    fn saved_files_are_read_back() {
+        // The colored cells, the garbage and the held piece,
+        // every standard piece has a color of its own
+        let text = [
+            "goal = \"t-spin double\"",
+            "pieces = \"TS\"",
+            "held = \"I\"",
+            "board = \"LLL.......\"",
+            "board = \"L#..IIIIJJ\"",
+        ]
+        .join("\n");
+        let puzzle = Puzzle::parse("pack", "01", &text).unwrap();
+        assert_eq!(puzzle.setup.hold, piece_by_name('I'));
+        assert_eq!(to_toml(Some(puzzle.goal), &puzzle.setup), text + "\n");
+        let position = Puzzle::parse_position(&to_toml(None, &puzzle.setup)).unwrap();
+        assert_eq!(position, puzzle.setup);
+
+        // The game starts with the colors and the held piece
+        let mut engine = TetrisEngine::new();
+        engine.load(&position);
+        let state = engine.get_state();
+        assert_eq!(state.colors[18][..2], [piece_color('L').unwrap(); 2]);
+        assert_eq!(state.colors[19][1], Color::default());
+        assert_eq!(state.hold_piece, piece_by_name('I'));
+        assert_eq!(
+            Puzzle::parse(
+                "pack",
+                "01",
+                "goal = \"1 line\"\npieces = \"T\"\nheld = \"TI\""
+            )
+            .unwrap_err()
+            .to_string(),
+            "line 3: expected a single piece"
+        );
+    }
+
+    #[test]
+    fn builtin_puzzles_are_valid() {
+        for (id, text) in BUILTIN_PUZZLES {
+            assert!(Puzzle::parse(BUILTIN_PACK, id, text).is_ok(), "{}", id);
+        }
+    }
+
+    #[test]
+    fn solved_puzzles_survive_the_round_trip() {
+        let puzzle = Puzzle {
+            pack: String::from("Basics"),
+            id: String::from("2-t-spin-double"),
+            ..Puzzle::default()
+        };
+        let mut solved = Solved::default();
+        assert!(solved.insert(&puzzle));
+        assert!(!solved.insert(&puzzle));
+        let text = solved.to_text();
+        assert_eq!(text, "Basics/2-t-spin-double\n");
+        assert!(Solved::parse(&text).contains(&puzzle));
+    }
+
+    fn start(name: &str) -> (Attempt, TetrisEngine) {
+        let (id, text) = BUILTIN_PUZZLES
+            .into_iter()
+            .find(|(id, _)| *id == name)
+            .unwrap();
+        let mut attempt = Attempt::new(Puzzle::parse(BUILTIN_PACK, id, text).unwrap());
+        let mut engine = TetrisEngine::with_rules(attempt.rules());
+        attempt.setup(&mut engine);
+        (attempt, engine)
+    }
+
+    fn lock(engine: &mut TetrisEngine, attempt: &mut Attempt) {
+        engine.hard_drop();
+        for lock in engine.take_locks() {
+            attempt.on_lock(engine, &lock, &Verdict::Unchecked);
+        }
+    }
+
+    #[test]
+    fn goal_is_checked_after_every_lock() {
+        // The I piece into the well on the right
+        let (mut attempt, mut engine) = start("1-tetris");
+        (0..10).for_each(|_| engine.move_current_shape(1, 0));
+        lock(&mut engine, &mut attempt);
+        assert!(attempt.solved);
+        assert!(engine.is_finished());
+        assert_eq!(attempt.hud(&engine, 0)[4], "0");
+    }
+
+    #[test]
+    fn t_spin_goal_needs_the_spin() {
+        // Pointing down, the T lands on the slot and turns into it
+        let (mut attempt, mut engine) = start("2-t-spin-double");
+        engine.rotate_180();
+        engine.move_current_shape(-2, 0);
+        (0..20).for_each(|_| engine.move_current_shape(0, 1));
+        engine.rotate_ccw();
+        lock(&mut engine, &mut attempt);
+        assert!(attempt.solved);
+        assert_eq!(engine.lines(), 2);
+
+        // The same lines without the spin
+        let (mut attempt, mut engine) = start("2-t-spin-double");
+        engine.rotate();
+        engine.move_current_shape(-2, 0);
+        lock(&mut engine, &mut attempt);
+        assert_eq!(engine.lines(), 2);
+        assert!(!attempt.solved);
+    }
+
+    #[test]
+    fn puzzle_fails_when_the_pieces_run_out() {
+        // The L fits on the left, the J doesn't fit on the right
+        let (mut attempt, mut engine) = start("3-perfect-clear");
+        (0..10).for_each(|_| engine.move_current_shape(-1, 0));
+        lock(&mut engine, &mut attempt);
+        assert!(!engine.is_finished());
+        (0..10).for_each(|_| engine.move_current_shape(1, 0));
+        lock(&mut engine, &mut attempt);
+        assert!(!attempt.solved);
+        assert!(engine.is_finished());
+    }
+}
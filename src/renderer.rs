@@ -1,25 +1,117 @@
-use std::io::{self, BufWriter, Stdout};
+use std::io::{self, BufWriter, Stdout, Write};
 
 use crossterm::{
-    cursor::{Hide, MoveTo, MoveToColumn, Show},
-    execute,
+    cursor::{Hide, MoveTo, Show},
+    execute, queue,
+    style::Print,
     terminal::{self, Clear, EnterAlternateScreen, LeaveAlternateScreen, SetSize, SetTitle},
 };
 
-use crate::tetris_engine::GameState;
+use crate::config::{Action, KeyBindings};
+use crate::pieces::{Color, PIECE_SIZE, Shape, shape_row};
+use crate::tetris_engine::{DEFAULT_HEIGHT, DEFAULT_WIDTH, GameState};
+
+// Every cell is drawn with a wide emoji, so it takes 2 terminal columns.
+const CELL_WIDTH: u16 = 2;
+// The side panel with the held piece is on the right of the board
+const PANEL_COLS: u16 = 2 + PIECE_SIZE as u16 * CELL_WIDTH;
+// The menus and the pause take the place of the standard playfield, 10 cells wide
+// and 20 cells high, plus one extra line under the board for the score.
+const BOARD_COLS: u16 = DEFAULT_WIDTH as u16 * CELL_WIDTH;
+const FRAME_COLS: u16 = BOARD_COLS + PANEL_COLS;
+const FRAME_ROWS: u16 = DEFAULT_HEIGHT as u16 + 1;
+// In the versus mode the pending garbage is shown on the left of every board
+const METER_COLS: u16 = 2;
+const VERSUS_GAP: u16 = 2;
+
+// The board of the given size with the side panel and the status line
+fn frame_size(state: &GameState) -> (u16, u16) {
+    let cols = state.width as u16 * CELL_WIDTH + PANEL_COLS;
+    (cols, state.playfield.len() as u16 + 1)
+}
+
+// Both boards of the versus mode with their garbage meters, side by side
+fn versus_size(states: [&GameState; 2]) -> (u16, u16) {
+    let [first, second] = states.map(frame_size);
+    let cols = 2 * METER_COLS + first.0 + second.0 + VERSUS_GAP;
+    (cols, first.1.max(second.1))
+}
+
+// The locked cells and the pieces are drawn in the colors of their pieces
+fn glyph(color: Color) -> char {
+    match color {
+        Color::Red => '🟥',
+        Color::Orange => '🟧',
+        Color::Yellow => '🟨',
+        Color::Green => '🟩',
+        // There is no cyan square
+        Color::Cyan => '🧊',
+        Color::Blue => '🟦',
+        Color::Purple => '🟪',
+        Color::Brown => '🟫',
+        Color::Black => '⬛',
+    }
+}
+
+const ENLARGE_MESSAGE: &str = "Please enlarge the terminal";
 
 #[derive(Debug)]
 pub struct Renderer {
     sout: BufWriter<Stdout>,
     init_terminal_size: (u16, u16),
+    terminal_size: (u16, u16),
+    // Top-left corner of the playfield on the screen
+    origin: (u16, u16),
 }
 
 impl Renderer {
     pub fn new() -> Self {
-        return Self {
+        let terminal_size = terminal::size().unwrap();
+        let mut renderer = Self {
             sout: io::BufWriter::new(io::stdout()),
-            init_terminal_size: terminal::size().unwrap(),
+            init_terminal_size: terminal_size,
+            terminal_size,
+            origin: (0, 0),
         };
+        renderer.resize(terminal_size.0, terminal_size.1);
+        renderer
+    }
+
+    pub fn resize(&mut self, cols: u16, rows: u16) {
+        // Recompute the layout: the playfield is centered in the terminal
+        self.terminal_size = (cols, rows);
+        self.origin = (
+            cols.saturating_sub(FRAME_COLS) / 2,
+            rows.saturating_sub(FRAME_ROWS) / 2,
+        );
+    }
+
+    fn fits_size(&self, (width, height): (u16, u16)) -> bool {
+        let (cols, rows) = self.terminal_size;
+        cols >= width && rows >= height
+    }
+
+    // The menus fit the terminal
+    pub fn fits(&self) -> bool {
+        self.fits_size((FRAME_COLS, FRAME_ROWS))
+    }
+
+    pub fn fits_game(&self, state: &GameState) -> bool {
+        self.fits_size(frame_size(state))
+    }
+
+    // The two boards of the versus mode need about twice the width
+    pub fn fits_versus(&self, states: [&GameState; 2]) -> bool {
+        self.fits_size(versus_size(states))
+    }
+
+    // The top-left corner of the frame of the given size in the middle of the terminal
+    fn centered(&self, (width, height): (u16, u16)) -> (u16, u16) {
+        let (cols, rows) = self.terminal_size;
+        (
+            cols.saturating_sub(width) / 2,
+            rows.saturating_sub(height) / 2,
+        )
     }
 
     pub fn setup(&mut self) -> io::Result<()> {
@@ -29,46 +121,275 @@ impl Renderer {
         Ok(())
     }
 
-    pub fn flush_changes(&mut self, state: &GameState) -> io::Result<()> {
+    // `hud` is the information of the mode, shown under the held piece
+    pub fn flush_changes(&mut self, state: &GameState, hud: &[String]) -> io::Result<()> {
         // 2.1 Clear the screen
-        execute!(self.sout, Clear(terminal::ClearType::All), MoveTo(0, 0))?;
+        queue!(self.sout, Clear(terminal::ClearType::All))?;
 
         // 2.2 Draw stuff
-        for line in self.get_playfield_lines(&state) {
-            println!("{}", line);
-            execute!(self.sout, MoveToColumn(0))?;
+        if self.fits_game(state) {
+            let origin = self.centered(frame_size(state));
+            self.draw_game(state, hud, origin)?;
+        } else {
+            self.draw_enlarge_message()?;
+        }
+        self.sout.flush()
+    }
+
+    // Both boards side by side, `players` are the states, HUDs and pending garbage of the players
+    pub fn flush_versus(
+        &mut self,
+        players: &[(GameState, Vec<String>, usize); 2],
+    ) -> io::Result<()> {
+        queue!(self.sout, Clear(terminal::ClearType::All))?;
+        let states = [&players[0].0, &players[1].0];
+        if !self.fits_versus(states) {
+            self.draw_enlarge_message()?;
+            return self.sout.flush();
+        }
+
+        let (mut x, y) = self.centered(versus_size(states));
+        for (state, hud, pending) in players.iter() {
+            self.draw_garbage_meter(*pending, state.playfield.len(), (x, y))?;
+            x += METER_COLS;
+            self.draw_game(state, hud, (x, y))?;
+            x += frame_size(state).0 + VERSUS_GAP;
+        }
+        self.sout.flush()
+    }
+
+    // The board with the hold panel, the HUD and the status line, `origin` is the top-left corner
+    fn draw_game(
+        &mut self,
+        state: &GameState,
+        hud: &[String],
+        origin: (u16, u16),
+    ) -> io::Result<()> {
+        self.draw_board(state, hud, origin)?;
+        let status = format!("Score: {}  Lines: {}", state.score, state.lines);
+        self.draw_status(state, &status, origin)
+    }
+
+    fn draw_board(
+        &mut self,
+        state: &GameState,
+        hud: &[String],
+        origin: (u16, u16),
+    ) -> io::Result<()> {
+        let (x, y) = origin;
+        for (i, line) in self.get_playfield_lines(state).iter().enumerate() {
+            queue!(self.sout, MoveTo(x, y + i as u16), Print(line))?;
+        }
+        let panel_x = x + state.width as u16 * CELL_WIDTH + 2;
+        self.draw_hold_panel(state, (panel_x, y))?;
+        for (i, line) in hud.iter().enumerate() {
+            queue!(self.sout, MoveTo(panel_x, y + 6 + i as u16), Print(line))?;
         }
         Ok(())
     }
 
-    fn render_line(&self, line: &u16) -> String {
+    // The line under the board
+    fn draw_status(
+        &mut self,
+        state: &GameState,
+        status: &str,
+        origin: (u16, u16),
+    ) -> io::Result<()> {
+        let (x, y) = origin;
+        let status_y = y + state.playfield.len() as u16;
+        queue!(self.sout, MoveTo(x, status_y), Print(status))
+    }
+
+    // The board of the editor with the cursor, `cursor` is the column and the row of its cell
+    pub fn flush_editor(
+        &mut self,
+        state: &GameState,
+        hud: &[String],
+        cursor: [usize; 2],
+        status: &str,
+    ) -> io::Result<()> {
+        queue!(self.sout, Clear(terminal::ClearType::All))?;
+        if !self.fits_game(state) {
+            self.draw_enlarge_message()?;
+            return self.sout.flush();
+        }
+        let origin = self.centered(frame_size(state));
+        self.draw_board(state, hud, origin)?;
+        let [column, row] = cursor;
+        let (x, y) = origin;
+        queue!(
+            self.sout,
+            MoveTo(x + column as u16 * CELL_WIDTH, y + row as u16),
+            Print('🔳')
+        )?;
+        self.draw_status(state, status, origin)?;
+        self.sout.flush()
+    }
+
+    // A bar growing from the bottom of the board, a cell per incoming garbage line
+    fn draw_garbage_meter(
+        &mut self,
+        pending: usize,
+        height: usize,
+        origin: (u16, u16),
+    ) -> io::Result<()> {
+        let (x, y) = origin;
+        for row in 0..height {
+            let cell = if height - row <= pending {
+                "▐█"
+            } else {
+                "  "
+            };
+            queue!(self.sout, MoveTo(x, y + row as u16), Print(cell))?;
+        }
+        Ok(())
+    }
+
+    // The board is hidden during the pause, so it can't be used to plan the next moves
+    pub fn flush_pause(&mut self, items: &[&str], selected: usize) -> io::Result<()> {
+        queue!(self.sout, Clear(terminal::ClearType::All))?;
+        if !self.fits() {
+            self.draw_enlarge_message()?;
+            return self.sout.flush();
+        }
+
+        let (x, y) = self.origin;
+        let empty_line = self.render_line(&0, &[], DEFAULT_WIDTH);
+        for i in 0..DEFAULT_HEIGHT as u16 {
+            queue!(self.sout, MoveTo(x, y + i), Print(&empty_line))?;
+        }
+        // The text is aligned to the cells and padded to the even width,
+        // otherwise it would cut the wide emoji in half
+        queue!(
+            self.sout,
+            MoveTo(x + 2 * CELL_WIDTH, y + 6),
+            Print("   PAUSED   ")
+        )?;
+        for (i, item) in items.iter().enumerate() {
+            let marker = if i == selected { ">" } else { " " };
+            let line = format!(" {} {:<13}", marker, item);
+            queue!(
+                self.sout,
+                MoveTo(x + CELL_WIDTH, y + 8 + i as u16),
+                Print(line)
+            )?;
+        }
+        self.sout.flush()
+    }
+
+    // A generic menu: the title, a few lines of information and the selectable items
+    pub fn flush_menu<T: AsRef<str>, U: AsRef<str>>(
+        &mut self,
+        title: &str,
+        info: &[T],
+        items: &[U],
+        selected: usize,
+    ) -> io::Result<()> {
+        queue!(self.sout, Clear(terminal::ClearType::All))?;
+        let (x, mut y) = (self.origin.0 + CELL_WIDTH, self.origin.1 + 2);
+        queue!(self.sout, MoveTo(x, y), Print(title))?;
+        y += 2;
+        for line in info.iter() {
+            queue!(self.sout, MoveTo(x, y), Print(line.as_ref()))?;
+            y += 1;
+        }
+        if !info.is_empty() {
+            y += 1;
+        }
+        for (i, item) in items.iter().enumerate() {
+            let marker = if i == selected { ">" } else { " " };
+            let line = format!("{} {}", marker, item.as_ref());
+            queue!(self.sout, MoveTo(x, y + i as u16), Print(line))?;
+        }
+        self.sout.flush()
+    }
+
+    // `origin` is the top-left corner of the panel
+    fn draw_hold_panel(&mut self, state: &GameState, origin: (u16, u16)) -> io::Result<()> {
+        let (x, y) = origin;
+        queue!(self.sout, MoveTo(x, y), Print("Hold"))?;
+        if let Some(piece) = state.hold_piece {
+            for (i, line) in self
+                .render_piece(&piece, state.hold_color)
+                .iter()
+                .enumerate()
+            {
+                // The empty cells of the piece shouldn't be visible outside of the playfield
+                let line = line.replace('⬜', "  ");
+                queue!(self.sout, MoveTo(x, y + 1 + i as u16), Print(line))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush_key_bindings(
+        &mut self,
+        keys: &KeyBindings,
+        selected: usize,
+        capturing: bool,
+        status: &str,
+    ) -> io::Result<()> {
+        queue!(self.sout, Clear(terminal::ClearType::All))?;
+        let title = format!("Key bindings (preset: {})", keys.preset.name());
+        queue!(self.sout, MoveTo(2, 1), Print(title))?;
+
+        for (i, action) in Action::ALL.iter().enumerate() {
+            let marker = if i == selected { ">" } else { " " };
+            let bound: Vec<String> = keys.keys(action).iter().map(|key| key.format()).collect();
+            let bound = if i == selected && capturing {
+                String::from("press a key, Esc to cancel")
+            } else {
+                bound.join(", ")
+            };
+            let line = format!("{} {:<14} {}", marker, action.name(), bound);
+            queue!(self.sout, MoveTo(2, 3 + i as u16), Print(line))?;
+        }
+
+        let help_row = 4 + Action::ALL.len() as u16;
+        let help = "Enter: add key  Backspace: clear  Tab: next preset  Esc: save";
+        queue!(self.sout, MoveTo(2, help_row), Print(help))?;
+        queue!(self.sout, MoveTo(2, help_row + 1), Print(status))?;
+        self.sout.flush()
+    }
+
+    fn draw_enlarge_message(&mut self) -> io::Result<()> {
+        // The terminal could be even narrower than the message itself
+        let (cols, rows) = self.terminal_size;
+        let message: String = ENLARGE_MESSAGE.chars().take(cols as usize).collect();
+        let x = cols.saturating_sub(message.len() as u16) / 2;
+        queue!(self.sout, MoveTo(x, rows / 2), Print(message))
+    }
+
+    // The cells without a color are drawn as the garbage
+    fn render_line(&self, line: &u16, colors: &[Color], width: usize) -> String {
         let mut result = String::from("");
-        for i in (0..10).rev() {
+        for i in (0..width).rev() {
             if (line >> i & 1) == 1 {
-                result.push_str("🟧");
+                let color = colors.get(width - 1 - i).copied().unwrap_or_default();
+                result.push(glyph(color));
             } else {
-                result.push_str("⬜");
+                result.push('⬜');
             }
         }
         result
     }
 
-    fn render_piece(&self, piece: &u16) -> Vec<String> {
+    fn render_piece(&self, piece: &Shape, color: Color) -> Vec<String> {
         let mut result = Vec::new();
-        let mut min_col = 4;
+        let mut min_col = PIECE_SIZE;
         let mut max_col = 0;
-        let mut min_row = 4;
+        let mut min_row = PIECE_SIZE;
         let mut max_row = 0;
 
         // Determine the bounding box of the piece (non-empty columns and rows)
-        for row in 0..4 {
-            let line = (piece >> (row * 4)) & 0b1111;
+        for row in 0..PIECE_SIZE {
+            let line = shape_row(*piece, row);
             if line != 0 {
                 min_row = min_row.min(row);
                 max_row = max_row.max(row);
             }
-            for col in 0..4 {
-                if (line >> (3 - col)) & 1 == 1 {
+            for col in 0..PIECE_SIZE {
+                if (line >> (PIECE_SIZE - 1 - col)) & 1 == 1 {
                     min_col = min_col.min(col);
                     max_col = max_col.max(col);
                 }
@@ -77,13 +398,13 @@ impl Renderer {
 
         // Convert the cropped piece into a vector of strings
         for row in min_row..=max_row {
-            let line = (piece >> (row * 4)) & 0b1111;
+            let line = shape_row(*piece, row);
             let mut row_str = String::new();
             for col in min_col..=max_col {
-                if (line >> (3 - col)) & 1 == 1 {
-                    row_str.push_str("🟧");
+                if (line >> (PIECE_SIZE - 1 - col)) & 1 == 1 {
+                    row_str.push(glyph(color));
                 } else {
-                    row_str.push_str("⬜");
+                    row_str.push('⬜');
                 }
             }
             result.push(row_str);
@@ -95,10 +416,20 @@ impl Renderer {
     fn get_playfield_lines(&self, state: &GameState) -> Vec<String> {
         let mut result: Vec<String> = vec![];
 
-        // Populate the grid cells of the playfield
-        for row in 0..20 {
-            let row = state.playfield[row];
-            result.push(self.render_line(&row))
+        // Populate the grid cells of the playfield, the outline is drawn in the empty cells
+        for (row, cells) in state.playfield.iter().enumerate() {
+            let colors = state.colors.get(row).map_or(&[][..], |row| &row[..]);
+            let line = self.render_line(cells, colors, state.width);
+            let overlay = state.overlay.get(row).copied().unwrap_or(0) & !cells;
+            let line = line
+                .chars()
+                .enumerate()
+                .map(|(col, cell)| match overlay >> (state.width - 1 - col) & 1 {
+                    1 => '🔲',
+                    _ => cell,
+                })
+                .collect();
+            result.push(line)
         }
 
         // Merge the active piece into playfield
@@ -106,7 +437,7 @@ impl Renderer {
             state.piece_position[0] as usize,
             state.piece_position[1] as usize,
         );
-        let piece_vec = self.render_piece(&state.active_piece);
+        let piece_vec = self.render_piece(&state.active_piece, state.active_color);
         for (row_offset, piece_row) in piece_vec.iter().enumerate() {
             let target_row = py + row_offset;
             if target_row >= state.playfield.len() {
@@ -0,0 +1,1202 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind};
+use std::{
+    io,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::config::{Action, Config, KeyBinding, KeyBindings, Preset};
+use crate::editor::{EditorRequest, EditorScreen};
+use crate::fumen::{self, Page};
+use crate::game_screen::{FRAME, GameRequest, GameScreen};
+use crate::high_scores::{self, HighScore, HighScores};
+use crate::modes::{self, Mode, Practice, Ruleset, frames_to_millis};
+use crate::modifiers::FADE_SECONDS;
+use crate::netplay::{NetplayScreen, Session};
+use crate::openers::{self, Guide, Opener};
+use crate::pieces::{self, PieceSet};
+use crate::protocol::Message;
+use crate::puzzles::{self, Attempt, Pack, Puzzle, Solved};
+use crate::renderer::Renderer;
+use crate::spectate::WatchScreen;
+use crate::tetris_engine::{GameState, Setup};
+use crate::versus::VersusScreen;
+
+// The state shared by all the screens
+#[derive(Debug)]
+pub struct Context {
+    pub config: Config,
+    // Whether the terminal reports key releases
+    pub key_releases: bool,
+    // The modes in the mode menu with the options chosen by the player
+    pub modes: [Mode; Mode::ALL.len()],
+    // The board of the practice mode, e.g. loaded from a fumen
+    pub practice: Setup,
+    // The puzzle and the opener chosen last, they are played again on retry
+    pub puzzle: Puzzle,
+    pub opener: Opener,
+}
+
+// What the active screen asks the app to do after handling a key
+#[derive(Debug)]
+pub enum Transition {
+    Stay,
+    Push(Screen),
+    Pop,
+    Replace(Screen),
+    // Drops all the screens and shows the title menu
+    ToTitle,
+    // Drops the screens above the game and starts it over
+    Restart,
+    Quit,
+}
+
+#[derive(Debug, Default)]
+pub struct Menu {
+    pub selected: usize,
+}
+
+impl Menu {
+    // Moves the selection, returns `true` when the selected item is chosen
+    fn navigate(&mut self, key: &KeyEvent, len: usize) -> bool {
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => self.selected = (self.selected + len - 1) % len,
+            KeyCode::Down | KeyCode::Char('j') | KeyCode::Tab => {
+                self.selected = (self.selected + 1) % len
+            }
+            KeyCode::Enter | KeyCode::Char(' ') => return true,
+            _ => {}
+        }
+        false
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct KeyBindingsScreen {
+    selected: usize,
+    // The next key press is bound to the selected action
+    capturing: bool,
+    status: String,
+}
+
+#[derive(Debug)]
+pub struct SettingsScreen {
+    menu: Menu,
+    status: String,
+    // The built-in piece sets and the player's ones
+    sets: Vec<Arc<PieceSet>>,
+}
+
+#[derive(Debug)]
+pub struct GameOverScreen {
+    menu: Menu,
+    // The mode to retry, the online match can't be retried from here
+    retry: Option<Mode>,
+    title: String,
+    info: Vec<String>,
+    // The result which made it into the high scores, until the player enters the name
+    record: Option<HighScore>,
+    shown: Instant,
+}
+
+#[derive(Debug)]
+pub struct PuzzlesScreen {
+    menu: Menu,
+    packs: Vec<Pack>,
+    pack: usize,
+    solved: Solved,
+    status: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct OpenersScreen {
+    menu: Menu,
+    openers: Vec<Opener>,
+    status: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct HighScoresScreen {
+    mode: Mode,
+    scores: HighScores,
+    status: String,
+}
+
+// The records with the replays, the chosen one is stepped through lock by lock
+#[derive(Debug)]
+pub struct ReplaysScreen {
+    menu: Menu,
+    // The modes of the records, the records as they're listed and their replays
+    replays: Vec<(String, String, String)>,
+    status: String,
+    // The pages of the watched replay and the one which is shown
+    watched: Option<(usize, Vec<Page>, usize)>,
+}
+
+#[derive(Debug)]
+pub enum Screen {
+    Title(Menu),
+    ModeSelect(Menu),
+    Settings(SettingsScreen),
+    KeyBindings(KeyBindingsScreen),
+    Game(Box<GameScreen>),
+    Versus(Box<VersusScreen>),
+    Netplay(Box<NetplayScreen>),
+    Watch(Box<WatchScreen>),
+    Pause(Menu),
+    GameOver(GameOverScreen),
+    HighScores(HighScoresScreen),
+    Puzzles(PuzzlesScreen),
+    Openers(OpenersScreen),
+    Editor(Box<EditorScreen>),
+    Replays(ReplaysScreen),
+}
+
+const TITLE_ITEMS: [&str; 9] = [
+    "Play",
+    "Puzzles",
+    "Openers",
+    "Editor",
+    "Versus",
+    "High scores",
+    "Replays",
+    "Settings",
+    "Quit",
+];
+const PAUSE_ITEMS: [&str; 5] = ["Resume", "Restart", "Settings", "Main menu", "Quit"];
+const GAME_OVER_ITEMS: [&str; 2] = ["Retry", "Main menu"];
+const PUZZLE_OVER_ITEMS: [&str; 3] = ["Retry", "Puzzles", "Main menu"];
+const OPENER_OVER_ITEMS: [&str; 3] = ["Retry", "Openers", "Main menu"];
+const NETPLAY_OVER_ITEMS: [&str; 1] = ["Main menu"];
+const SETTINGS_ITEMS: [&str; 12] = [
+    "Key bindings",
+    "Pieces",
+    "Gravity",
+    "Invisible",
+    "Fading",
+    "Monochrome",
+    "Big",
+    "DAS",
+    "ARR",
+    "SDF",
+    "DCD",
+    "Back",
+];
+
+const NO_INFO: [&str; 0] = [];
+
+// The keys pressed as the game ends aren't typed into the name
+const NAME_ENTRY_DELAY: Duration = Duration::from_millis(500);
+
+const MAX_SDF: u32 = 100;
+
+// Changes the duration by one frame, the handling is measured in frames in the end
+fn step_duration(duration: Duration, forward: bool) -> Duration {
+    if forward {
+        duration + FRAME
+    } else {
+        duration.saturating_sub(FRAME)
+    }
+}
+
+fn on_off(on: bool) -> &'static str {
+    if on { "on" } else { "off" }
+}
+
+// The next delay of the fading, or the previous one, the fading is off between the ends
+fn cycle_fade(fade: Option<u64>, forward: bool) -> Option<u64> {
+    let options: Vec<Option<u64>> = [None].into_iter().chain(FADE_SECONDS.map(Some)).collect();
+    let index = options.iter().position(|&o| o == fade).unwrap_or(0);
+    let len = options.len();
+    let next = if forward { index + 1 } else { index + len - 1 };
+    options[next % len]
+}
+
+impl Screen {
+    pub fn title() -> Self {
+        Screen::Title(Menu::default())
+    }
+
+    pub fn pause() -> Self {
+        Screen::Pause(Menu::default())
+    }
+
+    // The game is over: the mode's goal is reached or the player has topped out
+    pub fn game_over(game: &mut GameScreen) -> Self {
+        let (title, mut info) = if game.engine.is_finished() {
+            game.finish()
+        } else {
+            (String::from("Game over"), modes::summary(&game.engine))
+        };
+        info.push(String::new());
+        info.extend(game.stats_summary());
+        Screen::GameOver(GameOverScreen {
+            menu: Menu::default(),
+            retry: Some(game.mode),
+            title,
+            info,
+            record: Screen::high_score(game),
+            shown: Instant::now(),
+        })
+    }
+
+    // The result if it's good enough for the high scores. The races count only when finished.
+    fn high_score(game: &GameScreen) -> Option<HighScore> {
+        let engine = &game.engine;
+        if !game.mode.has_records()
+            || !game.is_standard()
+            || (game.mode.ranked_by_time() && !engine.is_finished())
+        {
+            return None;
+        }
+        let record = HighScore {
+            name: high_scores::default_name(),
+            score: engine.score(),
+            lines: engine.lines(),
+            time: frames_to_millis(engine.frames()),
+            date: high_scores::now(),
+            // The pages of the fumen replay every lock of the game
+            replay: Some(game.fumen()),
+        };
+        // The unreadable records don't stop the new one from being saved
+        let scores = HighScores::load().unwrap_or_default();
+        scores.rank(&game.mode, &record).map(|_| record)
+    }
+
+    // The winner of the versus match
+    fn versus_over(versus: &VersusScreen) -> Self {
+        let title = match versus.winner() {
+            Some(winner) => format!("Player {} wins", winner + 1),
+            None => String::from("Draw"),
+        };
+        let info = versus
+            .players
+            .iter()
+            .enumerate()
+            .map(|(i, player)| {
+                let engine = &player.engine;
+                let frames = engine.frames();
+                format!(
+                    "Player {}: {} lines, {} sent, {:.2} PPS, {:.1} APM",
+                    i + 1,
+                    engine.lines(),
+                    engine.attack_sent(),
+                    player.stats.pps(frames),
+                    player.stats.apm(frames)
+                )
+            })
+            .collect();
+        Screen::GameOver(GameOverScreen {
+            menu: Menu::default(),
+            retry: Some(Mode::Versus),
+            title,
+            info,
+            record: None,
+            shown: Instant::now(),
+        })
+    }
+
+    // The result of the online match
+    fn netplay_over(netplay: &NetplayScreen) -> Self {
+        let engine = &netplay.player.engine;
+        let opponent = &netplay.opponent;
+        let title = match (&netplay.error, engine.is_game_over(), opponent.game_over) {
+            (_, true, true) => String::from("Draw"),
+            (_, true, false) => String::from("You lose"),
+            (_, false, true) => String::from("You win"),
+            (Some(_), false, false) => String::from("Opponent disconnected"),
+            (None, false, false) => String::from("Game over"),
+        };
+        let frames = engine.frames();
+        let stats = &netplay.player.stats;
+        let mut info = vec![
+            format!(
+                "You: {} lines, {} sent, {:.2} PPS, {:.1} APM",
+                engine.lines(),
+                engine.attack_sent(),
+                stats.pps(frames),
+                stats.apm(frames)
+            ),
+            format!(
+                "Opponent: {} lines, {} sent",
+                opponent.state.lines, opponent.sent
+            ),
+        ];
+        if let Some(e) = &netplay.error
+            && e.kind() != io::ErrorKind::UnexpectedEof
+        {
+            info.push(format!("Connection error: {}", e));
+        }
+        Screen::GameOver(GameOverScreen {
+            menu: Menu::default(),
+            retry: None,
+            title,
+            info,
+            record: None,
+            shown: Instant::now(),
+        })
+    }
+
+    pub fn netplay(ctx: &Context, session: Session) -> Self {
+        Screen::Netplay(Box::new(NetplayScreen::new(
+            &ctx.config,
+            ctx.key_releases,
+            session,
+        )))
+    }
+
+    pub fn new_game(ctx: &Context, mode: Mode) -> Self {
+        match mode {
+            Mode::Versus => {
+                Screen::Versus(Box::new(VersusScreen::new(&ctx.config, ctx.key_releases)))
+            }
+            Mode::Practice | Mode::Puzzle | Mode::Opener => {
+                let ruleset: Box<dyn Ruleset> = match mode {
+                    Mode::Practice => Box::new(Practice {
+                        setup: ctx.practice.clone(),
+                    }),
+                    Mode::Puzzle => Box::new(Attempt::new(ctx.puzzle.clone())),
+                    _ => Box::new(Guide::new(ctx.opener.clone())),
+                };
+                Screen::Game(Box::new(GameScreen::with_ruleset(
+                    &ctx.config,
+                    ctx.key_releases,
+                    mode,
+                    ruleset,
+                )))
+            }
+            _ => Screen::Game(Box::new(GameScreen::new(
+                &ctx.config,
+                ctx.key_releases,
+                mode,
+            ))),
+        }
+    }
+
+    // The published game has ended
+    fn watch_over(watch: &WatchScreen) -> Self {
+        let info = match watch.error_message() {
+            Some(e) => vec![format!("Connection error: {}", e)],
+            None => Vec::new(),
+        };
+        Screen::GameOver(GameOverScreen {
+            menu: Menu::default(),
+            retry: None,
+            title: String::from("The stream has ended"),
+            info,
+            record: None,
+            shown: Instant::now(),
+        })
+    }
+
+    // The board of the player for the spectators, as the player sees it
+    pub fn snapshot(&self) -> Option<Message> {
+        let game = match self {
+            Screen::Game(game) => game,
+            Screen::Versus(versus) => &versus.players[0],
+            Screen::Netplay(netplay) => &netplay.player,
+            _ => return None,
+        };
+        let engine = &game.engine;
+        Some(Message::Board {
+            state: game.state(),
+            pending: engine.pending_garbage(),
+            sent: engine.attack_sent(),
+        })
+    }
+
+    // The screens which run the game simulation
+    pub fn is_playing(&self) -> bool {
+        matches!(
+            self,
+            Screen::Game(_) | Screen::Versus(_) | Screen::Netplay(_)
+        )
+    }
+
+    // The online match goes on regardless of the player, it's never paused
+    pub fn is_pausable(&self) -> bool {
+        matches!(self, Screen::Game(_) | Screen::Versus(_))
+    }
+
+    // Whether the game fits the terminal
+    pub fn fits(&self, renderer: &Renderer) -> bool {
+        match self {
+            Screen::Game(game) => renderer.fits_game(&game.state()),
+            Screen::Versus(versus) => {
+                let [first, second] = versus.players.each_ref().map(GameScreen::state);
+                renderer.fits_versus([&first, &second])
+            }
+            Screen::Netplay(netplay) => {
+                let local = netplay.player.state();
+                renderer.fits_versus([&local, &netplay.opponent.state])
+            }
+            Screen::Watch(watch) => renderer.fits_game(&watch.board.state),
+            _ => renderer.fits(),
+        }
+    }
+
+    pub fn pause_game(&mut self) {
+        match self {
+            Screen::Game(game) => game.pause(),
+            Screen::Versus(versus) => versus.pause(),
+            _ => {}
+        }
+    }
+
+    // Continues the paused game. The puzzle list shows the puzzle solved in the game above it.
+    pub fn resume_game(&mut self, config: &Config) {
+        match self {
+            Screen::Game(game) if game.engine.is_paused() => game.resume(config),
+            Screen::Versus(versus) if versus.is_paused() => versus.resume(config),
+            Screen::Puzzles(screen) => screen.reload(),
+            _ => {}
+        }
+    }
+
+    pub fn restart_game(&mut self) {
+        match self {
+            Screen::Game(game) => game.restart(),
+            Screen::Versus(versus) => versus.restart(),
+            _ => {}
+        }
+    }
+
+    // The time when the game has to simulate the next frame
+    pub fn frame_end(&self) -> Option<Instant> {
+        match self {
+            Screen::Game(game) => Some(game.frame_end()),
+            Screen::Versus(versus) => Some(versus.frame_end()),
+            Screen::Netplay(netplay) => Some(netplay.frame_end()),
+            // The stream is checked every frame, so the delay stays the same
+            Screen::Watch(_) => Some(Instant::now() + FRAME),
+            _ => None,
+        }
+    }
+
+    // Simulates the game up to the given time, returns the next screen when the game is over
+    pub fn advance(&mut self, now: Instant) -> Option<Screen> {
+        match self {
+            Screen::Game(game) => {
+                game.advance(now);
+                let over = game.engine.is_finished() || game.engine.is_game_over();
+                over.then(|| Screen::game_over(game))
+            }
+            Screen::Versus(versus) => {
+                versus.advance(now);
+                versus.is_over().then(|| Screen::versus_over(versus))
+            }
+            Screen::Netplay(netplay) => {
+                netplay.advance(now);
+                netplay.is_over().then(|| Screen::netplay_over(netplay))
+            }
+            Screen::Watch(watch) => {
+                watch.advance(now);
+                watch.is_over().then(|| Screen::watch_over(watch))
+            }
+            _ => None,
+        }
+    }
+
+    // The game has changed since it was rendered
+    pub fn needs_render(&self) -> bool {
+        match self {
+            Screen::Game(game) => game.engine.changed,
+            Screen::Versus(versus) => versus.players.iter().any(|p| p.engine.changed),
+            Screen::Netplay(netplay) => netplay.needs_render(),
+            Screen::Watch(watch) => watch.changed,
+            _ => false,
+        }
+    }
+
+    pub fn handle_key(&mut self, key: &KeyEvent, ctx: &mut Context) -> Transition {
+        // Only the game is interested in the key releases and repeats
+        if key.kind != KeyEventKind::Press && !self.is_playing() {
+            return Transition::Stay;
+        }
+
+        match self {
+            Screen::Title(menu) => {
+                if matches!(key.code, KeyCode::Esc | KeyCode::Char('q')) {
+                    return Transition::Quit;
+                }
+                if !menu.navigate(key, TITLE_ITEMS.len()) {
+                    return Transition::Stay;
+                }
+                match TITLE_ITEMS[menu.selected] {
+                    "Play" => Transition::Push(Screen::ModeSelect(Menu::default())),
+                    "Puzzles" => Transition::Push(Screen::Puzzles(PuzzlesScreen::new())),
+                    "Openers" => Transition::Push(Screen::Openers(OpenersScreen::new())),
+                    "Editor" => Transition::Push(Screen::Editor(Box::new(EditorScreen::new()))),
+                    "Versus" => Transition::Push(Screen::new_game(ctx, Mode::Versus)),
+                    "High scores" => {
+                        Transition::Push(Screen::HighScores(HighScoresScreen::new(ctx.modes[0])))
+                    }
+                    "Replays" => Transition::Push(Screen::Replays(ReplaysScreen::new())),
+                    "Settings" => Transition::Push(Screen::Settings(SettingsScreen::new())),
+                    _ => Transition::Quit,
+                }
+            }
+            Screen::ModeSelect(menu) => {
+                let mode = &mut ctx.modes[menu.selected];
+                match key.code {
+                    KeyCode::Esc => return Transition::Pop,
+                    KeyCode::Left | KeyCode::Right => {
+                        *mode = mode.cycled(key.code == KeyCode::Right);
+                        return Transition::Stay;
+                    }
+                    _ => {}
+                }
+                if !menu.navigate(key, ctx.modes.len()) {
+                    return Transition::Stay;
+                }
+                Transition::Replace(Screen::new_game(ctx, ctx.modes[menu.selected]))
+            }
+            Screen::Settings(screen) => screen.handle_key(key, &mut ctx.config),
+            Screen::KeyBindings(screen) => screen.handle_key(key, &mut ctx.config),
+            Screen::Game(game) => Screen::on_request(game.handle_key(key, &ctx.config.keys)),
+            Screen::Versus(versus) => Screen::on_request(versus.handle_key(key, &ctx.config)),
+            Screen::Netplay(netplay) => {
+                Screen::on_request(netplay.handle_key(key, &ctx.config.keys))
+            }
+            Screen::Watch(_) => match key.code {
+                KeyCode::Esc | KeyCode::Char('q') => Transition::Quit,
+                _ => Transition::Stay,
+            },
+            Screen::Pause(menu) => {
+                // The same key resumes the game
+                if key.code == KeyCode::Esc
+                    || ctx.config.keys.action_for(key) == Some(Action::Pause)
+                {
+                    return Transition::Pop;
+                }
+                if !menu.navigate(key, PAUSE_ITEMS.len()) {
+                    return Transition::Stay;
+                }
+                match PAUSE_ITEMS[menu.selected] {
+                    "Resume" => Transition::Pop,
+                    "Restart" => Transition::Restart,
+                    "Settings" => Transition::Push(Screen::Settings(SettingsScreen::new())),
+                    "Main menu" => Transition::ToTitle,
+                    _ => Transition::Quit,
+                }
+            }
+            Screen::GameOver(screen) if screen.record.is_some() => {
+                screen.enter_name(key);
+                Transition::Stay
+            }
+            Screen::GameOver(screen) => {
+                if key.code == KeyCode::Esc {
+                    return Transition::ToTitle;
+                }
+                if !screen.menu.navigate(key, screen.items().len()) {
+                    return Transition::Stay;
+                }
+                match (screen.items()[screen.menu.selected], screen.retry) {
+                    ("Retry", Some(mode)) => Transition::Replace(Screen::new_game(ctx, mode)),
+                    // The list of the puzzles or the openers is under the game
+                    ("Puzzles" | "Openers", _) => Transition::Pop,
+                    _ => Transition::ToTitle,
+                }
+            }
+            Screen::HighScores(screen) => screen.handle_key(key, ctx),
+            Screen::Puzzles(screen) => screen.handle_key(key, ctx),
+            Screen::Openers(screen) => screen.handle_key(key, ctx),
+            // The edited position is played as the practice, the retry starts it over
+            Screen::Editor(editor) => match editor.handle_key(key) {
+                Some(EditorRequest::Play(setup)) => {
+                    ctx.practice = setup;
+                    Transition::Push(Screen::new_game(ctx, Mode::Practice))
+                }
+                Some(EditorRequest::Back) => Transition::Pop,
+                None => Transition::Stay,
+            },
+            Screen::Replays(screen) => screen.handle_key(key),
+        }
+    }
+
+    // The mode with its option, e.g. `Sprint 40 lines`
+    fn mode_title(mode: &Mode) -> String {
+        match mode.option() {
+            Some(option) => format!("{} {}", mode.name(), option),
+            None => String::from(mode.name()),
+        }
+    }
+
+    fn on_request(request: Option<GameRequest>) -> Transition {
+        match request {
+            Some(GameRequest::Pause) => Transition::Push(Screen::pause()),
+            Some(GameRequest::Quit) => Transition::Quit,
+            Some(GameRequest::KeyBindings) => {
+                Transition::Push(Screen::KeyBindings(KeyBindingsScreen::default()))
+            }
+            None => Transition::Stay,
+        }
+    }
+
+    pub fn render(&mut self, renderer: &mut Renderer, ctx: &Context) -> io::Result<()> {
+        match self {
+            Screen::Title(menu) => {
+                renderer.flush_menu("R A T R I X", &NO_INFO, &TITLE_ITEMS, menu.selected)
+            }
+            Screen::ModeSelect(menu) => {
+                let items = ctx.modes.map(|mode| match mode.option() {
+                    Some(option) => format!("{:<9} < {} >", mode.name(), option),
+                    None => String::from(mode.name()),
+                });
+                renderer.flush_menu("Select mode", &NO_INFO, &items, menu.selected)
+            }
+            Screen::Settings(screen) => {
+                let handling = &ctx.config.handling;
+                let modifiers = &ctx.config.modifiers;
+                let items = [
+                    String::from(SETTINGS_ITEMS[0]),
+                    format!("Pieces < {} >", screen.set_name(&ctx.config.pieces)),
+                    format!(
+                        "Gravity < {} >",
+                        if ctx.config.cascade {
+                            "cascade"
+                        } else {
+                            "naive"
+                        }
+                    ),
+                    format!("Invisible < {} >", on_off(modifiers.invisible)),
+                    format!(
+                        "Fading < {} >",
+                        modifiers
+                            .fade
+                            .map_or(String::from("off"), |seconds| format!("{} s", seconds))
+                    ),
+                    format!("Monochrome < {} >", on_off(modifiers.monochrome)),
+                    format!("Big < {} >", on_off(modifiers.big)),
+                    format!("DAS  < {} ms >", handling.das.as_millis()),
+                    format!("ARR  < {} ms >", handling.arr.as_millis()),
+                    format!("SDF  < {}x >", handling.sdf),
+                    format!("DCD  < {} ms >", handling.dcd.as_millis()),
+                    String::from(SETTINGS_ITEMS[11]),
+                ];
+                // The pieces, the gravity and the big mode are only for some of the modes
+                let selected = SETTINGS_ITEMS[screen.menu.selected];
+                let info = if screen.status.is_empty()
+                    && ["Pieces", "Gravity", "Big"].contains(&selected)
+                {
+                    [String::from(
+                        "Not used by the practice, drills, puzzles, openers and online",
+                    )]
+                } else {
+                    [screen.status.clone()]
+                };
+                renderer.flush_menu("Settings", &info, &items, screen.menu.selected)
+            }
+            Screen::KeyBindings(screen) => renderer.flush_key_bindings(
+                &ctx.config.keys,
+                screen.selected,
+                screen.capturing,
+                &screen.status,
+            ),
+            Screen::Game(game) => {
+                game.engine.changed = false;
+                renderer.flush_changes(&game.state(), &game.hud())
+            }
+            Screen::Versus(versus) => {
+                let players = versus.players.each_mut().map(|player| {
+                    player.engine.changed = false;
+                    let pending = player.engine.pending_garbage();
+                    (player.state(), player.hud(), pending)
+                });
+                renderer.flush_versus(&players)
+            }
+            Screen::Netplay(netplay) => {
+                let player = &mut netplay.player;
+                player.engine.changed = false;
+                let local = (
+                    player.state(),
+                    player.hud(),
+                    player.engine.pending_garbage(),
+                );
+                let opponent = netplay.take_opponent();
+                let remote = (
+                    opponent.state.clone(),
+                    vec![String::from("Sent"), opponent.sent.to_string()],
+                    opponent.pending,
+                );
+                renderer.flush_versus(&[local, remote])
+            }
+            Screen::Watch(watch) => {
+                watch.changed = false;
+                renderer.flush_changes(&watch.board.state, &watch.hud())
+            }
+            Screen::Pause(menu) => renderer.flush_pause(&PAUSE_ITEMS, menu.selected),
+            Screen::GameOver(screen) => match &screen.record {
+                Some(record) => {
+                    let mut info = screen.info.clone();
+                    info.push(String::new());
+                    info.push(String::from("New high score! Enter your name:"));
+                    renderer.flush_menu(&screen.title, &info, &[format!("{}_", record.name)], 0)
+                }
+                None => renderer.flush_menu(
+                    &screen.title,
+                    &screen.info,
+                    screen.items(),
+                    screen.menu.selected,
+                ),
+            },
+            Screen::HighScores(screen) => {
+                let mut info = vec![
+                    format!("< {} >", Screen::mode_title(&screen.mode)),
+                    String::new(),
+                ];
+                info.extend(screen.lines(None));
+                if !screen.status.is_empty() {
+                    info.push(String::new());
+                    info.push(screen.status.clone());
+                }
+                renderer.flush_menu("High scores", &info, &["Back"], 0)
+            }
+            Screen::Puzzles(screen) => {
+                let pack = &screen.packs[screen.pack];
+                let solved = pack
+                    .puzzles
+                    .iter()
+                    .filter(|puzzle| screen.solved.contains(puzzle))
+                    .count();
+                let mut info = vec![format!(
+                    "< {} >  {}/{} solved",
+                    pack.name,
+                    solved,
+                    pack.puzzles.len()
+                )];
+                info.extend(screen.status.iter().cloned());
+                renderer.flush_menu("Puzzles", &info, &screen.items(), screen.menu.selected)
+            }
+            Screen::Openers(screen) => {
+                let mut items: Vec<&str> = screen
+                    .openers
+                    .iter()
+                    .map(|opener| opener.name.as_str())
+                    .collect();
+                items.push("Back");
+                renderer.flush_menu("Openers", &screen.status, &items, screen.menu.selected)
+            }
+            Screen::Editor(editor) => renderer.flush_editor(
+                &editor.state(),
+                &editor.hud(),
+                editor.cursor(),
+                editor.status(),
+            ),
+            Screen::Replays(screen) => match screen.page() {
+                Some(state) => renderer.flush_changes(&state, &screen.hud()),
+                None => {
+                    let mut items: Vec<&str> = screen
+                        .replays
+                        .iter()
+                        .map(|(_, line, _)| line.as_str())
+                        .collect();
+                    items.push("Back");
+                    let info = match (screen.status.as_str(), items.len()) {
+                        ("", 1) => "No replays yet",
+                        (status, _) => status,
+                    };
+                    renderer.flush_menu("Replays", &[info], &items, screen.menu.selected)
+                }
+            },
+        }
+    }
+}
+
+impl GameOverScreen {
+    // The name is typed right on the game over screen, then the record is saved
+    fn enter_name(&mut self, key: &KeyEvent) {
+        let Some(record) = &mut self.record else {
+            return;
+        };
+        if self.shown.elapsed() < NAME_ENTRY_DELAY {
+            return;
+        }
+        match key.code {
+            KeyCode::Char(c) if record.name.chars().count() < high_scores::MAX_NAME_LEN => {
+                record.name.push(c)
+            }
+            KeyCode::Backspace => {
+                record.name.pop();
+            }
+            // The record isn't saved at all
+            KeyCode::Esc => self.record = None,
+            KeyCode::Enter => self.save_record(),
+            _ => {}
+        }
+    }
+
+    fn save_record(&mut self) {
+        let (Some(mut record), Some(mode)) = (self.record.take(), self.retry) else {
+            return;
+        };
+        record.name = high_scores::clean_name(&record.name);
+        self.info.push(String::new());
+        match HighScores::submit(&mode, record) {
+            Ok((scores, rank)) => {
+                let screen = HighScoresScreen {
+                    mode,
+                    scores,
+                    status: String::new(),
+                };
+                self.info.extend(screen.lines(rank));
+            }
+            Err(e) => self.info.push(format!("Couldn't save the record: {}", e)),
+        }
+    }
+
+    fn items(&self) -> &'static [&'static str] {
+        match self.retry {
+            Some(Mode::Puzzle) => &PUZZLE_OVER_ITEMS,
+            Some(Mode::Opener) => &OPENER_OVER_ITEMS,
+            Some(_) => &GAME_OVER_ITEMS,
+            None => &NETPLAY_OVER_ITEMS,
+        }
+    }
+}
+
+impl PuzzlesScreen {
+    fn new() -> Self {
+        let (packs, mut status) = puzzles::packs();
+        let solved = Solved::load().unwrap_or_else(|e| {
+            status.push(format!("Couldn't read the solved puzzles: {}", e));
+            Solved::default()
+        });
+        Self {
+            menu: Menu::default(),
+            packs,
+            pack: 0,
+            solved,
+            status,
+        }
+    }
+
+    fn reload(&mut self) {
+        if let Ok(solved) = Solved::load() {
+            self.solved = solved;
+        }
+    }
+
+    // The puzzles of the pack with their goals, the solved ones are checked
+    fn items(&self) -> Vec<String> {
+        let mut items: Vec<String> = self.packs[self.pack]
+            .puzzles
+            .iter()
+            .map(|puzzle| {
+                let mark = if self.solved.contains(puzzle) {
+                    'x'
+                } else {
+                    ' '
+                };
+                format!("[{}] {:<16} {}", mark, puzzle.name, puzzle.goal.describe())
+            })
+            .collect();
+        items.push(String::from("Back"));
+        items
+    }
+
+    // Left and Right choose the pack
+    fn handle_key(&mut self, key: &KeyEvent, ctx: &mut Context) -> Transition {
+        let len = self.packs.len();
+        match key.code {
+            KeyCode::Esc => return Transition::Pop,
+            KeyCode::Left | KeyCode::Right => {
+                self.pack = match key.code {
+                    KeyCode::Right => (self.pack + 1) % len,
+                    _ => (self.pack + len - 1) % len,
+                };
+                self.menu = Menu::default();
+                return Transition::Stay;
+            }
+            _ => {}
+        }
+        let puzzles = &self.packs[self.pack].puzzles;
+        if !self.menu.navigate(key, puzzles.len() + 1) {
+            return Transition::Stay;
+        }
+        match puzzles.get(self.menu.selected) {
+            Some(puzzle) => {
+                ctx.puzzle = puzzle.clone();
+                Transition::Push(Screen::new_game(ctx, Mode::Puzzle))
+            }
+            None => Transition::Pop,
+        }
+    }
+}
+
+impl OpenersScreen {
+    fn new() -> Self {
+        let (openers, status) = openers::openers();
+        Self {
+            menu: Menu::default(),
+            openers,
+            status,
+        }
+    }
+
+    fn handle_key(&mut self, key: &KeyEvent, ctx: &mut Context) -> Transition {
+        if key.code == KeyCode::Esc {
+            return Transition::Pop;
+        }
+        if !self.menu.navigate(key, self.openers.len() + 1) {
+            return Transition::Stay;
+        }
+        match self.openers.get(self.menu.selected) {
+            Some(opener) => {
+                ctx.opener = opener.clone();
+                Transition::Push(Screen::new_game(ctx, Mode::Opener))
+            }
+            None => Transition::Pop,
+        }
+    }
+}
+
+impl ReplaysScreen {
+    fn new() -> Self {
+        let (scores, status) = match HighScores::load() {
+            Ok(scores) => (scores, String::new()),
+            Err(e) => (
+                HighScores::default(),
+                format!("Couldn't read the records: {}", e),
+            ),
+        };
+        let replays = scores
+            .replays()
+            .into_iter()
+            .map(|(key, rank, entry)| {
+                let mode =
+                    Mode::from_key(key).map_or(String::from(key), |mode| Screen::mode_title(&mode));
+                let line = format!("{:<20}{}", mode, entry.format(rank));
+                (mode, line, entry.replay.clone().unwrap_or_default())
+            })
+            .collect();
+        Self {
+            menu: Menu::default(),
+            replays,
+            status,
+            watched: None,
+        }
+    }
+
+    fn handle_key(&mut self, key: &KeyEvent) -> Transition {
+        if let Some((_, pages, page)) = &mut self.watched {
+            match key.code {
+                KeyCode::Esc | KeyCode::Enter => self.watched = None,
+                KeyCode::Left => *page = page.saturating_sub(1),
+                KeyCode::Right => *page = (*page + 1).min(pages.len() - 1),
+                KeyCode::Home => *page = 0,
+                KeyCode::End => *page = pages.len() - 1,
+                _ => {}
+            }
+            return Transition::Stay;
+        }
+        if key.code == KeyCode::Esc {
+            return Transition::Pop;
+        }
+        if !self.menu.navigate(key, self.replays.len() + 1) {
+            return Transition::Stay;
+        }
+        let Some((_, _, replay)) = self.replays.get(self.menu.selected) else {
+            return Transition::Pop;
+        };
+        match fumen::decode(replay) {
+            Ok(pages) if pages.is_empty() => {
+                self.status = String::from("The replay has no placements")
+            }
+            Ok(pages) => {
+                self.status.clear();
+                self.watched = Some((self.menu.selected, pages, 0));
+            }
+            Err(e) => self.status = format!("Couldn't read the replay: {}", e),
+        }
+        Transition::Stay
+    }
+
+    // The board of the shown page with its placement outlined
+    fn page(&self) -> Option<GameState> {
+        let (_, pages, page) = self.watched.as_ref()?;
+        let page = &pages[*page];
+        Some(GameState {
+            playfield: page.playfield.to_vec(),
+            overlay: page
+                .operation
+                .map_or(Vec::new(), |operation| operation.cells.to_vec()),
+            ..GameState::default()
+        })
+    }
+
+    fn hud(&self) -> Vec<String> {
+        let Some((replay, pages, page)) = &self.watched else {
+            return Vec::new();
+        };
+        let mut hud = vec![String::from("Replay"), self.replays[*replay].0.clone()];
+        hud.extend([
+            String::new(),
+            format!("Placement {}/{}", page + 1, pages.len()),
+            String::new(),
+            String::from("Left/Right step"),
+            String::from("Home/End ends"),
+            String::from("Esc back"),
+        ]);
+        hud
+    }
+}
+
+impl HighScoresScreen {
+    fn new(mode: Mode) -> Self {
+        let (scores, status) = match HighScores::load() {
+            Ok(scores) => (scores, String::new()),
+            Err(e) => (
+                HighScores::default(),
+                format!("Couldn't read the records: {}", e),
+            ),
+        };
+        Self {
+            mode,
+            scores,
+            status,
+        }
+    }
+
+    // The table of the mode, the new record is marked
+    fn lines(&self, new: Option<usize>) -> Vec<String> {
+        let table = self.scores.table(&self.mode);
+        if table.is_empty() {
+            return vec![String::from("No high scores yet")];
+        }
+        let mut lines = vec![high_scores::header()];
+        for (rank, entry) in table.iter().enumerate() {
+            let marker = if new == Some(rank) { " <" } else { "" };
+            lines.push(format!("{}{}", entry.format(rank), marker));
+        }
+        lines
+    }
+
+    // Left and Right choose the mode, Up and Down its option
+    fn handle_key(&mut self, key: &KeyEvent, ctx: &Context) -> Transition {
+        let modes: Vec<Mode> = ctx.modes.into_iter().filter(Mode::has_records).collect();
+        let index = modes
+            .iter()
+            .position(|mode| mode.name() == self.mode.name())
+            .unwrap_or(0);
+        match key.code {
+            KeyCode::Esc | KeyCode::Enter => return Transition::Pop,
+            KeyCode::Right => self.mode = modes[(index + 1) % modes.len()],
+            KeyCode::Left => self.mode = modes[(index + modes.len() - 1) % modes.len()],
+            KeyCode::Down => self.mode = self.mode.cycled(true),
+            KeyCode::Up => self.mode = self.mode.cycled(false),
+            _ => {}
+        }
+        Transition::Stay
+    }
+}
+
+impl SettingsScreen {
+    fn new() -> Self {
+        let (sets, errors) = pieces::sets();
+        Self {
+            menu: Menu::default(),
+            status: errors.join("; "),
+            sets,
+        }
+    }
+
+    // The sets which aren't found any more are shown by their id
+    fn set_name<'a>(&'a self, id: &'a str) -> &'a str {
+        self.sets
+            .iter()
+            .find(|set| set.id == id)
+            .map_or(id, |set| &set.name)
+    }
+
+    // The next set after the chosen one, or the one before it
+    fn cycle_set(&self, config: &mut Config, forward: bool) {
+        let len = self.sets.len();
+        let index = self
+            .sets
+            .iter()
+            .position(|set| set.id == config.pieces)
+            .unwrap_or(0);
+        let next = if forward { index + 1 } else { index + len - 1 };
+        config.pieces = self.sets[next % len].id.clone();
+    }
+
+    fn handle_key(&mut self, key: &KeyEvent, config: &mut Config) -> Transition {
+        let handling = &mut config.handling;
+        let forward = key.code == KeyCode::Right;
+        if matches!(key.code, KeyCode::Left | KeyCode::Right) {
+            match SETTINGS_ITEMS[self.menu.selected] {
+                "Pieces" => self.cycle_set(config, forward),
+                "Gravity" => config.cascade = !config.cascade,
+                "Invisible" => config.modifiers.invisible = !config.modifiers.invisible,
+                "Fading" => config.modifiers.fade = cycle_fade(config.modifiers.fade, forward),
+                "Monochrome" => config.modifiers.monochrome = !config.modifiers.monochrome,
+                "Big" => config.modifiers.big = !config.modifiers.big,
+                "DAS" => handling.das = step_duration(handling.das, forward),
+                "ARR" => handling.arr = step_duration(handling.arr, forward),
+                "DCD" => handling.dcd = step_duration(handling.dcd, forward),
+                "SDF" if forward => handling.sdf = (handling.sdf + 1).min(MAX_SDF),
+                "SDF" => handling.sdf = (handling.sdf - 1).max(1),
+                _ => {}
+            }
+            return Transition::Stay;
+        }
+
+        let chosen = self.menu.navigate(key, SETTINGS_ITEMS.len());
+        match SETTINGS_ITEMS[self.menu.selected] {
+            "Key bindings" if chosen => {
+                Transition::Push(Screen::KeyBindings(KeyBindingsScreen::default()))
+            }
+            "Back" if chosen => self.save_and_close(config),
+            _ if key.code == KeyCode::Esc => self.save_and_close(config),
+            _ => Transition::Stay,
+        }
+    }
+
+    fn save_and_close(&mut self, config: &Config) -> Transition {
+        match config.save() {
+            Ok(()) => Transition::Pop,
+            Err(e) => {
+                self.status = format!("Couldn't save the config: {}", e);
+                Transition::Stay
+            }
+        }
+    }
+}
+
+impl KeyBindingsScreen {
+    fn handle_key(&mut self, key: &KeyEvent, config: &mut Config) -> Transition {
+        let action = Action::ALL[self.selected];
+
+        if self.capturing {
+            // Esc cancels the capture, so it can't be bound
+            if key.code != KeyCode::Esc {
+                config.keys.add(action, KeyBinding::from_event(key));
+            }
+            self.capturing = false;
+            return Transition::Stay;
+        }
+
+        // The navigation keys of this screen are fixed,
+        // so a broken config can always be fixed from the game
+        match key.code {
+            KeyCode::Up => {
+                self.selected = (self.selected + Action::ALL.len() - 1) % Action::ALL.len()
+            }
+            KeyCode::Down => self.selected = (self.selected + 1) % Action::ALL.len(),
+            KeyCode::Enter => self.capturing = true,
+            KeyCode::Backspace | KeyCode::Delete => config.keys.set(action, Vec::new()),
+            KeyCode::Tab => {
+                let current = Preset::ALL.iter().position(|p| *p == config.keys.preset);
+                let next = Preset::ALL[(current.unwrap_or(0) + 1) % Preset::ALL.len()];
+                config.keys = KeyBindings::from_preset(next);
+            }
+            KeyCode::Esc => match config.save() {
+                Ok(()) => return Transition::Pop,
+                Err(e) => self.status = format!("Couldn't save the config: {}", e),
+            },
+            _ => {}
+        }
+        Transition::Stay
+    }
+}
@@ -0,0 +1,262 @@
+use std::{
+    collections::VecDeque,
+    io::{self, ErrorKind},
+    net::{TcpListener, TcpStream},
+    time::{Duration, Instant},
+};
+
+use crate::netplay::RemoteBoard;
+use crate::protocol::{Connection, Message, PROTOCOL_VERSION};
+use crate::tetris_engine::GameState;
+
+pub const DEFAULT_PORT: u16 = 7341;
+// The board is sent right after the hello if the game has been started
+const BOARD_TIMEOUT: Duration = Duration::from_secs(2);
+// The spectators see every board this much later than it was played
+const WATCH_DELAY: Duration = Duration::from_millis(500);
+// The spectator who falls that many bytes behind is disconnected, so the stalled one
+// doesn't make the game keep the whole stream
+const MAX_BACKLOG: usize = 64 * 1024;
+
+// Sends the boards of the running game to every connected spectator.
+// The spectators only listen, the game never waits for them.
+#[derive(Debug)]
+pub struct Publisher {
+    listener: TcpListener,
+    spectators: Vec<Connection>,
+    // The board for the spectators who connect in the middle of the game
+    last_board: Option<Message>,
+}
+
+impl Publisher {
+    // Only the spectators on the same computer can connect
+    pub fn bind(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            spectators: Vec::new(),
+            last_board: None,
+        })
+    }
+
+    fn accept(&mut self) {
+        while let Ok((stream, _)) = self.listener.accept() {
+            let Ok(mut spectator) = Connection::new(stream) else {
+                continue;
+            };
+            let hello = Message::Hello {
+                version: PROTOCOL_VERSION,
+                seed: 0,
+            };
+            let sent = spectator.send(&hello).and_then(|_| match &self.last_board {
+                Some(board) => spectator.send(board),
+                None => Ok(()),
+            });
+            if sent.is_ok() {
+                self.spectators.push(spectator);
+            }
+        }
+    }
+
+    // Welcomes the new spectators and forgets the ones who have left
+    pub fn update(&mut self) {
+        self.accept();
+        self.spectators
+            .retain_mut(|spectator| spectator.poll().is_ok() && spectator.backlog() <= MAX_BACKLOG);
+    }
+
+    pub fn publish(&mut self, message: Message) {
+        self.accept();
+        self.spectators.retain_mut(|spectator| {
+            spectator.send(&message).is_ok() && spectator.backlog() <= MAX_BACKLOG
+        });
+        if let Message::Board { .. } = message {
+            self.last_board = Some(message);
+        }
+    }
+}
+
+// The current board of the published game
+pub fn published_board(port: u16) -> io::Result<GameState> {
+    let mut connection = connect(port)?;
+    match connection.receive(BOARD_TIMEOUT) {
+        Ok(Message::Board { state, .. }) => Ok(state),
+        Ok(_) => Err(io::Error::new(ErrorKind::InvalidData, "Unexpected message")),
+        Err(e) if e.kind() == ErrorKind::TimedOut => Err(io::Error::new(
+            ErrorKind::NotFound,
+            "The published game hasn't been started yet",
+        )),
+        Err(e) => Err(e),
+    }
+}
+
+// The read-only view of the game published on another terminal
+#[derive(Debug)]
+pub struct WatchScreen {
+    connection: Connection,
+    // The received messages with the time when they are shown
+    delayed: VecDeque<(Instant, Message)>,
+    pub board: RemoteBoard,
+    pub changed: bool,
+    // The reason why the stream has ended
+    pub error: Option<io::Error>,
+}
+
+// Connects to the game published on the same computer
+fn connect(port: u16) -> io::Result<Connection> {
+    let stream = TcpStream::connect(("127.0.0.1", port))?;
+    let mut connection = Connection::new(stream)?;
+    connection.receive_hello()?;
+    Ok(connection)
+}
+
+pub fn watch(port: u16) -> io::Result<WatchScreen> {
+    let connection = connect(port)?;
+    Ok(WatchScreen {
+        connection,
+        delayed: VecDeque::new(),
+        board: RemoteBoard::default(),
+        changed: true,
+        error: None,
+    })
+}
+
+impl WatchScreen {
+    // The stream has ended and everything received is shown
+    pub fn is_over(&self) -> bool {
+        self.error.is_some() && self.delayed.is_empty()
+    }
+
+    pub fn hud(&self) -> Vec<String> {
+        let mut hud = vec![
+            String::from("Spectating"),
+            String::new(),
+            String::from("Sent"),
+            self.board.sent.to_string(),
+        ];
+        if self.board.game_over {
+            hud.push(String::new());
+            hud.push(String::from("Game over"));
+        }
+        hud
+    }
+
+    pub fn advance(&mut self, now: Instant) {
+        if self.error.is_none() {
+            match self.connection.poll() {
+                Ok(messages) => self
+                    .delayed
+                    .extend(messages.into_iter().map(|m| (now + WATCH_DELAY, m))),
+                Err(e) => self.error = Some(e),
+            }
+        }
+
+        while self.delayed.front().is_some_and(|(time, _)| *time <= now) {
+            let Some((_, message)) = self.delayed.pop_front() else {
+                break;
+            };
+            match message {
+                Message::Board {
+                    state,
+                    pending,
+                    sent,
+                } => {
+                    self.board.state = state;
+                    self.board.pending = pending;
+                    self.board.sent = sent;
+                    // The player has started over
+                    self.board.game_over = false;
+                }
+                Message::GameOver => self.board.game_over = true,
+                Message::Hello { .. } | Message::Lock { .. } | Message::Garbage(_) => {}
+            }
+            self.changed = true;
+        }
+    }
+
+    // The end of the stream isn't an error when the player has simply quit
+    pub fn error_message(&self) -> Option<String> {
+        match &self.error {
+            Some(e) if e.kind() != ErrorKind::UnexpectedEof => Some(e.to_string()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn every_spectator_gets_the_boards_with_a_delay() {
+        let mut publisher = Publisher::bind(0).unwrap();
+        let port = publisher.listener.local_addr().unwrap().port();
+        let board = Message::Board {
+            state: GameState {
+                score: 100,
+                ..GameState::default()
+            },
+            pending: 0,
+            sent: 2,
+        };
+        let connect = |publisher: &mut Publisher| {
+            let spectator = thread::spawn(move || watch(port).unwrap());
+            let count = publisher.spectators.len();
+            while publisher.spectators.len() == count {
+                publisher.update();
+            }
+            spectator.join().unwrap()
+        };
+        // The first spectator is there from the start, the second one comes later
+        let mut first = connect(&mut publisher);
+        publisher.publish(board);
+        let mut second = connect(&mut publisher);
+        publisher.publish(Message::GameOver);
+
+        let start = Instant::now();
+        for spectator in [&mut first, &mut second] {
+            while spectator.delayed.len() < 2 && start.elapsed().as_secs() < 5 {
+                spectator.advance(start);
+            }
+            // Nothing is shown until the delay is over
+            assert_eq!(spectator.board.sent, 0);
+            spectator.advance(Instant::now() + WATCH_DELAY * 2);
+            assert_eq!(spectator.board.state.score, 100);
+            assert_eq!(spectator.board.sent, 2);
+            assert!(spectator.board.game_over);
+        }
+
+        drop(publisher);
+        while !first.is_over() && start.elapsed().as_secs() < 5 {
+            first.advance(Instant::now());
+        }
+        assert!(first.is_over());
+        assert_eq!(first.error_message(), None);
+    }
+
+    #[test]
+    fn stalled_spectator_is_disconnected() {
+        let mut publisher = Publisher::bind(0).unwrap();
+        let port = publisher.listener.local_addr().unwrap().port();
+        let spectator = thread::spawn(move || watch(port).unwrap());
+        while publisher.spectators.is_empty() {
+            publisher.update();
+        }
+        // The spectator never reads the boards
+        let _spectator = spectator.join().unwrap();
+        let board = Message::Board {
+            state: GameState::default(),
+            pending: 0,
+            sent: 0,
+        };
+        for _ in 0..1_000_000 {
+            publisher.publish(board.clone());
+            if publisher.spectators.is_empty() {
+                break;
+            }
+        }
+        assert!(publisher.spectators.is_empty());
+    }
+}
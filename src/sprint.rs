@@ -0,0 +1,357 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, ErrorKind},
+    path::PathBuf,
+};
+
+use crate::config;
+use crate::modes::{Ruleset, format_time, frames_to_millis};
+use crate::tetris_engine::{Rules, TetrisEngine};
+
+// A split time is recorded every time this many lines are cleared
+const SPLIT_LINES: usize = 10;
+
+// The difference to the personal best, e.g. `-1.250` when the run is faster
+fn format_delta(millis: u64, best: u64) -> String {
+    let (sign, delta) = if millis <= best {
+        ('-', best - millis)
+    } else {
+        ('+', millis - best)
+    };
+    format!("{}{}.{:03}", sign, delta / 1000, delta % 1000)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct SprintResult {
+    lines: usize,
+    // All the times are in milliseconds since the first input
+    time: u64,
+    // The time when each next 10 lines were cleared
+    splits: Vec<u64>,
+}
+
+// Times the sprint in the engine frames. The clock starts on the first input,
+// so the player can look at the first pieces before the run.
+#[derive(Debug)]
+struct SprintTimer {
+    lines: usize,
+    start: Option<u64>,
+    splits: Vec<u64>,
+    finish: Option<u64>,
+}
+
+impl SprintTimer {
+    fn new(lines: usize) -> Self {
+        Self {
+            lines,
+            start: None,
+            splits: Vec::new(),
+            finish: None,
+        }
+    }
+
+    fn lines(&self) -> usize {
+        self.lines
+    }
+
+    fn start(&mut self, frame: u64) {
+        self.start.get_or_insert(frame);
+    }
+
+    fn is_started(&self) -> bool {
+        self.start.is_some()
+    }
+
+    // Records the splits and the finish, `frame` is the number of the frames simulated so far
+    fn update(&mut self, lines: usize, frame: u64) {
+        let Some(start) = self.start else {
+            return;
+        };
+        let elapsed = frame - start;
+        while self.splits.len() < lines.min(self.lines) / SPLIT_LINES {
+            self.splits.push(elapsed);
+        }
+        if lines >= self.lines {
+            self.finish.get_or_insert(elapsed);
+        }
+    }
+
+    // The time shown while the run is going on
+    fn elapsed(&self, frame: u64) -> u64 {
+        let frames = match (self.start, self.finish) {
+            (_, Some(finish)) => finish,
+            (Some(start), None) => frame - start,
+            (None, None) => 0,
+        };
+        frames_to_millis(frames)
+    }
+
+    fn result(&self) -> Option<SprintResult> {
+        Some(SprintResult {
+            lines: self.lines,
+            time: frames_to_millis(self.finish?),
+            splits: self.splits.iter().map(|&f| frames_to_millis(f)).collect(),
+        })
+    }
+}
+
+// The best sprint for every line target. The file holds a line per target:
+// `<lines> <time> <splits...>`, all the times are in milliseconds.
+#[derive(Debug, Default)]
+struct PersonalBests {
+    results: HashMap<usize, SprintResult>,
+}
+
+impl PersonalBests {
+    fn path() -> Option<PathBuf> {
+        Some(config::data_dir()?.join("sprint.txt"))
+    }
+
+    // A missing file means that there are no records yet
+    fn load() -> io::Result<Self> {
+        let Some(path) = Self::path() else {
+            return Ok(Self::default());
+        };
+        match fs::read_to_string(path) {
+            Ok(text) => Ok(Self::parse(&text)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let Some(path) = Self::path() else {
+            return Err(io::Error::new(ErrorKind::NotFound, "no data directory"));
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_text())
+    }
+
+    // The broken lines are skipped, losing a record is better than refusing to play
+    fn parse(text: &str) -> Self {
+        let mut bests = Self::default();
+        for line in text.lines() {
+            let numbers: Result<Vec<u64>, _> = line.split_whitespace().map(str::parse).collect();
+            if let Ok([lines, time, splits @ ..]) = numbers.as_deref() {
+                let result = SprintResult {
+                    lines: *lines as usize,
+                    time: *time,
+                    splits: splits.to_vec(),
+                };
+                bests.submit(result);
+            }
+        }
+        bests
+    }
+
+    fn to_text(&self) -> String {
+        let mut results: Vec<&SprintResult> = self.results.values().collect();
+        results.sort_by_key(|result| result.lines);
+        let mut text = String::new();
+        for result in results {
+            let mut numbers = vec![result.lines as u64, result.time];
+            numbers.extend(&result.splits);
+            let numbers: Vec<String> = numbers.iter().map(u64::to_string).collect();
+            text.push_str(&numbers.join(" "));
+            text.push('\n');
+        }
+        text
+    }
+
+    fn best(&self, lines: usize) -> Option<&SprintResult> {
+        self.results.get(&lines)
+    }
+
+    // Keeps the result if it's faster than the personal best, returns `true` in that case
+    fn submit(&mut self, result: SprintResult) -> bool {
+        if self
+            .best(result.lines)
+            .is_some_and(|best| best.time <= result.time)
+        {
+            return false;
+        }
+        self.results.insert(result.lines, result);
+        true
+    }
+}
+
+// Clear the lines as fast as possible, the level and the gravity don't change
+#[derive(Debug)]
+pub struct Sprint {
+    timer: SprintTimer,
+}
+
+impl Sprint {
+    pub fn new(lines: usize) -> Self {
+        Self {
+            timer: SprintTimer::new(lines),
+        }
+    }
+
+    // Compares the run with the personal best and keeps it if it's faster.
+    // The runs of the other pieces, board or rules aren't compared.
+    fn summary(result: &SprintResult, standard: bool) -> Vec<String> {
+        let mut status = None;
+        let mut previous_best = None;
+        if standard {
+            let mut bests = PersonalBests::load().unwrap_or_else(|e| {
+                status = Some(format!("Couldn't read the records: {}", e));
+                PersonalBests::default()
+            });
+            previous_best = bests.best(result.lines).cloned();
+            if bests.submit(result.clone())
+                && let Err(e) = bests.save()
+            {
+                status = Some(format!("Couldn't save the record: {}", e));
+            }
+        }
+
+        let mut info = vec![format!("Time: {}", format_time(result.time))];
+        let best = previous_best.as_ref();
+        match best {
+            Some(best) if result.time < best.time => info.push(format!(
+                "New personal best! ({})",
+                format_delta(result.time, best.time)
+            )),
+            Some(best) => info.push(format!(
+                "Personal best: {} ({})",
+                format_time(best.time),
+                format_delta(result.time, best.time)
+            )),
+            None if standard => info.push(String::from("New personal best!")),
+            None => info.push(String::from("No personal best with these settings")),
+        }
+        info.push(String::new());
+
+        // The splits are compared with the same splits of the previous best
+        for (i, split) in result.splits.iter().enumerate() {
+            let delta = best
+                .and_then(|best| best.splits.get(i))
+                .map(|best_split| format_delta(*split, *best_split))
+                .unwrap_or_default();
+            info.push(format!(
+                "{:>3} lines  {}  {}",
+                (i + 1) * SPLIT_LINES,
+                format_time(*split),
+                delta
+            ));
+        }
+        if let Some(status) = status {
+            info.push(String::new());
+            info.push(status);
+        }
+        info
+    }
+}
+
+impl Ruleset for Sprint {
+    fn rules(&self) -> Rules {
+        Rules {
+            line_goal: Some(self.timer.lines()),
+            lines_per_level: None,
+            ..Rules::default()
+        }
+    }
+
+    fn reset(&mut self) {
+        self.timer = SprintTimer::new(self.timer.lines());
+    }
+
+    fn is_waiting(&self) -> bool {
+        !self.timer.is_started()
+    }
+
+    fn on_input(&mut self, frame: u64) {
+        self.timer.start(frame);
+    }
+
+    fn update(&mut self, engine: &mut TetrisEngine, frame: u64) {
+        self.timer.update(engine.lines(), frame);
+    }
+
+    fn hud(&self, engine: &TetrisEngine, frame: u64) -> Vec<String> {
+        vec![
+            String::from("Time"),
+            format_time(self.timer.elapsed(frame)),
+            String::new(),
+            String::from("Lines"),
+            format!("{}/{}", engine.lines(), self.timer.lines()),
+        ]
+    }
+
+    fn finish(&mut self, _engine: &TetrisEngine, standard: bool) -> (String, Vec<String>) {
+        let title = format!("Sprint {} lines", self.timer.lines());
+        match self.timer.result() {
+            Some(result) => (title, Self::summary(&result, standard)),
+            None => (title, Vec::new()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_are_recorded_every_10_lines() {
+        let mut timer = SprintTimer::new(20);
+        timer.update(4, 30); // Not started yet
+        assert_eq!(timer.elapsed(30), 0);
+        timer.start(60);
+        timer.update(9, 600);
+        timer.update(12, 660);
+        assert_eq!(timer.elapsed(900), 14_000);
+        // A tetris over the goal still finishes the run
+        timer.update(22, 1260);
+        assert_eq!(timer.elapsed(2000), 20_000);
+        let result = timer.result().unwrap();
+        assert_eq!(result.time, 20_000);
+        assert_eq!(result.splits, vec![10_000, 20_000]);
+    }
+
+    #[test]
+    fn times_are_formatted_with_milliseconds() {
+        assert_eq!(format_time(63_250), "1:03.250");
+        assert_eq!(format_delta(41_000, 42_317), "-1.317");
+        assert_eq!(format_delta(42_317, 42_000), "+0.317");
+    }
+
+    #[test]
+    fn only_faster_runs_are_personal_bests() {
+        let mut bests = PersonalBests::default();
+        let run = |time| SprintResult {
+            lines: 20,
+            time,
+            splits: vec![time / 2, time],
+        };
+        assert!(bests.submit(run(30_000)));
+        assert!(!bests.submit(run(31_000)));
+        assert!(bests.submit(run(29_000)));
+        assert_eq!(bests.best(20).map(|best| best.time), Some(29_000));
+        assert_eq!(bests.best(40), None);
+    }
+
+    #[test]
+    fn variants_are_not_compared_with_the_personal_best() {
+        let run = SprintResult {
+            lines: 20,
+            time: 1_000,
+            splits: vec![500, 1_000],
+        };
+        let info = Sprint::summary(&run, false);
+        assert_eq!(info[1], "No personal best with these settings");
+        assert!(info.iter().all(|line| !line.contains("Couldn't")));
+    }
+
+    #[test]
+    fn personal_bests_survive_the_round_trip() {
+        let text = "20 29000 14500 29000\n40 61000 15000 30000 45000 61000\n";
+        assert_eq!(PersonalBests::parse(text).to_text(), text);
+        // The corrupted lines are dropped
+        let bests = PersonalBests::parse("40 61000 15000 oops\n\n20\n");
+        assert_eq!(bests.to_text(), "");
+    }
+}
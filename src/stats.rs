@@ -0,0 +1,184 @@
+use crate::config::Action;
+use crate::tetris_engine::{FRAMES_PER_SECOND, Lock};
+
+// The performance of the player: the speed and the kinds of the clears.
+// It's fed with the key presses and the locks of the engine, the time is the engine's frames.
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    pieces: usize,
+    keys: usize,
+    attack: usize,
+    lines: usize,
+    // The clears by the number of the lines, the first one is unused
+    clears: [usize; 5],
+    t_spins: usize,
+    // The clears set off by the cascade gravity
+    chains: usize,
+    finesse_faults: usize,
+}
+
+// The clears in the summary
+const CLEAR_NAMES: [&str; 5] = ["", "Singles", "Doubles", "Triples", "Tetrises"];
+
+impl Stats {
+    // Every press of a gameplay key counts, the repeats of the held keys don't
+    pub fn on_action(&mut self, action: &Action) {
+        if matches!(
+            action,
+            Action::MoveLeft
+                | Action::MoveRight
+                | Action::SoftDrop
+                | Action::HardDrop
+                | Action::RotateCw
+                | Action::RotateCcw
+                | Action::Rotate180
+                | Action::Hold
+        ) {
+            self.keys += 1;
+        }
+    }
+
+    pub fn on_lock(&mut self, lock: &Lock) {
+        self.pieces += 1;
+        self.attack += lock.attack;
+        self.lines += lock.cleared + lock.chain_lines;
+        self.chains += lock.chains;
+        // The 5 lines of a long piece count as a tetris
+        self.clears[lock.cleared.min(self.clears.len() - 1)] += 1;
+        if lock.t_spin {
+            self.t_spins += 1;
+        }
+    }
+
+    pub fn on_finesse_fault(&mut self) {
+        self.finesse_faults += 1;
+    }
+
+    fn per_second(count: usize, frames: u64) -> f64 {
+        if frames == 0 {
+            return 0.0;
+        }
+        count as f64 * FRAMES_PER_SECOND as f64 / frames as f64
+    }
+
+    // Pieces per second
+    pub fn pps(&self, frames: u64) -> f64 {
+        Self::per_second(self.pieces, frames)
+    }
+
+    // Keys per piece
+    pub fn kpp(&self) -> f64 {
+        if self.pieces == 0 {
+            return 0.0;
+        }
+        self.keys as f64 / self.pieces as f64
+    }
+
+    // Attack per minute
+    pub fn apm(&self, frames: u64) -> f64 {
+        Self::per_second(self.attack, frames) * 60.0
+    }
+
+    // Lines per minute
+    pub fn lpm(&self, frames: u64) -> f64 {
+        Self::per_second(self.lines, frames) * 60.0
+    }
+
+    // The lines shown in the side panel
+    pub fn hud(&self, frames: u64) -> Vec<String> {
+        vec![
+            format!("PPS {:>6.2}", self.pps(frames)),
+            format!("KPP {:>6.2}", self.kpp()),
+            format!("APM {:>6.1}", self.apm(frames)),
+            format!("LPM {:>6.1}", self.lpm(frames)),
+            format!("Faults {:>3}", self.finesse_faults),
+        ]
+    }
+
+    // The lines of the game over screen
+    pub fn summary(&self, frames: u64) -> Vec<String> {
+        let mut summary = vec![
+            format!(
+                "PPS: {:.2}  KPP: {:.2}  APM: {:.1}  LPM: {:.1}",
+                self.pps(frames),
+                self.kpp(),
+                self.apm(frames),
+                self.lpm(frames)
+            ),
+            format!(
+                "Pieces: {}  Keys: {}  Finesse faults: {}",
+                self.pieces, self.keys, self.finesse_faults
+            ),
+        ];
+        let clears: Vec<String> = (1..self.clears.len())
+            .map(|lines| format!("{}: {}", CLEAR_NAMES[lines], self.clears[lines]))
+            .collect();
+        // Two lines, so the summary fits the narrow terminals
+        summary.push(clears[..3].join("  "));
+        summary.push(format!(
+            "{}  T-spins: {}  Chains: {}",
+            clears[3], self.t_spins, self.chains
+        ));
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rates_are_measured_in_the_engine_time() {
+        let mut stats = Stats::default();
+        assert_eq!(stats.pps(0), 0.0);
+        assert_eq!(stats.kpp(), 0.0);
+        for action in [Action::MoveLeft, Action::RotateCw, Action::HardDrop] {
+            stats.on_action(&action);
+        }
+        stats.on_action(&Action::Pause);
+        stats.on_lock(&Lock::default());
+        stats.on_action(&Action::HardDrop);
+        stats.on_lock(&Lock {
+            cleared: 4,
+            attack: 4,
+            ..Lock::default()
+        });
+        // 2 pieces in 2 seconds
+        let frames = 2 * FRAMES_PER_SECOND as u64;
+        assert_eq!(stats.pps(frames), 1.0);
+        assert_eq!(stats.kpp(), 2.0);
+        assert_eq!(stats.apm(frames), 120.0);
+        assert_eq!(stats.lpm(frames), 120.0);
+    }
+
+    #[test]
+    fn clears_are_counted_by_kind() {
+        let mut stats = Stats::default();
+        for (cleared, t_spin) in [(1, false), (2, true), (4, false), (4, false), (0, true)] {
+            stats.on_lock(&Lock {
+                cleared,
+                t_spin,
+                ..Lock::default()
+            });
+        }
+        assert_eq!(stats.clears, [1, 1, 1, 0, 2]);
+        assert_eq!(stats.t_spins, 2);
+        let summary = stats.summary(60);
+        assert_eq!(summary[2], "Singles: 1  Doubles: 1  Triples: 0");
+        assert_eq!(summary[3], "Tetrises: 2  T-spins: 2  Chains: 0");
+    }
+
+    #[test]
+    fn chain_lines_count_as_lines() {
+        let mut stats = Stats::default();
+        stats.on_lock(&Lock {
+            cleared: 1,
+            chains: 2,
+            chain_lines: 3,
+            ..Lock::default()
+        });
+        assert_eq!(stats.lines, 4);
+        assert_eq!(stats.clears, [0, 1, 0, 0, 0]);
+        assert_eq!(stats.chains, 2);
+    }
+}
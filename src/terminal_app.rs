@@ -1,79 +1,241 @@
-use crossterm::event::{self, KeyCode};
+use crossterm::{
+    event::{
+        self, DisableFocusChange, EnableFocusChange, KeyboardEnhancementFlags,
+        PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    },
+    execute, terminal,
+};
 use std::{
     io::{self},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
+use crate::config::Config;
+use crate::modes::Mode;
+use crate::netplay::Session;
+use crate::openers::Opener;
+use crate::protocol::Message;
+use crate::puzzles::Puzzle;
 use crate::renderer;
-use crate::tetris_engine::TetrisEngine;
+use crate::screens::{Context, Screen, Transition};
+use crate::spectate::{Publisher, WatchScreen};
+use crate::tetris_engine::Setup;
+
+// The menus are redrawn only on input, so there is no need to wake up often
+const IDLE_POLL: Duration = Duration::from_millis(250);
 
 #[derive(Debug)]
 pub struct App {
     rerender_required: bool,
     exit: bool,
+    // The active screen is the last one
+    screens: Vec<Screen>,
+    context: Context,
     renderer: renderer::Renderer,
+    // Sends the game to the spectators on the other terminals
+    publisher: Option<Publisher>,
 }
 
 impl App {
-    pub fn new() -> Self {
-        return Self {
+    pub fn new(config: Config) -> Self {
+        Self {
             rerender_required: true,
             exit: false,
+            screens: vec![Screen::title()],
+            context: Context {
+                config,
+                key_releases: false,
+                modes: Mode::ALL,
+                practice: Setup::default(),
+                puzzle: Puzzle::default(),
+                opener: Opener::default(),
+            },
             renderer: renderer::Renderer::new(),
-        };
+            publisher: None,
+        }
+    }
+
+    pub fn publish(&mut self, publisher: Publisher) {
+        self.publisher = Some(publisher);
+    }
+
+    // Shows the game of another terminal instead of the title menu
+    pub fn start_watching(&mut self, watch: WatchScreen) {
+        self.screens = vec![Screen::Watch(Box::new(watch))];
+    }
+
+    // Starts the online match right away instead of the title menu.
+    // The terminal has to be set up first, the input handling depends on it.
+    pub fn start_netplay(&mut self, session: Session) {
+        self.screens = vec![Screen::netplay(&self.context, session)];
     }
 
-    fn handle_key<F>(&mut self, f: F) -> io::Result<()>
-    where
-        F: FnOnce(KeyCode) -> (),
-    {
+    // Starts the game from the given board instead of the title menu
+    pub fn start_practice(&mut self, setup: Setup) {
+        self.context.practice = setup;
+        self.screens = vec![Screen::new_game(&self.context, Mode::Practice)];
+    }
+
+    fn handle_event(&mut self) -> io::Result<()> {
         let e = event::read()?;
-        if let event::Event::Key(key) = e {
-            match key.code {
-                KeyCode::Esc => self.exit = true,
-                _ => f(key.code),
+        match e {
+            event::Event::Key(key) => {
+                let transition = match self.screens.last_mut() {
+                    Some(screen) => screen.handle_key(&key, &mut self.context),
+                    None => Transition::Quit,
+                };
+                self.apply(transition);
+                self.rerender_required = true;
+            }
+            event::Event::Resize(cols, rows) => {
+                self.renderer.resize(cols, rows);
+                // The game stays paused after the terminal is enlarged again,
+                // so the player can get ready before resuming it
+                if self
+                    .screens
+                    .last()
+                    .is_some_and(|screen| !screen.fits(&self.renderer))
+                {
+                    self.pause_game();
+                }
+                self.rerender_required = true;
+            }
+            event::Event::FocusLost => {
+                self.pause_game();
+                self.rerender_required = true;
             }
+            _ => {}
         }
         Ok(())
     }
 
+    fn pause_game(&mut self) {
+        if self.screens.last().is_some_and(Screen::is_pausable) {
+            self.apply(Transition::Push(Screen::pause()));
+        }
+    }
+
+    fn apply(&mut self, transition: Transition) {
+        match transition {
+            Transition::Stay => return,
+            Transition::Push(screen) => {
+                // The game is frozen while any other screen is on top of it
+                if let Some(game) = self.screens.last_mut() {
+                    game.pause_game();
+                }
+                self.screens.push(screen);
+            }
+            Transition::Pop => {
+                self.screens.pop();
+            }
+            Transition::Replace(screen) => {
+                self.screens.pop();
+                self.screens.push(screen);
+            }
+            Transition::ToTitle => {
+                self.screens.clear();
+                self.screens.push(Screen::title());
+            }
+            Transition::Restart => {
+                while self
+                    .screens
+                    .last()
+                    .is_some_and(|screen| !screen.is_playing())
+                {
+                    self.screens.pop();
+                }
+                if let Some(game) = self.screens.last_mut() {
+                    game.restart_game();
+                    game.pause_game();
+                }
+            }
+            Transition::Quit => self.exit = true,
+        }
+
+        match self.screens.last_mut() {
+            None => self.exit = true,
+            // Resuming is impossible until the board fits the terminal again
+            Some(game) if game.is_pausable() && !game.fits(&self.renderer) => {
+                self.screens.push(Screen::pause());
+            }
+            Some(screen) => screen.resume_game(&self.context.config),
+        }
+    }
+
     pub fn setup(&mut self) -> io::Result<()> {
         self.renderer.setup()?;
+        // Key releases are only reported by the terminals which support the kitty keyboard protocol.
+        // The input handler guesses them from the OS auto-repeat on the other terminals.
+        // Some terminals don't answer the query at all, which is the same as no support.
+        if terminal::supports_keyboard_enhancement().unwrap_or(false) {
+            execute!(
+                io::stdout(),
+                PushKeyboardEnhancementFlags(
+                    KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES
+                        | KeyboardEnhancementFlags::REPORT_EVENT_TYPES
+                )
+            )?;
+            self.context.key_releases = true;
+        }
+        // The game is paused automatically when the terminal loses focus
+        execute!(io::stdout(), EnableFocusChange)?;
         Ok(())
     }
 
     pub fn teardown(&mut self) -> io::Result<()> {
+        execute!(io::stdout(), DisableFocusChange)?;
+        if self.context.key_releases {
+            execute!(io::stdout(), PopKeyboardEnhancementFlags)?;
+        }
         self.renderer.teardown()?;
         Ok(())
     }
 
-    pub fn run(&mut self, engine: &mut TetrisEngine) -> io::Result<()> {
+    pub fn run(&mut self) -> io::Result<()> {
         while !self.exit {
             // Mainloop:
-            // 1. Handle key events
-            if event::poll(Duration::from_millis(16))? {
-                self.handle_key(|key: KeyCode| match key {
-                    KeyCode::Right => engine.move_current_shape(1, 0),
-                    KeyCode::Left => engine.move_current_shape(-1, 0),
-                    KeyCode::Up => engine.rotate(),
-                    KeyCode::Down => engine.move_current_shape(0, 1),
-                    _ => {}
-                })?;
+            // 1. Handle key events. The game has to wake up at the end of every frame,
+            // the key events are stamped with their frame and applied by the simulation.
+            let timeout = match self.screens.last().and_then(Screen::frame_end) {
+                Some(frame_end) => frame_end.saturating_duration_since(Instant::now()),
+                None => IDLE_POLL,
+            };
+            if event::poll(timeout)? {
+                self.handle_event()?;
             }
 
-            // 2. Perform engine changes
-            engine.update();
+            // 2. Simulate the frames which are over, if the game is the active screen
+            if let Some(game) = self.screens.last_mut() {
+                let next = game.advance(Instant::now());
 
-            // 2.1 Render is required if engine was changed
-            if engine.changed {
-                self.rerender_required = true;
+                // 2.1 Render is required if engine was changed
+                if game.needs_render() {
+                    self.rerender_required = true;
+                    if let Some(publisher) = &mut self.publisher
+                        && let Some(board) = game.snapshot()
+                    {
+                        publisher.publish(board);
+                    }
+                }
+                if let Some(screen) = next {
+                    if let Some(publisher) = &mut self.publisher
+                        && game.snapshot().is_some()
+                    {
+                        publisher.publish(Message::GameOver);
+                    }
+                    self.apply(Transition::Replace(screen));
+                }
+            }
+            if let Some(publisher) = &mut self.publisher {
+                publisher.update();
             }
 
             // 3. Refresh screen if needed
             if self.rerender_required {
-                self.renderer.flush_changes(&(engine.get_state()))?;
+                if let Some(screen) = self.screens.last_mut() {
+                    screen.render(&mut self.renderer, &self.context)?;
+                }
                 self.rerender_required = false;
-                engine.changed = false;
             }
         }
         Ok(())
@@ -1,15 +1,55 @@
-use std::time::{SystemTime, UNIX_EPOCH};
-
-enum Tetromino {
-    T,
-    I,
-    O,
-    L,
-    J,
-    S,
-    Z,
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use std::{collections::VecDeque, sync::Arc};
+
+use crate::pieces::{self, Color, PIECE_SIZE, Piece, PieceSet, Shape, shape_row, shape_size};
+
+const ORIENTATIONS: [Orientation; 4] = [
+    Orientation::N,
+    Orientation::E,
+    Orientation::S,
+    Orientation::W,
+];
+
+// The letter of the standard piece in any of its orientations, e.g. `T`
+pub fn piece_name(shape: Shape) -> Option<char> {
+    let pieces = pieces::standard();
+    pieces.find(shape).map(|i| pieces.pieces[i].name)
+}
+
+// The standard piece with the given letter as it spawns
+pub fn piece_by_name(name: char) -> Option<Shape> {
+    let pieces = pieces::standard();
+    let index = pieces.by_name(name.to_ascii_uppercase())?;
+    Some(pieces.pieces[index].rotations[0])
+}
+
+// The color of the standard piece with the given letter
+pub fn piece_color(name: char) -> Option<Color> {
+    let pieces = pieces::standard();
+    let index = pieces.by_name(name.to_ascii_uppercase())?;
+    Some(pieces.pieces[index].color)
+}
+
+// The standard piece in the orientations N, E, S and W, each one is a clockwise turn
+// of the previous one
+pub fn piece_rotations(piece: Shape) -> Option<[Shape; 4]> {
+    let pieces = pieces::standard();
+    pieces.find(piece).map(|i| pieces.pieces[i].rotations)
+}
+
+// The cells of the piece at the position, as the rows of the standard playfield
+pub fn piece_cells(shape: Shape, position: [u8; 2]) -> [u16; 20] {
+    let mut cells = [0; DEFAULT_HEIGHT];
+    for i in 0..PIECE_SIZE as u8 {
+        let y = (position[1] + i) as usize;
+        if y < DEFAULT_HEIGHT {
+            cells[y] = get_positioned_piece_row(&shape, &i, &position[0], DEFAULT_WIDTH);
+        }
+    }
+    cells
 }
 
+#[derive(Debug, Clone, Copy)]
 enum Orientation {
     N,
     E,
@@ -17,205 +57,801 @@ enum Orientation {
     W,
 }
 
-fn get_tetromino_representation(piece: &Tetromino, orientation: &Orientation) -> u16 {
-    // The `u16` integer encodes a 4x4 Tetris piece (tetromino) using bitwise representation.
-    //
-    // Interpretation of the `u16` layout:
-    //
-    //    |r4| |r3| |r2| |r1|
-    // 0b ----_----_----_----
-    //
-    // Each 4-bit group (`rn`) represents a row of the tetromino, starting from the bottom (`r1`) to the top (`r4`).
-    //
-    // Example: The L-shaped piece in binary representation:
-    // 0b_0000_0000_0010_1110
-    //
-    // This corresponds to the following 4x4 grid:
-    //
-    //  1110   →  ███░
-    //  1000   →  █░░░
-    //  0000   →  ░░░░
-    //  0000   →  ░░░░
-    //
-    // Important Constraints:
-    // - Each shape should be **aligned to the top-left corner** of the 4x4 matrix.
-
-    // The pieces I've already check is labeled `OK`
-    // The rest were produced by ChatGPT
-
-    match (piece, orientation) {
-        // T-Piece
-        (Tetromino::T, Orientation::N) => 0b_0000_0000_1110_0100,
-        (Tetromino::T, Orientation::E) => 0b_0000_1000_1100_1000,
-        (Tetromino::T, Orientation::S) => 0b_0000_0000_0100_1110,
-        (Tetromino::T, Orientation::W) => 0b_0000_0100_1100_0100,
-
-        // I-Piece
-        (Tetromino::I, Orientation::N) => 0b_1000_1000_1000_1000,
-        (Tetromino::I, Orientation::E) => 0b_0000_0000_0000_1111,
-        (Tetromino::I, Orientation::S) => 0b_1000_1000_1000_1000,
-        (Tetromino::I, Orientation::W) => 0b_0000_0000_0000_1111,
-
-        // O-Piece (always the same)
-        (Tetromino::O, _) => 0b_0000_0000_1100_1100,
-
-        // L-Piece
-        (Tetromino::L, Orientation::N) => 0b_0000_0000_1000_1110,
-        (Tetromino::L, Orientation::E) => 0b_0000_1100_1000_1000,
-        (Tetromino::L, Orientation::S) => 0b_0000_0000_1110_0010,
-        (Tetromino::L, Orientation::W) => 0b_0000_0100_0100_1100,
-
-        // J-Piece
-        (Tetromino::J, Orientation::N) => 0b_0000_0111_0001_0000,
-        (Tetromino::J, Orientation::E) => 0b_0000_0011_0010_0010,
-        (Tetromino::J, Orientation::S) => 0b_0000_0100_0111_0000,
-        (Tetromino::J, Orientation::W) => 0b_0000_0010_0010_0110,
-
-        // S-Piece
-        (Tetromino::S, Orientation::N) => 0b_0000_0000_1100_0110,
-        (Tetromino::S, Orientation::E) => 0b_0000_0100_1100_1000,
-        (Tetromino::S, Orientation::S) => 0b_0000_0000_1100_0110,
-        (Tetromino::S, Orientation::W) => 0b_0000_0100_1100_1000,
-
-        // Z-Piece
-        (Tetromino::Z, Orientation::N) => 0b_0000_0000_0110_1100,
-        (Tetromino::Z, Orientation::E) => 0b_0000_1000_1100_0100,
-        (Tetromino::Z, Orientation::S) => 0b_0000_0000_0110_1100,
-        (Tetromino::Z, Orientation::W) => 0b_0000_1000_1100_0100,
+impl Orientation {
+    fn rotated(&self, quarter_turns: u8) -> Orientation {
+        // Clockwise rotation by the given number of 90° turns
+        ORIENTATIONS[(*self as usize + quarter_turns as usize) % 4]
     }
 }
 
-fn get_current_time() -> f64 {
-    match SystemTime::now().duration_since(UNIX_EPOCH) {
-        Ok(n) => return n.as_secs_f64(),
-        Err(_) => panic!("System time error"),
-    }
+fn get_piece_height(piece: &Shape) -> u8 {
+    shape_size(*piece).1 as u8
+}
+
+fn get_positioned_piece_row(piece: &Shape, i: &u8, x: &u8, width: usize) -> u16 {
+    // Extracts the i-th row from a piece and position it into
+    // a `width` bit wide row in accordance with the specified (x, y) position.
+    // The cells which are beyond the right wall are cut off.
+    let piece_row = shape_row(*piece, *i as usize);
+    // Move it to the X=0 coordinate, then to the actual x position
+    (piece_row << width >> (PIECE_SIZE + *x as usize)) as u16
+}
+
+// The columns from the leftmost cell to the rightmost one in any of the rows
+fn get_piece_width(piece: &Shape) -> u8 {
+    shape_size(*piece).0 as u8
+}
+
+// The playfield as the rows of the standard one, if it's the standard size,
+// e.g. for the fumen
+pub fn standard_playfield(playfield: &[u16], width: usize) -> Option<[u16; 20]> {
+    playfield.try_into().ok().filter(|_| width == DEFAULT_WIDTH)
+}
+
+// DTO which is used to transfer the data into the renderer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameState {
+    // The rows from the top, bit `width - 1` is the leftmost column
+    pub playfield: Vec<u16>,
+    pub width: usize,
+    // The colors of the cells, the rows as in the playfield and the columns from the left
+    pub colors: Vec<Vec<Color>>,
+    pub piece_position: [u8; 2],
+    pub active_piece: Shape,
+    pub active_color: Color,
+    pub hold_piece: Option<Shape>,
+    pub hold_color: Color,
+    pub score: usize,
+    pub lines: usize,
+    // The cells drawn as an outline, e.g. the target of the finesse drill,
+    // the rows which are missing have no outline
+    pub overlay: Vec<u16>,
 }
 
-fn get_piece_height(piece: &u16) -> u8 {
-    let mut result: u8 = 0;
-    for i in 0..4 {
-        let piece_row = (piece >> i * 4) & 0xf;
-        if piece_row > 0 {
-            result += 1;
+// The empty standard playfield
+impl Default for GameState {
+    fn default() -> Self {
+        Self {
+            playfield: vec![0; DEFAULT_HEIGHT],
+            width: DEFAULT_WIDTH,
+            colors: vec![vec![Color::default(); DEFAULT_WIDTH]; DEFAULT_HEIGHT],
+            piece_position: [0, 0],
+            active_piece: 0,
+            active_color: Color::default(),
+            hold_piece: None,
+            hold_color: Color::default(),
+            score: 0,
+            lines: 0,
+            overlay: Vec::new(),
         }
     }
-    result
 }
 
-fn get_positioned_piece_row(piece: &u16, i: &u8, x: &u8) -> u16 {
-    // Extracts the i-th row from a piece and position it into
-    // a 10 bit wide row in accordance with the specified (x, y) position.
-
-    // Extracting i-th the row
-    let mut piece_row = (piece >> (i * 4)) & 0xf;
-    // Removing the extra zeroes from the right
-    // let width = get_piece_width(&piece);
-    // piece_row >>= 4 - width;
-    // // Shifting to the leftmost position
-    // piece_row <<= 10 - width;
-    // // Shifting to the action x position
-    // piece_row >>= x;
-    piece_row <<= 6; // Move it to the X=0 coordinate
-    piece_row >>= x;
-    piece_row
+// What happened when a piece was locked, for the statistics
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Lock {
+    pub cleared: usize,
+    pub t_spin: bool,
+    // The garbage generated by the clear, including the part which cancelled the pending one
+    pub attack: usize,
+    // The clears set off by the falling cells with the cascade gravity, and their lines
+    pub chains: usize,
+    pub chain_lines: usize,
+    // The piece as it spawns (`GameState::active_piece`), and its shape and column when locked
+    pub piece: Shape,
+    pub shape: Shape,
+    pub position: [u8; 2],
+    // The orientation of the shape, 0-3 for N, E, S and W
+    pub orientation: u8,
+    // The hold was used before the piece was locked
+    pub held: bool,
 }
 
-fn get_piece_width(piece: &u16) -> u8 {
-    let mut max_width = 0;
-
-    // Iterate through each of the 4 rows
-    for i in 0..4 {
-        let row = (piece >> (i * 4)) & 0b1111; // Extract the 4-bit row
+// The board and the pieces which the game starts with, e.g. loaded from a fumen
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Setup {
+    // The rows at the bottom of the playfield, from the top
+    pub playfield: Vec<u16>,
+    // The colors of the cells, the rows as in the playfield and the columns from the left.
+    // The cells without a color are the garbage.
+    pub colors: Vec<Vec<Color>>,
+    // The first pieces as they spawn, the random ones come after them
+    pub queue: Vec<Shape>,
+    // The piece in the hold at the start
+    pub hold: Option<Shape>,
+}
 
-        let mut leftmost = 4; // Leftmost occupied column (initialize to max)
-        let mut rightmost = 0; // Rightmost occupied column
+// The size of the standard playfield, the engine can be made with any size within the limits
+pub const DEFAULT_WIDTH: usize = 10;
+pub const DEFAULT_HEIGHT: usize = 20;
+pub const MIN_WIDTH: usize = 4;
+pub const MAX_WIDTH: usize = 16;
+pub const MIN_HEIGHT: usize = 4;
+pub const MAX_HEIGHT: usize = 60;
+
+// The engine is advanced by `update` in fixed steps (frames), so the game speed
+// doesn't depend on how often the terminal app manages to call it.
+pub const FRAMES_PER_SECOND: u32 = 60;
+// Frames the piece can stay on the ground before it's locked
+const LOCK_DELAY_FRAMES: u32 = 30;
+
+// Wall kicks: horizontal shifts which are tried in turn when a rotated piece doesn't fit
+pub const KICKS: [isize; 5] = [0, -1, 1, -2, 2];
+
+// The pieces of 5 cells can clear 5 lines at once, it counts as a tetris
+const MAX_CLEAR: usize = 4;
+
+// Points for clearing 0-4 lines at once, multiplied by the level
+const LINE_SCORES: [usize; 5] = [0, 100, 300, 500, 800];
+
+// Garbage lines sent to the opponent for clearing 0-4 lines at once
+const ATTACK_TABLE: [usize; 5] = [0, 0, 1, 2, 4];
+// Garbage lines sent for the T-spin single, double and triple
+const T_SPIN_ATTACK_TABLE: [usize; 4] = [0, 2, 4, 6];
+// The extra lines for the consecutive clears, by the number of the clear in the combo
+const COMBO_TABLE: [usize; 11] = [0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5];
+// The extra line for the tetris or the T-spin clear right after another one (back-to-back)
+const B2B_BONUS: usize = 1;
+const PERFECT_CLEAR_ATTACK: usize = 10;
+
+// How the holes of the inserted garbage rows are placed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GarbageHoles {
+    // All the rows have the hole in the same column
+    Clean,
+    // The hole moves to another column now and then
+    Messy,
+    // Every row has its own hole
+    Random,
+}
 
-        for j in 0..4 {
-            if (row >> j) & 1 == 1 {
-                leftmost = leftmost.min(j);
-                rightmost = rightmost.max(j);
-            }
-        }
+// The chance of the messy garbage hole to move on the next row
+const MESSY_HOLE_CHANCE: f64 = 0.3;
+
+// The number of the placements which can be taken back
+pub const UNDO_LIMIT: usize = 100;
+
+// The goals and the speed of the game, set by the game mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rules {
+    // The game is won when this many lines are cleared
+    pub line_goal: Option<usize>,
+    // The game is won when this many garbage lines are cleared
+    pub garbage_goal: Option<usize>,
+    // The game is over after this many frames of play
+    pub frame_limit: Option<u64>,
+    pub start_level: u32,
+    // The level goes up every time this many lines are cleared,
+    // the level stays the same if it's `None`
+    pub lines_per_level: Option<usize>,
+    pub hold: bool,
+    // The size of the playfield in cells
+    pub width: usize,
+    pub height: usize,
+    // After a clear the groups of the cells next to each other fall on their own,
+    // instead of the rows above moving down, and the lines they fill are cleared too
+    pub cascade: bool,
+    // The placements can be taken back and made again
+    pub undo: bool,
+    // The perfect clear can be looked up during the game
+    pub hint: bool,
+}
 
-        if leftmost < 4 {
-            // If there is at least one occupied tile in this row
-            max_width = max_width.max(rightmost - leftmost + 1);
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            line_goal: None,
+            garbage_goal: None,
+            frame_limit: None,
+            start_level: 1,
+            lines_per_level: Some(10),
+            hold: true,
+            width: DEFAULT_WIDTH,
+            height: DEFAULT_HEIGHT,
+            cascade: false,
+            undo: false,
+            hint: false,
         }
     }
+}
 
-    max_width as u8
+// A locked cell: the color of its piece and the frame it was locked on
+#[derive(Debug, Clone, Copy, Default)]
+struct Cell {
+    color: Color,
+    frame: u64,
 }
 
-// DTO which is used to transfer the data into the renderer.
-pub struct GameState {
-    pub playfield: [u16; 20],
-    pub piece_position: [u8; 2],
-    pub active_piece: u16,
-    pub score: usize,
+// The game at the spawn of a piece, the undo goes back to it
+#[derive(Debug, Clone)]
+struct Snapshot {
+    playfield: Vec<u16>,
+    cells: Vec<Vec<Cell>>,
+    piece_position: [u8; 2],
+    active_piece: usize,
+    held_piece: Option<usize>,
+    queue: VecDeque<usize>,
+    rng: StdRng,
+    garbage_rng: StdRng,
+    game_over: bool,
+    score: usize,
+    lines: usize,
+    level: u32,
+    garbage_rows: usize,
+    garbage_cleared: usize,
+    garbage_hole: Option<u8>,
+    combo: Option<usize>,
+    b2b: bool,
 }
 
+#[derive(Debug, Clone)]
 pub struct TetrisEngine {
-    playfield: [u16; 20],
+    playfield: Vec<u16>,
+    // The locked cells, the columns from the left
+    cells: Vec<Vec<Cell>>,
+    // The pieces the game draws from, the active, held and queued ones are their indices
+    pieces: Arc<PieceSet>,
     piece_position: [u8; 2],
     piece_orientation: Orientation,
-    active_piece: Tetromino,
+    active_piece: usize,
+    held_piece: Option<usize>,
+    // The piece can be held only once until it's locked
+    hold_used: bool,
     pub changed: bool,
-    last_update: f64,
+    // Frames elapsed since the piece moved down by gravity
+    gravity_timer: u32,
+    // Frames elapsed since the piece has landed
+    lock_timer: u32,
+    // The timers are frozen while the game is paused
+    paused: bool,
+    game_over: bool,
+    // The mode has ended the game on its own, e.g. the puzzle is solved
+    stopped: bool,
     score: usize,
+    lines: usize,
+    level: u32,
+    // Frames of play, the pauses aren't counted
+    frames: u64,
+    rules: Rules,
+    // The garbage is always at the bottom of the playfield,
+    // the rows stay garbage until cleared even if the holes are filled
+    garbage_rows: usize,
+    garbage_cleared: usize,
+    // The column of the hole of the last inserted garbage row
+    garbage_hole: Option<u8>,
+    // The number of the consecutive locks which have cleared lines, minus one
+    combo: Option<usize>,
+    // The last clear was a tetris
+    b2b: bool,
+    // The attacks of the opponent, inserted when a piece is locked without clearing lines
+    pending_garbage: VecDeque<usize>,
+    // The attack which isn't taken by the opponent yet, and the total one
+    outgoing_attack: usize,
+    attack_sent: usize,
+    // The pieces are drawn from the seeded generator, so the same seed gives the same pieces
+    rng: StdRng,
+    // The garbage holes have a generator of their own, so the garbage
+    // doesn't change the pieces of the players with the same seed
+    garbage_rng: StdRng,
+    // The last successful move of the piece was a rotation, needed for the T-spins
+    rotated_last: bool,
+    // The locks which aren't taken by the statistics yet
+    locks: Vec<Lock>,
+    // The pieces which come before the random ones
+    queue: VecDeque<usize>,
+    // The game at the spawn of every piece, the last one is of the active piece,
+    // and the ones taken back by the undo, the last one comes first
+    history: VecDeque<Snapshot>,
+    redo: Vec<Snapshot>,
 }
 
 impl TetrisEngine {
     pub fn new() -> Self {
-        return Self {
-            playfield: [0; 20],
-            piece_position: [4, 0], // TODO: The initial position should be different for every tetramino!
+        let pieces = pieces::standard();
+        // The game starts with the L until the next piece is drawn
+        let active_piece = pieces.by_name('L').unwrap_or(0);
+        Self {
+            playfield: vec![0; DEFAULT_HEIGHT],
+            cells: vec![vec![Cell::default(); DEFAULT_WIDTH]; DEFAULT_HEIGHT],
+            pieces,
+            piece_position: [0, 0],
             changed: true,
-            active_piece: Tetromino::L,
+            active_piece,
             piece_orientation: Orientation::N,
-            last_update: get_current_time(),
+            held_piece: None,
+            hold_used: false,
+            gravity_timer: 0,
+            lock_timer: 0,
+            paused: false,
+            game_over: false,
+            stopped: false,
             score: 0,
+            lines: 0,
+            level: 1,
+            frames: 0,
+            rules: Rules::default(),
+            garbage_rows: 0,
+            garbage_cleared: 0,
+            garbage_hole: None,
+            combo: None,
+            b2b: false,
+            pending_garbage: VecDeque::new(),
+            outgoing_attack: 0,
+            attack_sent: 0,
+            rng: StdRng::from_os_rng(),
+            garbage_rng: StdRng::from_os_rng(),
+            rotated_last: false,
+            locks: Vec::new(),
+            queue: VecDeque::new(),
+            history: VecDeque::new(),
+            redo: Vec::new(),
+        }
+    }
+
+    // The size of the playfield is kept within the limits
+    pub fn with_rules(rules: Rules) -> Self {
+        let rules = Rules {
+            width: rules.width.clamp(MIN_WIDTH, MAX_WIDTH),
+            height: rules.height.clamp(MIN_HEIGHT, MAX_HEIGHT),
+            ..rules
         };
+        let mut engine = Self::new();
+        engine.rules = rules;
+        engine.level = rules.start_level;
+        engine.playfield = vec![0; rules.height];
+        engine.cells = vec![vec![Cell::default(); rules.width]; rules.height];
+        engine.piece_position = engine.spawn_position();
+        engine
+    }
+
+    // The game draws the pieces from the set, starting with a new piece
+    pub fn set_pieces(&mut self, pieces: Arc<PieceSet>) {
+        if Arc::ptr_eq(&self.pieces, &pieces) {
+            return;
+        }
+        self.pieces = pieces;
+        self.held_piece = None;
+        self.queue.clear();
+        self.history.clear();
+        self.spawn_next_piece();
+        self.changed = true;
+    }
+
+    pub fn pieces(&self) -> &Arc<PieceSet> {
+        &self.pieces
+    }
+
+    // The same rules and pieces
+    pub fn restart(&mut self) {
+        let pieces = self.pieces.clone();
+        *self = Self::with_rules(self.rules);
+        self.set_pieces(pieces);
+    }
+
+    // Both players of an online match get the same sequence of pieces,
+    // starting with a new piece
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+        self.garbage_rng = StdRng::seed_from_u64(!seed);
+        self.held_piece = None;
+        self.queue.clear();
+        self.history.clear();
+        self.spawn_next_piece();
+        self.changed = true;
+    }
+
+    pub fn lines(&self) -> usize {
+        self.lines
+    }
+
+    pub fn score(&self) -> usize {
+        self.score
+    }
+
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn rules(&self) -> Rules {
+        self.rules
+    }
+
+    pub fn width(&self) -> usize {
+        self.rules.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.rules.height
+    }
+
+    fn full_row(&self) -> u16 {
+        ((1u32 << self.width()) - 1) as u16
+    }
+
+    // The middle of the top row, the 3 cells wide pieces are a cell to the left of the middle
+    fn spawn_position(&self) -> [u8; 2] {
+        [(self.width() / 2 - 1) as u8, 0]
+    }
+
+    pub fn garbage_rows(&self) -> usize {
+        self.garbage_rows
+    }
+
+    pub fn garbage_cleared(&self) -> usize {
+        self.garbage_cleared
+    }
+
+    // Queues the attack of the opponent
+    pub fn receive_garbage(&mut self, lines: usize) {
+        if lines > 0 {
+            self.pending_garbage.push_back(lines);
+            self.changed = true;
+        }
+    }
+
+    pub fn pending_garbage(&self) -> usize {
+        self.pending_garbage.iter().sum()
+    }
+
+    // The garbage to send to the opponent since the last call
+    pub fn take_attack(&mut self) -> usize {
+        std::mem::take(&mut self.outgoing_attack)
+    }
+
+    pub fn attack_sent(&self) -> usize {
+        self.attack_sent
+    }
+
+    // The pieces locked since the last call
+    pub fn take_locks(&mut self) -> Vec<Lock> {
+        std::mem::take(&mut self.locks)
+    }
+
+    // The finish condition of the mode is reached: the line goal, the time limit
+    // or whatever the mode checks itself
+    pub fn is_finished(&self) -> bool {
+        self.stopped
+            || self.rules.line_goal.is_some_and(|goal| self.lines >= goal)
+            || (self.rules.garbage_goal).is_some_and(|goal| self.garbage_cleared >= goal)
+            || self
+                .rules
+                .frame_limit
+                .is_some_and(|limit| self.frames >= limit)
+    }
+
+    // Finishes the game, for the goals which aren't in the rules
+    pub fn stop(&mut self) {
+        self.stopped = true;
+        self.changed = true;
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.changed = true;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
     }
 
     pub fn generate_random_piece(&mut self) {
-        let idx: usize = rand::random_range(0..7);
-        self.active_piece = match idx {
-            0 => Tetromino::I,
-            1 => Tetromino::J,
-            2 => Tetromino::L,
-            3 => Tetromino::O,
-            4 => Tetromino::S,
-            5 => Tetromino::T,
-            6 => Tetromino::Z,
-            _ => panic!("No such type of tetromino found"),
+        self.active_piece = match self.queue.pop_front() {
+            Some(piece) => piece,
+            None => self.random_piece(),
+        }
+    }
+
+    fn random_piece(&mut self) -> usize {
+        self.rng.random_range(0..self.pieces.pieces.len())
+    }
+
+    fn piece(&self) -> &Piece {
+        &self.pieces.pieces[self.active_piece]
+    }
+
+    // The active piece in the orientation
+    fn shape(&self, orientation: Orientation) -> Shape {
+        self.piece().rotations[orientation as usize]
+    }
+
+    fn spawn_next_piece(&mut self) {
+        self.generate_random_piece();
+        self.hold_used = false;
+        self.place_at_spawn();
+        if self.rules.undo {
+            // The new placement replaces the ones taken back
+            self.redo.clear();
+            self.history.push_back(self.snapshot());
+            if self.history.len() > UNDO_LIMIT + 1 {
+                self.history.pop_front();
+            }
+        }
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            playfield: self.playfield.clone(),
+            cells: self.cells.clone(),
+            piece_position: self.piece_position,
+            active_piece: self.active_piece,
+            held_piece: self.held_piece,
+            queue: self.queue.clone(),
+            rng: self.rng.clone(),
+            garbage_rng: self.garbage_rng.clone(),
+            game_over: self.game_over,
+            score: self.score,
+            lines: self.lines,
+            level: self.level,
+            garbage_rows: self.garbage_rows,
+            garbage_cleared: self.garbage_cleared,
+            garbage_hole: self.garbage_hole,
+            combo: self.combo,
+            b2b: self.b2b,
+        }
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.playfield = snapshot.playfield;
+        self.cells = snapshot.cells;
+        self.piece_position = snapshot.piece_position;
+        self.piece_orientation = Orientation::N;
+        self.active_piece = snapshot.active_piece;
+        self.held_piece = snapshot.held_piece;
+        self.hold_used = false;
+        self.queue = snapshot.queue;
+        self.rng = snapshot.rng;
+        self.garbage_rng = snapshot.garbage_rng;
+        self.game_over = snapshot.game_over;
+        self.score = snapshot.score;
+        self.lines = snapshot.lines;
+        self.level = snapshot.level;
+        self.garbage_rows = snapshot.garbage_rows;
+        self.garbage_cleared = snapshot.garbage_cleared;
+        self.garbage_hole = snapshot.garbage_hole;
+        self.combo = snapshot.combo;
+        self.b2b = snapshot.b2b;
+        self.gravity_timer = 0;
+        self.lock_timer = 0;
+        self.rotated_last = false;
+        self.locks.clear();
+        self.changed = true;
+    }
+
+    // Goes back to the spawn of the previous piece. Returns `false` if there is nothing
+    // to take back, or the rules don't allow it.
+    pub fn undo(&mut self) -> bool {
+        if self.history.len() < 2 {
+            return false;
+        }
+        if let Some(current) = self.history.pop_back() {
+            self.redo.push(current);
+        }
+        if let Some(previous) = self.history.back() {
+            self.restore(previous.clone());
+        }
+        true
+    }
+
+    // Places again the piece taken back by the undo, as it was placed
+    pub fn redo(&mut self) -> bool {
+        let Some(next) = self.redo.pop() else {
+            return false;
+        };
+        self.history.push_back(next.clone());
+        self.restore(next);
+        true
+    }
+
+    // The piece is shifted from the spawn position as its set says, within the walls
+    fn place_at_spawn(&mut self) {
+        let piece = self.shape(Orientation::N);
+        let [dx, dy] = self.piece().spawn;
+        let [x, y] = self.spawn_position();
+        let right = self
+            .width()
+            .saturating_sub(get_piece_width(&piece) as usize);
+        let x = (x as isize + dx as isize).clamp(0, right as isize) as u8;
+        self.piece_position = [x, y + dy as u8];
+        self.piece_orientation = Orientation::N;
+        self.gravity_timer = 0;
+        self.lock_timer = 0;
+        self.rotated_last = false;
+        // Top out: there is no room for the new piece
+        let [x, y] = self.piece_position;
+        if !self.piece_fits(&piece, &x, &y) {
+            self.game_over = true;
+        }
+    }
+
+    fn lock_and_spawn(&mut self) {
+        let t_spin = self.is_t_spin();
+        let piece = self.shape(Orientation::N);
+        let shape = self.shape(self.piece_orientation);
+        let position = self.piece_position;
+        self.lock_active_piece();
+        let cleared = self.apply_gravity();
+        self.score += LINE_SCORES[cleared.min(MAX_CLEAR)] * self.level as usize;
+        let (chains, chain_lines) = self.cascade(cleared);
+        if let Some(lines_per_level) = self.rules.lines_per_level {
+            self.level = self.rules.start_level + (self.lines / lines_per_level) as u32;
+        }
+        let attack = self.attack(cleared, t_spin);
+        self.locks.push(Lock {
+            cleared,
+            t_spin,
+            attack,
+            chains,
+            chain_lines,
+            piece,
+            shape,
+            position,
+            orientation: self.piece_orientation as u8,
+            held: self.hold_used,
+        });
+        // There is no next piece after the last line, so it can't top out
+        if !self.is_finished() {
+            self.spawn_next_piece();
+        }
+    }
+
+    // Locks the piece where the other player has locked it, so the opponent's game is rebuilt
+    // from the same seed and the locks. Returns `false` if the piece doesn't come next
+    // or doesn't fit there, the games are out of sync then.
+    pub fn replay_lock(
+        &mut self,
+        piece: Shape,
+        orientation: u8,
+        position: [u8; 2],
+        held: bool,
+        t_spin: bool,
+    ) -> bool {
+        if held {
+            self.hold();
+        }
+        let Some(&orientation) = ORIENTATIONS.get(orientation as usize) else {
+            return false;
+        };
+        let [x, y] = position;
+        if self.shape(Orientation::N) != piece || !self.piece_fits(&self.shape(orientation), &x, &y)
+        {
+            return false;
+        }
+        self.piece_orientation = orientation;
+        self.piece_position = position;
+        // The T-spin is only detected after a rotation
+        self.rotated_last = t_spin;
+        self.lock_and_spawn();
+        self.changed = true;
+        true
+    }
+
+    pub fn is_game_over(&self) -> bool {
+        self.game_over
+    }
+
+    // Replaces the active piece with the one which spawns as `piece`, e.g. to repeat a piece
+    pub fn set_piece(&mut self, piece: Shape) {
+        let Some(index) = self
+            .pieces
+            .pieces
+            .iter()
+            .position(|p| p.rotations[0] == piece)
+        else {
+            return;
+        };
+        self.active_piece = index;
+        self.place_at_spawn();
+        self.changed = true;
+    }
+
+    // Starts the game from the board and the pieces of the setup
+    pub fn load(&mut self, setup: &Setup) {
+        // The rows which don't fit are cut off at the top, the cells at the left
+        let full_row = self.full_row();
+        let rows = setup.playfield.len().min(self.height());
+        let top = self.height() - rows;
+        self.playfield.fill(0);
+        self.reset_cells();
+        for (row, setup_row) in self.playfield[top..]
+            .iter_mut()
+            .zip(&setup.playfield[setup.playfield.len() - rows..])
+        {
+            *row = setup_row & full_row;
+        }
+        // The colors are aligned with the cells, the rows and the columns of the setup
+        // could be more or fewer than the ones of the playfield
+        let width = self.width();
+        let skipped = setup.playfield.len() - rows;
+        for (cells, colors) in self.cells[top..]
+            .iter_mut()
+            .zip(setup.colors.iter().skip(skipped))
+        {
+            for (x, cell) in cells.iter_mut().enumerate() {
+                let column = (colors.len() + x).checked_sub(width);
+                cell.color = column.map_or(Color::default(), |column| colors[column]);
+            }
+        }
+        self.queue = setup
+            .queue
+            .iter()
+            .filter_map(|&piece| self.pieces.find(piece))
+            .collect();
+        self.held_piece = setup.hold.and_then(|piece| self.pieces.find(piece));
+        // The game before the board can't be brought back
+        self.history.clear();
+        self.spawn_next_piece();
+        self.changed = true;
+    }
+
+    // Removes all the locked cells, the garbage too
+    pub fn clear_playfield(&mut self) {
+        self.playfield.fill(0);
+        self.reset_cells();
+        self.garbage_rows = 0;
+        self.changed = true;
+    }
+
+    fn reset_cells(&mut self) {
+        for row in &mut self.cells {
+            row.fill(Cell::default());
+        }
+    }
+
+    pub fn hold(&mut self) {
+        if self.hold_used || !self.rules.hold {
+            return;
+        }
+        match self.held_piece.replace(self.active_piece) {
+            Some(piece) => self.active_piece = piece,
+            None => self.generate_random_piece(),
         }
+        self.place_at_spawn();
+        self.hold_used = true;
+        self.changed = true;
+    }
+
+    pub fn hard_drop(&mut self) {
+        while self.can_move_down() {
+            self.piece_position[1] += 1;
+            self.score += 2;
+            self.rotated_last = false;
+        }
+        self.lock_and_spawn();
+        self.changed = true;
     }
 
     pub fn move_current_shape(&mut self, dx: isize, dy: isize) {
-        if let Some(new_x) = (self.piece_position[0] as isize + dx).try_into().ok() {
-            let piece = get_tetromino_representation(&self.active_piece, &self.piece_orientation);
-            let valid_move = get_piece_width(&piece) + new_x <= 10;
+        if let Ok(new_x) = (self.piece_position[0] as isize + dx).try_into() {
+            let piece = self.shape(self.piece_orientation);
+            let valid_move = (get_piece_width(&piece) + new_x) as usize <= self.width();
             let valid_move =
                 valid_move && !self.overlaps_locked_pieces(&new_x, &self.piece_position[1]);
-            if valid_move {
+            if valid_move && new_x != self.piece_position[0] {
                 self.piece_position[0] = new_x;
+                self.rotated_last = false;
             }
         }
 
-        if let Some(new_y) = (self.piece_position[1] as isize + dy).try_into().ok() {
-            if self.can_move_down() {
-                self.piece_position[1] = new_y;
-            }
+        if let Ok(new_y) = (self.piece_position[1] as isize + dy).try_into()
+            && self.can_move_down()
+        {
+            self.piece_position[1] = new_y;
+            // Soft drop
+            self.score += dy.max(0) as usize;
+            self.rotated_last &= dy == 0;
         }
 
         self.changed = true;
     }
 
     fn can_move_down(&self) -> bool {
-        let piece = get_tetromino_representation(&self.active_piece, &self.piece_orientation);
+        let piece = self.shape(self.piece_orientation);
         let piece_height = get_piece_height(&piece);
-        if (self.piece_position[1] + piece_height) > 19 {
+        if (self.piece_position[1] + piece_height) as usize >= self.height() {
             return false;
         }
         if self.overlaps_locked_pieces(&self.piece_position[0], &(self.piece_position[1] + 1)) {
@@ -225,11 +861,11 @@ impl TetrisEngine {
     }
 
     fn overlaps_locked_pieces(&self, x: &u8, y: &u8) -> bool {
-        let piece = get_tetromino_representation(&self.active_piece, &self.piece_orientation);
-        for i in 0..4 {
-            let piece_row = get_positioned_piece_row(&piece, &i, x);
+        let piece = self.shape(self.piece_orientation);
+        for i in 0..PIECE_SIZE as u8 {
+            let piece_row = get_positioned_piece_row(&piece, &i, x, self.width());
             let target_y = (y + i) as usize;
-            if target_y > 19 {
+            if target_y >= self.height() {
                 break;
             };
             let playfield_row = self.playfield[target_y];
@@ -240,52 +876,240 @@ impl TetrisEngine {
         false
     }
 
+    fn piece_fits(&self, piece: &Shape, x: &u8, y: &u8) -> bool {
+        if (get_piece_width(piece) + x) as usize > self.width()
+            || (get_piece_height(piece) + y) as usize > self.height()
+        {
+            return false;
+        }
+        (0..PIECE_SIZE as u8).all(|i| {
+            let target_y = (y + i) as usize;
+            let row = get_positioned_piece_row(piece, &i, x, self.width());
+            target_y >= self.height() || row & self.playfield[target_y] == 0
+        })
+    }
+
+    // Frames between the moves of the piece down, the guideline speed curve:
+    // (0.8 - (level - 1) * 0.007) ^ (level - 1) seconds per row.
+    // It can't be faster than a row per frame.
+    pub fn gravity_frames(&self) -> u32 {
+        let level = self.level.max(1) as f64 - 1.0;
+        let seconds = (0.8 - level * 0.007).powf(level);
+        ((seconds * FRAMES_PER_SECOND as f64).round() as u32).max(1)
+    }
+
+    // Advances the game by one frame
     pub fn update(&mut self) {
-        // TODO: the idle time actually depends on the speed, but it's not added yet
-        let idle_time = 0.5;
-        let current_time = get_current_time();
-        if current_time < self.last_update + idle_time {
-            // Not enough time elapsed from the previous update
+        if self.paused || self.game_over || self.is_finished() {
+            return;
+        }
+        self.frames += 1;
+        if self.is_finished() {
+            // The time is over
+            self.changed = true;
             return;
         }
-
         if self.can_move_down() {
+            self.lock_timer = 0;
+            self.gravity_timer += 1;
+            if self.gravity_timer < self.gravity_frames() {
+                return;
+            }
+            self.gravity_timer = 0;
             self.piece_position[1] += 1;
+            self.rotated_last = false;
         } else {
-            self.lock_active_piece();
-            self.piece_position = [4, 0];
-            self.generate_random_piece();
+            self.lock_timer += 1;
+            if self.lock_timer < LOCK_DELAY_FRAMES {
+                return;
+            }
+            self.lock_and_spawn();
         }
-
-        self.apply_gravity();
-        self.last_update = current_time;
         self.changed = true;
     }
 
     fn lock_active_piece(&mut self) {
-        let piece = get_tetromino_representation(&self.active_piece, &self.piece_orientation);
-        for i in 0..4 {
-            let piece_row = get_positioned_piece_row(&piece, &i, &self.piece_position[0]);
+        let piece = self.shape(self.piece_orientation);
+        let color = self.piece().color;
+        for i in 0..PIECE_SIZE as u8 {
+            let piece_row =
+                get_positioned_piece_row(&piece, &i, &self.piece_position[0], self.width());
             if piece_row == 0 {
                 continue;
             }
             let target_y = (self.piece_position[1] + i) as usize;
             self.playfield[target_y] |= piece_row;
+            for (x, cell) in self.cells[target_y].iter_mut().enumerate() {
+                if piece_row >> (self.rules.width - 1 - x) & 1 == 1 {
+                    *cell = Cell {
+                        color,
+                        frame: self.frames,
+                    };
+                }
+            }
         }
     }
 
+    #[cfg(test)]
     fn lock_tile(&mut self, x: usize, y: usize) {
-        self.playfield[y] = (1 << (9 - x)) | self.playfield[y];
+        self.playfield[y] |= 1 << (self.width() - 1 - x);
         self.changed = true;
     }
 
     pub fn rotate(&mut self) {
-        match self.piece_orientation {
-            Orientation::N => self.piece_orientation = Orientation::E,
-            Orientation::E => self.piece_orientation = Orientation::S,
-            Orientation::S => self.piece_orientation = Orientation::W,
-            Orientation::W => self.piece_orientation = Orientation::N,
+        self.rotate_by(1);
+    }
+
+    pub fn rotate_ccw(&mut self) {
+        self.rotate_by(3);
+    }
+
+    pub fn rotate_180(&mut self) {
+        self.rotate_by(2);
+    }
+
+    fn rotate_by(&mut self, quarter_turns: u8) {
+        let orientation = self.piece_orientation.rotated(quarter_turns);
+        let piece = self.shape(orientation);
+        for i in 0..self.piece().kicks.len() {
+            let dx = self.piece().kicks[i];
+            let Ok(x) = u8::try_from(self.piece_position[0] as isize + dx) else {
+                continue;
+            };
+            if self.piece_fits(&piece, &x, &self.piece_position[1]) {
+                self.piece_position[0] = x;
+                self.piece_orientation = orientation;
+                self.rotated_last = true;
+                self.changed = true;
+                return;
+            }
+        }
+    }
+
+    // The T piece is rotated into a spot where 3 of the 4 cells diagonal to its center
+    // are filled, the walls and the floor count as filled
+    fn is_t_spin(&self) -> bool {
+        if piece_name(self.shape(Orientation::N)) != Some('T') || !self.rotated_last {
+            return false;
+        }
+        let (dx, dy) = match self.piece_orientation {
+            Orientation::N | Orientation::W => (1, 1),
+            Orientation::E => (0, 1),
+            Orientation::S => (1, 0),
+        };
+        let center_x = self.piece_position[0] as isize + dx;
+        let center_y = self.piece_position[1] as isize + dy;
+        let filled = [(-1, -1), (1, -1), (-1, 1), (1, 1)]
+            .into_iter()
+            .filter(|(cx, cy)| {
+                let (x, y) = (center_x + cx, center_y + cy);
+                let width = self.width() as isize;
+                if !(0..width).contains(&x) || !(0..self.height() as isize).contains(&y) {
+                    // The cells above the playfield are empty
+                    return y >= 0;
+                }
+                self.playfield[y as usize] >> (width - 1 - x) & 1 == 1
+            })
+            .count();
+        filled >= 3
+    }
+
+    // Sends the garbage for the cleared lines, cancelling the pending garbage first.
+    // The pending garbage which is left arrives when no lines are cleared.
+    // Returns the garbage generated by the clear.
+    fn attack(&mut self, cleared: usize, t_spin: bool) -> usize {
+        if cleared == 0 {
+            self.combo = None;
+            while let Some(lines) = self.pending_garbage.pop_front() {
+                // Every attack has its own hole, the rows of the attack share it
+                self.push_garbage(1, GarbageHoles::Random);
+                self.push_garbage(lines - 1, GarbageHoles::Clean);
+            }
+            return 0;
+        }
+
+        let combo = self.combo.map_or(0, |combo| combo + 1);
+        self.combo = Some(combo);
+        let cleared = cleared.min(MAX_CLEAR);
+        let lines = match t_spin {
+            true => T_SPIN_ATTACK_TABLE[cleared.min(T_SPIN_ATTACK_TABLE.len() - 1)],
+            false => ATTACK_TABLE[cleared],
+        };
+        let mut attack = lines + COMBO_TABLE[combo.min(COMBO_TABLE.len() - 1)];
+        // The tetrises and the T-spin clears keep the back-to-back going
+        let difficult = t_spin || cleared == MAX_CLEAR;
+        if difficult && self.b2b {
+            attack += B2B_BONUS;
+        }
+        self.b2b = difficult;
+        if self.playfield.iter().all(|row| *row == 0) {
+            attack += PERFECT_CLEAR_ATTACK;
+        }
+        let generated = attack;
+
+        while attack > 0
+            && let Some(pending) = self.pending_garbage.front_mut()
+        {
+            let cancelled = attack.min(*pending);
+            *pending -= cancelled;
+            attack -= cancelled;
+            if *pending == 0 {
+                self.pending_garbage.pop_front();
+            }
+        }
+        self.outgoing_attack += attack;
+        self.attack_sent += attack;
+        generated
+    }
+
+    // Inserts the garbage rows under the stack, the stack and the active piece are pushed up
+    pub fn insert_garbage(&mut self, count: usize, holes: GarbageHoles) {
+        self.push_garbage(count, holes);
+        let x = self.piece_position[0];
+        while self.overlaps_locked_pieces(&x, &self.piece_position[1]) {
+            match self.piece_position[1].checked_sub(1) {
+                Some(y) => self.piece_position[1] = y,
+                None => {
+                    self.game_over = true;
+                    break;
+                }
+            }
+        }
+    }
+
+    fn push_garbage(&mut self, count: usize, holes: GarbageHoles) {
+        let width = self.width() as u8;
+        for _ in 0..count {
+            let hole = match (self.garbage_hole, holes) {
+                (Some(hole), GarbageHoles::Clean) => hole,
+                (Some(hole), GarbageHoles::Messy)
+                    if !self.garbage_rng.random_bool(MESSY_HOLE_CHANCE) =>
+                {
+                    hole
+                }
+                // The new hole is never in the same column as the previous one
+                (Some(hole), _) => (hole + self.garbage_rng.random_range(1..width)) % width,
+                (None, _) => self.garbage_rng.random_range(0..width),
+            };
+            self.push_garbage_row(hole);
+        }
+    }
+
+    fn push_garbage_row(&mut self, hole: u8) {
+        // Top out: the stack is pushed out of the playfield
+        if self.playfield[0] != 0 {
+            self.game_over = true;
         }
+        let bottom = self.height() - 1;
+        self.playfield.copy_within(1.., 0);
+        self.playfield[bottom] = self.full_row() & !(1 << (self.width() - 1 - hole as usize));
+        self.cells.rotate_left(1);
+        self.cells[bottom].fill(Cell {
+            color: Color::default(),
+            frame: self.frames,
+        });
+        self.garbage_rows = (self.garbage_rows + 1).min(self.height());
+        self.garbage_hole = Some(hole);
         self.changed = true;
     }
 
@@ -294,22 +1118,158 @@ impl TetrisEngine {
             self.playfield[j] = self.playfield[j - 1];
         }
         self.playfield[0] = 0;
+        self.cells[..=i].rotate_right(1);
+        self.cells[0].fill(Cell::default());
+    }
+
+    // Clears the full lines, returns how many of them were cleared.
+    // With the cascade gravity the rows above stay in place until the cells fall.
+    fn apply_gravity(&mut self) -> usize {
+        let mut cleared = 0;
+        for i in 0..self.height() {
+            if self.playfield[i] == self.full_row() {
+                if self.rules.cascade {
+                    self.playfield[i] = 0;
+                    self.cells[i].fill(Cell::default());
+                } else {
+                    self.clear_line(i);
+                }
+                cleared += 1;
+                if i >= self.height() - self.garbage_rows {
+                    self.garbage_rows -= 1;
+                    self.garbage_cleared += 1;
+                }
+            }
+        }
+        self.lines += cleared;
+        cleared
     }
 
-    fn apply_gravity(&mut self) {
-        for i in 0..20 {
-            if self.playfield[i] == 0b1111111111 {
-                self.clear_line(i);
+    // The cells which were locked by the frame, the rows as in the playfield
+    pub fn cells_locked_by(&self, frame: u64) -> Vec<u16> {
+        let width = self.width();
+        self.playfield
+            .iter()
+            .zip(&self.cells)
+            .map(|(&row, cells)| {
+                let locked = (0..width)
+                    .filter(|&x| cells[x].frame <= frame)
+                    .map(|x| 1 << (width - 1 - x))
+                    .sum::<u16>();
+                row & locked
+            })
+            .collect()
+    }
+
+    // The cells split into the groups of the cells next to each other
+    fn cell_groups(&self) -> Vec<Vec<(usize, usize)>> {
+        let (width, height) = (self.width(), self.height());
+        let filled = |x: usize, y: usize| self.playfield[y] >> (width - 1 - x) & 1 == 1;
+        let mut seen = vec![vec![false; width]; height];
+        let mut groups = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                if seen[y][x] || !filled(x, y) {
+                    continue;
+                }
+                seen[y][x] = true;
+                let mut group = vec![(x, y)];
+                let mut next = 0;
+                while let Some(&(x, y)) = group.get(next) {
+                    next += 1;
+                    let neighbours = [
+                        (x.wrapping_sub(1), y),
+                        (x + 1, y),
+                        (x, y.wrapping_sub(1)),
+                        (x, y + 1),
+                    ];
+                    for (x, y) in neighbours {
+                        if x < width && y < height && !seen[y][x] && filled(x, y) {
+                            seen[y][x] = true;
+                            group.push((x, y));
+                        }
+                    }
+                }
+                groups.push(group);
+            }
+        }
+        groups
+    }
+
+    // Every group of cells falls until it lands on the floor or on another group
+    fn settle(&mut self) {
+        let mut groups = self.cell_groups();
+        let mut moved = true;
+        while moved {
+            moved = false;
+            for group in &mut groups {
+                // The group is lifted off the board, so it doesn't land on itself
+                let cells: Vec<Cell> = group.iter().map(|&(x, y)| self.cells[y][x]).collect();
+                for &(x, y) in group.iter() {
+                    self.playfield[y] &= !(1 << (self.width() - 1 - x));
+                    self.cells[y][x] = Cell::default();
+                }
+                let fits = |dy: usize| {
+                    group.iter().all(|&(x, y)| {
+                        y + dy < self.height()
+                            && self.playfield[y + dy] >> (self.width() - 1 - x) & 1 == 0
+                    })
+                };
+                let mut drop = 0;
+                while fits(drop + 1) {
+                    drop += 1;
+                }
+                for ((x, y), cell) in group.iter_mut().zip(cells) {
+                    *y += drop;
+                    self.playfield[*y] |= 1 << (self.width() - 1 - *x);
+                    self.cells[*y][*x] = cell;
+                }
+                moved |= drop > 0;
             }
         }
     }
 
+    // The cells fall after the clear and the lines they fill are cleared in turn,
+    // the n-th clear of the chain scores n + 1 times the points.
+    // Returns the number of the chain clears and their lines.
+    fn cascade(&mut self, cleared: usize) -> (usize, usize) {
+        if !self.rules.cascade || cleared == 0 {
+            return (0, 0);
+        }
+        let (mut chains, mut lines) = (0, 0);
+        loop {
+            self.settle();
+            let cleared = self.apply_gravity();
+            if cleared == 0 {
+                return (chains, lines);
+            }
+            chains += 1;
+            lines += cleared;
+            self.score += LINE_SCORES[cleared.min(MAX_CLEAR)] * self.level as usize * (chains + 1);
+        }
+    }
+
     pub fn get_state(&self) -> GameState {
         GameState {
-            playfield: self.playfield,
+            playfield: self.playfield.clone(),
+            width: self.width(),
             piece_position: self.piece_position,
-            active_piece: get_tetromino_representation(&self.active_piece, &self.piece_orientation),
+            colors: self
+                .cells
+                .iter()
+                .map(|row| row.iter().map(|cell| cell.color).collect())
+                .collect(),
+            active_piece: self.shape(self.piece_orientation),
+            active_color: self.piece().color,
+            hold_piece: self
+                .held_piece
+                .map(|piece| self.pieces.pieces[piece].rotations[0]),
+            hold_color: self
+                .held_piece
+                .map_or(Color::default(), |piece| self.pieces.pieces[piece].color),
             score: self.score,
+            lines: self.lines,
+            overlay: Vec::new(),
         }
     }
 }
@@ -318,11 +1278,25 @@ impl TetrisEngine {
 mod tests {
     use super::*;
 
+    fn tetromino(name: char) -> usize {
+        pieces::standard().by_name(name).unwrap()
+    }
+
+    fn shape(name: char, orientation: Orientation) -> Shape {
+        pieces::standard().pieces[tetromino(name)].rotations[orientation as usize]
+    }
+
+    fn advance(tetris: &mut TetrisEngine, frames: u32) {
+        for _ in 0..frames {
+            tetris.update();
+        }
+    }
+
     #[test]
     fn change_is_true_when_piece_lock_happened() {
         let mut tetris = TetrisEngine::new();
         tetris.lock_tile(0, 0);
-        assert_eq!(tetris.changed, true);
+        assert!(tetris.changed);
     }
 
     #[test]
@@ -330,14 +1304,14 @@ mod tests {
         let mut tetris = TetrisEngine::new();
         tetris.update();
         // The initial update shouldn't change the position of the active piece,
-        // since not enough time elapsed from the 'last_update'
+        // since not enough frames elapsed from the previous move
         assert_eq!(tetris.piece_position[1], 0);
-        // Mock the scenario when 1 second elapsed from the previous step
-        tetris.last_update -= 1.0;
+        // Run the rest of the frames until the gravity step
+        let frames = tetris.gravity_frames();
+        advance(&mut tetris, frames - 1);
         // The update should affect the y position of the piece now!
-        tetris.update();
         assert_eq!(tetris.piece_position[1], 1);
-        assert_eq!(tetris.changed, true);
+        assert!(tetris.changed);
     }
 
     #[test]
@@ -376,8 +1350,7 @@ mod tests {
     fn update_should_lock_the_piece_in_the_bottom() {
         let mut tetris = TetrisEngine::new();
         tetris.piece_position = [7, 18];
-        tetris.last_update -= 1.0;
-        tetris.update();
+        advance(&mut tetris, LOCK_DELAY_FRAMES);
         assert_eq!(tetris.playfield[18], 0b0000000111); // ░░░░░░░███
         assert_eq!(tetris.playfield[19], 0b0000000100); // ░░░░░░░█░░
     }
@@ -397,7 +1370,7 @@ mod tests {
         tetris.lock_tile(9, 18);
         tetris.lock_tile(7, 19);
         tetris.piece_position = [7, 16];
-        assert_eq!(tetris.can_move_down(), false);
+        assert!(!tetris.can_move_down());
     }
     #[test]
     fn can_move_down_tricky_case_1() {
@@ -414,7 +1387,7 @@ mod tests {
         tetris.lock_tile(7, 19);
         tetris.lock_tile(8, 19);
         tetris.piece_position = [7, 16];
-        assert_eq!(tetris.can_move_down(), true);
+        assert!(tetris.can_move_down());
     }
 
     #[test]
@@ -428,21 +1401,21 @@ mod tests {
         let mut tetris = TetrisEngine::new();
         tetris.rotate();
         tetris.piece_position = [0, 17];
-        assert_eq!(tetris.can_move_down(), false);
+        assert!(!tetris.can_move_down());
     }
 
     #[test]
     fn piece_height_is_correctly_calculated() {
-        let north_l = get_tetromino_representation(&Tetromino::L, &Orientation::N);
-        let east_l = get_tetromino_representation(&Tetromino::L, &Orientation::E);
+        let north_l = shape('L', Orientation::N);
+        let east_l = shape('L', Orientation::E);
         assert_eq!(get_piece_height(&north_l), 2);
         assert_eq!(get_piece_height(&east_l), 3);
     }
 
     #[test]
     fn piece_width_is_correctly_calculated() {
-        let north_l = get_tetromino_representation(&Tetromino::L, &Orientation::N);
-        let east_l = get_tetromino_representation(&Tetromino::L, &Orientation::E);
+        let north_l = shape('L', Orientation::N);
+        let east_l = shape('L', Orientation::E);
         assert_eq!(get_piece_width(&north_l), 3);
         assert_eq!(get_piece_width(&east_l), 2);
     }
@@ -453,8 +1426,7 @@ mod tests {
         tetris.piece_position = [7, 0];
         tetris.move_current_shape(1, 0);
         assert_eq!(tetris.piece_position, [7, 0]);
-        tetris.last_update -= 1.0;
-        tetris.update(); // Update shouldn't crash the game
+        advance(&mut tetris, LOCK_DELAY_FRAMES); // Update shouldn't crash the game
     }
 
     #[test]
@@ -467,12 +1439,10 @@ mod tests {
 
     #[test]
     fn aligned_row_with_piece() {
-        let mut tetris = TetrisEngine::new();
-        let piece = &get_tetromino_representation(&Tetromino::L, &Orientation::N);
-        tetris.piece_position[0] = 2;
-        let row = get_positioned_piece_row(&piece, &0, &2);
+        let piece = &shape('L', Orientation::N);
+        let row = get_positioned_piece_row(piece, &0, &2, DEFAULT_WIDTH);
         assert_eq!(row, 0b0011100000);
-        let row = get_positioned_piece_row(&piece, &1, &2);
+        let row = get_positioned_piece_row(piece, &1, &2, DEFAULT_WIDTH);
         assert_eq!(row, 0b0010000000);
     }
 
@@ -492,7 +1462,7 @@ mod tests {
         tetris.piece_position = [3, 17];
         tetris.lock_active_piece();
         tetris.piece_position = [4, 14];
-        assert_eq!(tetris.can_move_down(), true);
+        assert!(tetris.can_move_down());
     }
 
     #[test]
@@ -576,8 +1546,7 @@ mod tests {
         tetris.piece_position = [6, 17];
         tetris.lock_active_piece();
         tetris.piece_position = [8, 17];
-        tetris.last_update -= 1.0;
-        tetris.update();
+        advance(&mut tetris, LOCK_DELAY_FRAMES);
         assert_eq!(tetris.playfield[19], 0b1010101010);
         assert_eq!(tetris.playfield[18], 0b1010101010);
         assert_eq!(tetris.playfield[17], 0b0000000000);
@@ -607,8 +1576,7 @@ mod tests {
             tetris.lock_tile(i, 19);
         }
         tetris.piece_position = [0, 17];
-        tetris.last_update -= 1.0;
-        tetris.update();
+        advance(&mut tetris, LOCK_DELAY_FRAMES);
         assert_eq!(tetris.playfield[18], 0b1110000000);
         assert_eq!(tetris.playfield[19], 0b0011111111)
     }
@@ -616,8 +1584,542 @@ mod tests {
     #[test]
     fn ipiece_is_able_to_appear_on_the_playfield() {
         let mut tetris = TetrisEngine::new();
-        tetris.active_piece = Tetromino::I;
-        tetris.last_update -= 1.0;
+        tetris.active_piece = tetromino('I');
+        advance(&mut tetris, LOCK_DELAY_FRAMES);
+    }
+
+    #[test]
+    fn timers_are_frozen_while_paused() {
+        let mut tetris = TetrisEngine::new();
+        advance(&mut tetris, 10);
+        tetris.set_paused(true);
+        advance(&mut tetris, 100);
+        assert_eq!(tetris.piece_position[1], 0);
+        // The gravity timer continues where it stopped
+        tetris.set_paused(false);
+        let frames = tetris.gravity_frames();
+        advance(&mut tetris, frames - 11);
+        assert_eq!(tetris.piece_position[1], 0);
+        advance(&mut tetris, 1);
+        assert_eq!(tetris.piece_position[1], 1);
+    }
+
+    #[test]
+    fn game_is_over_when_there_is_no_room_for_the_next_piece() {
+        //    0123456789
+        //  0 ░░░░██░░░░ <- Every piece overlaps these tiles at the spawn position
+        let mut tetris = TetrisEngine::new();
+        tetris.lock_tile(4, 0);
+        tetris.lock_tile(5, 0);
+        tetris.piece_position = [0, 17];
+        tetris.hard_drop();
+        assert!(tetris.is_game_over());
+        advance(&mut tetris, LOCK_DELAY_FRAMES); // Nothing happens after the game is over
+        assert_eq!(tetris.piece_position, tetris.spawn_position());
+    }
+
+    #[test]
+    fn j_piece_is_the_mirrored_l_piece() {
+        // Every row is mirrored within the piece width to keep the shape aligned to the left
+        for orientation in [
+            Orientation::N,
+            Orientation::E,
+            Orientation::S,
+            Orientation::W,
+        ] {
+            let l = shape('L', orientation);
+            let j = shape('J', orientation);
+            let width = get_piece_width(&l) as usize;
+            for i in 0..PIECE_SIZE {
+                let mirrored =
+                    shape_row(l, i).reverse_bits() >> (Shape::BITS as usize - PIECE_SIZE);
+                assert_eq!(shape_row(j, i), mirrored << (PIECE_SIZE - width));
+            }
+        }
+    }
+
+    #[test]
+    fn hard_drop_locks_the_piece_at_the_bottom() {
+        let mut tetris = TetrisEngine::new();
+        tetris.piece_position = [0, 0];
+        tetris.hard_drop();
+        assert_eq!(tetris.playfield[18], 0b1110000000); // ███░░░░░░░
+        assert_eq!(tetris.playfield[19], 0b1000000000); // █░░░░░░░░░
+        assert_eq!(tetris.piece_position, tetris.spawn_position());
+    }
+
+    #[test]
+    fn rotation_kicks_the_piece_off_the_wall() {
+        // The vertical I-piece in the rightmost column becomes horizontal,
+        // so it has to be moved 3 cells to the left
+        //    0123456789
+        //  0 ░░░░░░░░░▒       ░░░░░░▒▒▒▒
+        //  1 ░░░░░░░░░▒   →   ░░░░░░░░░░
+        let mut tetris = TetrisEngine::new();
+        tetris.active_piece = tetromino('I');
+        tetris.piece_position = [9, 0];
+        tetris.rotate();
+        assert_eq!(tetris.piece_position, [9, 0]); // The kicks are too short
+        tetris.piece_position = [8, 0];
+        tetris.rotate();
+        assert_eq!(tetris.piece_position, [6, 0]);
+    }
+
+    #[test]
+    fn rotation_is_ignored_if_the_piece_doesnt_fit() {
+        let mut tetris = TetrisEngine::new();
+        tetris.piece_position = [0, 18];
+        tetris.rotate(); // The E-oriented L-shape is 3 cells high
+        assert_eq!(tetris.get_state().active_piece, shape('L', Orientation::N));
+        tetris.rotate_180();
+        assert_eq!(tetris.get_state().active_piece, shape('L', Orientation::S));
+    }
+
+    #[test]
+    fn piece_can_be_held_once_per_lock() {
+        let mut tetris = TetrisEngine::new();
+        tetris.piece_position = [0, 10];
+        tetris.hold();
+        assert_eq!(
+            tetris.get_state().hold_piece,
+            Some(shape('L', Orientation::N))
+        );
+        assert_eq!(tetris.piece_position, tetris.spawn_position());
+        tetris.active_piece = tetromino('O');
+        tetris.hold(); // Ignored, the hold is already used
+        assert_eq!(
+            tetris.get_state().hold_piece,
+            Some(shape('L', Orientation::N))
+        );
+        tetris.hard_drop();
+        tetris.hold();
+        // The L-shape is back in play after the lock
+        assert_eq!(tetris.get_state().active_piece, shape('L', Orientation::N));
+    }
+
+    #[test]
+    fn game_is_finished_when_the_line_goal_is_reached() {
+        // The vertical I-piece clears the last 3 lines of the goal
+        //    0123456789
+        // 16 ░░░░░░░░░▒
+        // 17 █████████▒       ░░░░░░░░░░
+        // 18 █████████▒       ░░░░░░░░░░
+        // 19 █████████▒   →   ░░░░░░░░░█
+        let mut tetris = TetrisEngine::with_rules(Rules {
+            line_goal: Some(3),
+            ..Rules::default()
+        });
+        for y in 17..20 {
+            tetris.playfield[y] = 0b1111111110;
+        }
+        tetris.active_piece = tetromino('I');
+        tetris.piece_position = [9, 0];
+        tetris.hard_drop();
+        assert_eq!(tetris.lines(), 3);
+        assert!(tetris.is_finished());
+        assert!(!tetris.is_game_over());
+        // The game doesn't go on after the finish
+        let state = tetris.get_state();
+        advance(&mut tetris, LOCK_DELAY_FRAMES);
+        assert_eq!(tetris.get_state().playfield, state.playfield);
+    }
+
+    #[test]
+    fn lines_are_scored_by_the_level() {
+        // A tetris at level 3 and the 4 rows of the hard drop
+        let mut tetris = TetrisEngine::with_rules(Rules {
+            start_level: 3,
+            lines_per_level: Some(2),
+            ..Rules::default()
+        });
+        for y in 16..20 {
+            tetris.playfield[y] = 0b1111111110;
+        }
+        tetris.active_piece = tetromino('I');
+        tetris.piece_position = [9, 12];
+        tetris.hard_drop();
+        assert_eq!(tetris.score(), 800 * 3 + 4 * 2);
+        assert_eq!(tetris.level(), 5);
+    }
+
+    #[test]
+    fn gravity_speeds_up_with_the_level() {
+        let mut tetris = TetrisEngine::new();
+        assert_eq!(tetris.gravity_frames(), 60);
+        tetris.level = 5;
+        assert_eq!(tetris.gravity_frames(), 21);
+        tetris.level = 20;
+        assert_eq!(tetris.gravity_frames(), 1);
+    }
+
+    #[test]
+    fn game_is_finished_when_the_time_is_over() {
+        let mut tetris = TetrisEngine::with_rules(Rules {
+            frame_limit: Some(90),
+            ..Rules::default()
+        });
+        tetris.set_paused(true);
+        advance(&mut tetris, 100); // The pause doesn't count
+        assert!(!tetris.is_finished());
+        tetris.set_paused(false);
+        advance(&mut tetris, 89);
+        assert!(!tetris.is_finished());
         tetris.update();
+        assert!(tetris.is_finished());
+    }
+
+    #[test]
+    fn garbage_pushes_the_stack_up() {
+        //    0123456789
+        // 17 ░░░░░░░░░░       ░░░░░░░░░░
+        // 18 ░░░░░░░░░░       ████░░░░░░
+        // 19 ████░░░░░░   →   ██░███████
+        let mut tetris = TetrisEngine::new();
+        tetris.playfield[19] = 0b1111000000;
+        tetris.piece_position = [0, 17];
+        tetris.garbage_hole = Some(2);
+        tetris.insert_garbage(1, GarbageHoles::Clean);
+        assert_eq!(tetris.playfield[18], 0b1111000000);
+        assert_eq!(tetris.playfield[19], 0b1101111111);
+        // The piece is pushed up together with the stack
+        assert_eq!(tetris.piece_position, [0, 16]);
+        assert_eq!(tetris.garbage_rows(), 1);
+    }
+
+    #[test]
+    fn only_garbage_lines_count_for_the_garbage_goal() {
+        // The I-piece clears a garbage line and the line over it
+        let mut tetris = TetrisEngine::with_rules(Rules {
+            garbage_goal: Some(2),
+            ..Rules::default()
+        });
+        tetris.push_garbage_row(9);
+        tetris.push_garbage_row(9);
+        tetris.playfield[17] = 0b1111111110;
+        tetris.active_piece = tetromino('I');
+        tetris.piece_position = [9, 0];
+        tetris.hard_drop();
+        assert_eq!(tetris.lines(), 3);
+        assert_eq!(tetris.garbage_cleared(), 2);
+        assert!(tetris.is_finished());
+    }
+
+    #[test]
+    fn clean_garbage_has_the_hole_in_the_same_column() {
+        let mut tetris = TetrisEngine::new();
+        tetris.insert_garbage(5, GarbageHoles::Clean);
+        assert!(
+            tetris.playfield[15..]
+                .iter()
+                .all(|row| *row == tetris.playfield[19])
+        );
+        assert_eq!(tetris.playfield[19].count_ones(), 9);
+        // The clean garbage is pushed up to the rows 10-14
+        tetris.insert_garbage(5, GarbageHoles::Random);
+        for y in 14..19 {
+            assert_eq!(tetris.playfield[y].count_ones(), 9);
+            assert_ne!(tetris.playfield[y], tetris.playfield[y + 1]);
+        }
+    }
+
+    #[test]
+    fn t_spin_double_is_detected() {
+        //    0123456789
+        // 17 ░░░█░░░░░░       ░░░░░░░░░░
+        // 18 ███▒▒▒████   →   ░░░░░░░░░░
+        // 19 ████▒█████       ░░░█░░░░░░
+        let tspin = |rotated_last| {
+            let mut tetris = TetrisEngine::new();
+            tetris.playfield[17] = 0b0001000000;
+            tetris.playfield[18] = 0b1110001111;
+            tetris.playfield[19] = 0b1111011111;
+            tetris.active_piece = tetromino('T');
+            tetris.piece_orientation = Orientation::S;
+            tetris.piece_position = [3, 18];
+            tetris.rotated_last = rotated_last;
+            tetris.hard_drop();
+            tetris.take_locks()
+        };
+        let lock = Lock {
+            cleared: 2,
+            t_spin: true,
+            attack: T_SPIN_ATTACK_TABLE[2],
+            chains: 0,
+            chain_lines: 0,
+            piece: shape('T', Orientation::N),
+            shape: shape('T', Orientation::S),
+            position: [3, 18],
+            orientation: Orientation::S as u8,
+            held: false,
+        };
+        assert_eq!(tspin(true), vec![lock]);
+        // The piece wasn't rotated into the slot
+        assert_eq!(
+            tspin(false),
+            vec![Lock {
+                t_spin: false,
+                attack: ATTACK_TABLE[2],
+                ..lock
+            }]
+        );
+    }
+
+    #[test]
+    fn t_spin_keeps_the_back_to_back() {
+        // The T-spin double sends 4 lines, the tetris after it
+        // 4 + 1 for the back-to-back and 1 for the combo
+        //    0123456789
+        // 17 ░░░█░░░░░░
+        // 18 ███▒▒▒████
+        // 19 ████▒█████
+        let mut tetris = TetrisEngine::new();
+        tetris.playfield[17] = 0b0001000000;
+        tetris.playfield[18] = 0b1110001111;
+        tetris.playfield[19] = 0b1111011111;
+        tetris.active_piece = tetromino('T');
+        tetris.piece_orientation = Orientation::S;
+        tetris.piece_position = [3, 18];
+        tetris.rotated_last = true;
+        tetris.hard_drop();
+        assert_eq!(tetris.take_attack(), 4);
+
+        for y in 16..20 {
+            tetris.playfield[y] = 0b1111111110;
+        }
+        tetris.playfield[15] = 0b1000000000; // No perfect clear
+        tetris.active_piece = tetromino('I');
+        tetris.piece_position = [9, 0];
+        tetris.hard_drop();
+        assert_eq!(tetris.take_attack(), 6);
+    }
+
+    #[test]
+    fn same_seed_gives_same_pieces() {
+        let pieces = |seed| {
+            let mut tetris = TetrisEngine::new();
+            tetris.set_seed(seed);
+            (0..20)
+                .map(|_| {
+                    tetris.generate_random_piece();
+                    tetris.get_state().active_piece
+                })
+                .collect::<Vec<Shape>>()
+        };
+        assert_eq!(pieces(42), pieces(42));
+        assert_ne!(pieces(42), pieces(43));
+    }
+
+    #[test]
+    fn same_seed_gives_same_garbage() {
+        let garbage = |seed| {
+            let mut tetris = TetrisEngine::new();
+            tetris.set_seed(seed);
+            tetris.insert_garbage(19, GarbageHoles::Random);
+            tetris.playfield.clone()
+        };
+        assert_eq!(garbage(42), garbage(42));
+        assert_ne!(garbage(42), garbage(43));
+    }
+
+    fn sized(width: usize, height: usize) -> TetrisEngine {
+        TetrisEngine::with_rules(Rules {
+            width,
+            height,
+            ..Rules::default()
+        })
+    }
+
+    #[test]
+    fn playfield_size_comes_from_the_rules() {
+        let mut tetris = sized(6, 40);
+        tetris.set_piece(shape('O', Orientation::N));
+        assert_eq!(tetris.piece_position, [2, 0]);
+        (0..10).for_each(|_| tetris.move_current_shape(1, 0));
+        assert_eq!(tetris.piece_position[0], 4);
+        tetris.hard_drop();
+        let state = tetris.get_state();
+        assert_eq!((state.width, state.playfield.len()), (6, 40));
+        assert_eq!(state.playfield[38..], [0b11, 0b11]);
+
+        // The 6 cells make a full line
+        tetris.playfield[39] |= 0b111100;
+        tetris.playfield[38] |= 0b111000;
+        tetris.lock_and_spawn();
+        assert_eq!(tetris.lines(), 1);
+        assert_eq!(tetris.playfield[39], 0b111011);
+    }
+
+    #[test]
+    fn playfield_size_is_kept_within_the_limits() {
+        let tetris = sized(2, 100);
+        assert_eq!((tetris.width(), tetris.height()), (MIN_WIDTH, MAX_HEIGHT));
+        let mut tetris = sized(16, 20);
+        tetris.insert_garbage(1, GarbageHoles::Random);
+        assert_eq!(tetris.playfield[19].count_ones(), 15);
+    }
+
+    #[test]
+    fn undo_brings_back_the_placements() {
+        let mut tetris = TetrisEngine::with_rules(Rules {
+            undo: true,
+            ..Rules::default()
+        });
+        tetris.load(&Setup::default());
+        let start = tetris.get_state();
+        tetris.move_current_shape(-1, 0);
+        tetris.hard_drop();
+        let placed = (tetris.get_state(), tetris.score());
+        tetris.hard_drop();
+        let next = (tetris.get_state(), tetris.score());
+
+        assert!(tetris.undo());
+        assert_eq!((tetris.get_state(), tetris.score()), placed);
+        assert!(tetris.undo());
+        assert!(!tetris.undo());
+        assert_eq!(tetris.get_state(), start);
+        assert_eq!(tetris.score(), 0);
+
+        assert!(tetris.redo());
+        assert_eq!((tetris.get_state(), tetris.score()), placed);
+        // The random pieces are drawn again as they were
+        tetris.hard_drop();
+        assert_eq!((tetris.get_state(), tetris.score()), next);
+        assert!(!tetris.redo());
+
+        // The rules of the competitive modes don't keep the history
+        let mut tetris = TetrisEngine::new();
+        tetris.hard_drop();
+        tetris.hard_drop();
+        assert!(!tetris.undo());
+    }
+
+    #[test]
+    fn attack_cancels_the_pending_garbage() {
+        // Two tetrises in a row: 4 lines, then 4 + 1 for the back-to-back and 1 for the combo
+        let mut tetris = TetrisEngine::new();
+        tetris.receive_garbage(3);
+        tetris.receive_garbage(2);
+        for _ in 0..2 {
+            for y in 16..20 {
+                tetris.playfield[y] = 0b1111111110;
+            }
+            tetris.playfield[15] = 0b1000000000; // No perfect clear
+            tetris.active_piece = tetromino('I');
+            tetris.piece_position = [9, 0];
+            tetris.hard_drop();
+        }
+        // 4 lines cancel the first attack and a part of the second one
+        assert_eq!(tetris.pending_garbage(), 0);
+        assert_eq!(tetris.take_attack(), 6 - 1);
+        assert_eq!(tetris.take_attack(), 0);
+    }
+
+    #[test]
+    fn pending_garbage_arrives_when_no_lines_are_cleared() {
+        let mut tetris = TetrisEngine::new();
+        tetris.receive_garbage(2);
+        tetris.receive_garbage(1);
+        tetris.hard_drop();
+        assert_eq!(tetris.pending_garbage(), 0);
+        assert_eq!(tetris.garbage_rows(), 3);
+        // The piece was locked at the bottom before the garbage came
+        assert_eq!(tetris.playfield[15].count_ones(), 3);
+        // Every attack has a hole of its own
+        assert_eq!(tetris.playfield[17], tetris.playfield[18]);
+        assert_ne!(tetris.playfield[18], tetris.playfield[19]);
+    }
+
+    #[test]
+    fn locked_cells_keep_the_color_of_their_piece() {
+        let mut tetris = TetrisEngine::new();
+        tetris.playfield[19] = 0b1111011111;
+        tetris.piece_position = [4, 0];
+        // The L piece lands with its foot in the gap and clears the bottom line
+        tetris.hard_drop();
+        let colors = &tetris.get_state().colors;
+        assert_eq!(
+            colors[19][3..8],
+            [
+                Color::default(),
+                Color::Orange,
+                Color::Orange,
+                Color::Orange,
+                Color::default()
+            ]
+        );
+        assert!(colors[18].iter().all(|&color| color == Color::default()));
+    }
+
+    #[test]
+    fn pieces_come_from_the_set() {
+        let text = "[I]\ncolor = \"red\"\nn = [\"#####\"]\nspawn = [-1, 1]\n";
+        let mut tetris = TetrisEngine::new();
+        tetris.set_pieces(Arc::new(PieceSet::parse("custom", text).unwrap()));
+        let state = tetris.get_state();
+        assert_eq!(state.active_piece, 0b11111);
+        assert_eq!(state.active_color, Color::Red);
+        assert_eq!(state.piece_position, [3, 1]);
+        tetris.generate_random_piece();
+        assert_eq!(tetris.get_state().active_piece, 0b11111);
+        tetris.hard_drop();
+        assert_eq!(tetris.playfield[19], 0b0001111100);
+        // The shift doesn't push the piece through the wall
+        let text = "[I]\nn = [\"#####\"]\nspawn = [4, 0]\n";
+        tetris.set_pieces(Arc::new(PieceSet::parse("custom", text).unwrap()));
+        assert_eq!(tetris.get_state().piece_position, [5, 0]);
+    }
+
+    #[test]
+    fn five_lines_count_as_a_tetris() {
+        let text = "[I]\nn = [\"#\", \"#\", \"#\", \"#\", \"#\"]\n";
+        let mut tetris = TetrisEngine::new();
+        tetris.set_pieces(Arc::new(PieceSet::parse("custom", text).unwrap()));
+        for y in 15..20 {
+            tetris.playfield[y] = 0b1111111110;
+        }
+        tetris.playfield[14] = 0b1000000000; // No perfect clear
+        tetris.piece_position = [9, 0];
+        tetris.hard_drop();
+        assert_eq!(tetris.lines(), 5);
+        assert_eq!(tetris.take_locks()[0].cleared, 5);
+        assert_eq!(tetris.take_attack(), ATTACK_TABLE[MAX_CLEAR]);
+    }
+
+    #[test]
+    fn cascade_gravity_sets_off_chain_clears() {
+        //    0123456789       0123456789       0123456789
+        // 16 ░░░░░░░░░▒    16 ░░░░░░░░░░    16 ░░░░░░░░░░
+        // 17 █░░░░░░░░▒ →  17 ░░░░░░░░░▒ →  17 ░░░░░░░░░░
+        // 18 ░████████▒    18 ░█████████    18 ░░░░░░░░░▒
+        // 19 █████████▒    19 █████████▒    19 ░░░░░░░░░▒
+        let drop = |cascade| {
+            let mut tetris = TetrisEngine::with_rules(Rules {
+                cascade,
+                ..Rules::default()
+            });
+            tetris.playfield[17] = 0b1000000000;
+            tetris.playfield[18] = 0b0111111110;
+            tetris.playfield[19] = 0b1111111110;
+            tetris.active_piece = tetromino('I');
+            tetris.piece_position = [9, 0];
+            tetris.hard_drop();
+            tetris
+        };
+        let mut tetris = drop(true);
+        assert_eq!(tetris.playfield[16..], [0, 0, 0b1, 0b1]);
+        assert_eq!(tetris.lines(), 2);
+        let lock = tetris.take_locks()[0];
+        assert_eq!((lock.cleared, lock.chains, lock.chain_lines), (1, 1, 1));
+        // The chain clear scores twice as much as the clear of the piece
+        assert_eq!(tetris.score() - drop(false).score(), 2 * LINE_SCORES[1]);
+        assert_eq!(tetris.get_state().colors[19][9], Color::Cyan);
+
+        // The rows above the cleared one just move down
+        let mut tetris = drop(false);
+        assert_eq!(
+            tetris.playfield[17..],
+            [0b0000000001, 0b1000000001, 0b0111111111]
+        );
+        assert_eq!(tetris.take_locks()[0].chains, 0);
     }
 }
@@ -0,0 +1,152 @@
+use crossterm::event::{KeyEvent, KeyEventKind};
+use std::time::Instant;
+
+use crate::config::{Action, Config};
+use crate::game_screen::{GameRequest, GameScreen};
+use crate::modes::{Mode, Ruleset};
+use crate::tetris_engine::{Rules, TetrisEngine};
+
+// The rules of both players: the speed doesn't change, only the attacks matter
+#[derive(Debug)]
+pub struct Versus;
+
+impl Ruleset for Versus {
+    fn rules(&self) -> Rules {
+        Rules {
+            lines_per_level: None,
+            ..Rules::default()
+        }
+    }
+
+    fn hud(&self, engine: &TetrisEngine, _frame: u64) -> Vec<String> {
+        vec![String::from("Sent"), engine.attack_sent().to_string()]
+    }
+
+    // The match is over only when one of the players tops out
+    fn finish(&mut self, engine: &TetrisEngine, _standard: bool) -> (String, Vec<String>) {
+        (
+            String::from("Game over"),
+            vec![format!("Sent: {}", engine.attack_sent())],
+        )
+    }
+}
+
+// Two players on the same keyboard, every player has its own keys and engine.
+// The cleared lines are sent to the opponent as garbage.
+#[derive(Debug)]
+pub struct VersusScreen {
+    pub players: [GameScreen; 2],
+}
+
+impl VersusScreen {
+    pub fn new(config: &Config, key_releases: bool) -> Self {
+        Self {
+            players: [
+                GameScreen::new(config, key_releases, Mode::Versus),
+                GameScreen::new(config, key_releases, Mode::Versus),
+            ],
+        }
+    }
+
+    pub fn frame_end(&self) -> Instant {
+        self.players[0].frame_end().min(self.players[1].frame_end())
+    }
+
+    pub fn pause(&mut self) {
+        self.players.iter_mut().for_each(GameScreen::pause);
+    }
+
+    pub fn resume(&mut self, config: &Config) {
+        self.players
+            .iter_mut()
+            .for_each(|player| player.resume(config));
+    }
+
+    pub fn restart(&mut self) {
+        self.players.iter_mut().for_each(GameScreen::restart);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.players[0].engine.is_paused()
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.players
+            .iter()
+            .any(|player| player.engine.is_game_over())
+    }
+
+    // The index of the player who didn't top out, `None` if both did at the same time
+    pub fn winner(&self) -> Option<usize> {
+        match self
+            .players
+            .each_ref()
+            .map(|player| player.engine.is_game_over())
+        {
+            [false, true] => Some(0),
+            [true, false] => Some(1),
+            _ => None,
+        }
+    }
+
+    pub fn handle_key(&mut self, key: &KeyEvent, config: &Config) -> Option<GameRequest> {
+        // The players don't know whose key is released, it's ignored by the other one
+        if key.kind == KeyEventKind::Release {
+            for (player, keys) in self.players.iter_mut().zip(&config.versus_keys) {
+                player.handle_key(key, keys);
+            }
+            return None;
+        }
+        if key.kind != KeyEventKind::Press {
+            return None;
+        }
+
+        let [first, second] = &config.versus_keys;
+        let (index, action) = match (first.action_for(key), second.action_for(key)) {
+            (Some(action), _) => (0, action),
+            (None, Some(action)) => (1, action),
+            (None, None) => return None,
+        };
+        match action {
+            Action::Restart => {
+                self.restart();
+                None
+            }
+            // The search of one player would hold up the other one
+            Action::Hint => None,
+            _ => self.players[index].handle_key(key, &config.versus_keys[index]),
+        }
+    }
+
+    // Simulates both players and delivers the attacks
+    pub fn advance(&mut self, now: Instant) {
+        for player in self.players.iter_mut() {
+            player.advance(now);
+        }
+        let [first, second] = &mut self.players;
+        let (first, second) = (&mut first.engine, &mut second.engine);
+        let attacks = [first.take_attack(), second.take_attack()];
+        first.receive_garbage(attacks[1]);
+        second.receive_garbage(attacks[0]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::{KeyCode, KeyModifiers};
+
+    #[test]
+    fn keys_are_routed_to_their_player() {
+        let config = Config::default();
+        let mut versus = VersusScreen::new(&config, false);
+        // `w` is the hard drop of the first player, `Up` of the second one
+        let key = KeyEvent::new(KeyCode::Char('w'), KeyModifiers::NONE);
+        assert_eq!(versus.handle_key(&key, &config), None);
+        versus.advance(versus.frame_end());
+        assert!(versus.players[0].engine.score() > 0);
+        assert_eq!(versus.players[1].engine.score(), 0);
+        assert!(!versus.is_over());
+        assert_eq!(versus.winner(), None);
+    }
+}